@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use patch_editor::patch::{Change, Hunk, HunkLineKind, ModificationType, range_begin};
+use change_set::CombinedPatch;
+
+// The patches which introduced the lines, by the line numbers in the current version of the file. The lines not listed come from the base.
+type LineOrigins = BTreeMap<usize, usize>;
+
+#[derive(Default)]
+struct FileState {
+	introduced_by: Option<usize>,
+	removed_by: Option<usize>,
+	line_origins: LineOrigins,
+}
+
+// The patches are indexed the same way as in the series: the newest one goes first
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DependencyGraph {
+	pub dependencies: Vec<BTreeSet<usize>>,
+}
+
+impl DependencyGraph {
+	pub fn new(combined_patches: &[CombinedPatch]) -> DependencyGraph {
+		let mut dependencies = vec![BTreeSet::new(); combined_patches.len()];
+		let mut files: HashMap<String, FileState> = HashMap::new();
+
+		for (index, combined_patch) in combined_patches.iter().enumerate().rev() {
+			let patch_dependencies = &mut dependencies[index];
+			for patch in &combined_patch.patches {
+				match patch.change {
+					Change::Addition { ref new_properties } => {
+						let mut file = files.remove(&new_properties.name).unwrap_or_default();
+						patch_dependencies.extend(file.removed_by);
+
+						file.line_origins = apply_hunks(&LineOrigins::new(), &patch.hunks, index, patch_dependencies);
+						file.introduced_by = Some(index);
+						file.removed_by = None;
+						files.insert(new_properties.name.clone(), file);
+					}
+					Change::Removal { ref old_properties } => {
+						let file = files.entry(old_properties.name.clone()).or_insert_with(FileState::default);
+						patch_dependencies.extend(file.introduced_by);
+
+						// The removal lists all the lines of the file, so they all become the dependencies
+						apply_hunks(&file.line_origins, &patch.hunks, index, patch_dependencies);
+						*file = FileState {
+							removed_by: Some(index),
+							..FileState::default()
+						};
+					}
+					Change::Modification { ref modification_type, ref old_properties, ref new_properties } => {
+						let old_file = files.remove(&old_properties.name).unwrap_or_default();
+						patch_dependencies.extend(old_file.introduced_by);
+
+						let new_file = match *modification_type {
+							ModificationType::Edited | ModificationType::ModeChanged => FileState {
+								line_origins: apply_hunks(&old_file.line_origins, &patch.hunks, index, patch_dependencies),
+								..old_file
+							},
+							ModificationType::Renamed { .. } | ModificationType::Copied { .. } => {
+								if let Some(file) = files.get(&new_properties.name) {
+									patch_dependencies.extend(file.removed_by);
+								}
+
+								// A copy is made from the whole contents of the original file
+								if let ModificationType::Copied { .. } = *modification_type {
+									patch_dependencies.extend(old_file.line_origins.values().cloned());
+								}
+
+								let new_file = FileState {
+									introduced_by: Some(index),
+									removed_by: None,
+									line_origins: apply_hunks(&old_file.line_origins, &patch.hunks, index, patch_dependencies),
+								};
+
+								let renamed = if let ModificationType::Renamed { .. } = *modification_type { true } else { false };
+								files.insert(old_properties.name.clone(), if renamed {
+									FileState {
+										removed_by: Some(index),
+										..FileState::default()
+									}
+								} else {
+									old_file
+								});
+								new_file
+							}
+						};
+						files.insert(new_properties.name.clone(), new_file);
+					}
+				}
+			}
+			patch_dependencies.remove(&index);
+		}
+
+		DependencyGraph {
+			dependencies,
+		}
+	}
+
+	pub fn dependents(&self, index: usize) -> Vec<usize> {
+		(0..self.dependencies.len())
+				.filter(|&other_index| self.dependencies[other_index].contains(&index))
+				.collect()
+	}
+
+	// An order is valid when every patch goes after the ones it depends on, i.e. closer to the end of the series
+	pub fn is_valid_order(&self, order: &[usize]) -> bool {
+		let positions = order.iter()
+				.enumerate()
+				.map(|(position, &index)| (index, position))
+				.collect::<HashMap<_, _>>();
+
+		order.iter().all(|index| {
+			self.dependencies[*index].iter().all(|dependency| match (positions.get(index), positions.get(dependency)) {
+				(Some(position), Some(dependency_position)) => position < dependency_position,
+				_ => false
+			})
+		})
+	}
+
+	// The groups of patches which don't depend on each other, e.g. to send them as separate pull requests
+	pub fn independent_series(&self) -> Vec<Vec<usize>> {
+		let mut series_by_patch: Vec<Option<usize>> = vec![None; self.dependencies.len()];
+		let mut series = Vec::<Vec<usize>>::new();
+
+		for index in 0..self.dependencies.len() {
+			if series_by_patch[index].is_some() { continue; }
+
+			let series_index = series.len();
+			let mut current_series = Vec::new();
+			let mut unvisited = vec![index];
+			series_by_patch[index] = Some(series_index);
+			while let Some(visited_index) = unvisited.pop() {
+				current_series.push(visited_index);
+				for neighbour in self.dependencies[visited_index].iter().cloned().chain(self.dependents(visited_index)) {
+					if series_by_patch[neighbour].is_none() {
+						series_by_patch[neighbour] = Some(series_index);
+						unvisited.push(neighbour);
+					}
+				}
+			}
+
+			current_series.sort_unstable();
+			series.push(current_series);
+		}
+
+		series
+	}
+}
+
+// Records the patches which introduced the context and the removed lines as dependencies, and returns the line origins after the hunks are applied
+fn apply_hunks(line_origins: &LineOrigins, hunks: &[Hunk], patch_index: usize, dependencies: &mut BTreeSet<usize>) -> LineOrigins {
+	let mut new_line_origins = LineOrigins::new();
+	let mut offset = 0isize;
+	let mut remaining_line_origins = line_origins.iter().peekable();
+
+	for hunk in hunks {
+		let begin = range_begin(&hunk.old_file_range);
+		let end = begin + (hunk.old_file_range.end - hunk.old_file_range.start);
+
+		while let Some(&(&line, &origin)) = remaining_line_origins.peek() {
+			if line >= end { break; }
+			if line < begin {
+				new_line_origins.insert((line as isize + offset) as usize, origin);
+			}
+			remaining_line_origins.next();
+		}
+
+		for line in &hunk.lines {
+			let origin = line.old_line_number.and_then(|line_number| line_origins.get(&line_number)).cloned();
+			dependencies.extend(origin);
+
+			match line.kind {
+				HunkLineKind::Context => if let (Some(origin), Some(line_number)) = (origin, line.new_line_number) {
+					new_line_origins.insert(line_number, origin);
+				},
+				HunkLineKind::Added => if let Some(line_number) = line.new_line_number {
+					new_line_origins.insert(line_number, patch_index);
+				},
+				HunkLineKind::Removed => ()
+			}
+		}
+
+		offset += (hunk.new_file_range.end - hunk.new_file_range.start) as isize - (hunk.old_file_range.end - hunk.old_file_range.start) as isize;
+	}
+
+	for (&line, &origin) in remaining_line_origins {
+		new_line_origins.insert((line as isize + offset) as usize, origin);
+	}
+
+	new_line_origins
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use change_set::ChangeSetInfo;
+	use patch_editor::parser::parse_combined_patch;
+
+	const ADD_F: &[u8] = b"diff --git a/f.txt b/f.txt
+new file mode 100644
+index 0000000..e97f8c1
+--- /dev/null
++++ b/f.txt
+@@ -0,0 +1,10 @@
++f 1
++f 2
++f 3
++f 4
++f 5
++f 6
++f 7
++f 8
++f 9
++f 10
+";
+
+	const EDIT_G_2: &[u8] = b"diff --git a/g.txt b/g.txt
+index 3724560..fd218d3 100644
+--- a/g.txt
++++ b/g.txt
+@@ -1,5 +1,5 @@
+ g 1
+-g 2
++g two
+ g 3
+ g 4
+ g 5
+";
+
+	const EDIT_F_2: &[u8] = b"diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,5 +1,5 @@
+ f 1
+-f 2
++f two
+ f 3
+ f 4
+ f 5
+";
+
+	const EDIT_G_9: &[u8] = b"diff --git a/g.txt b/g.txt
+index fd218d3..73df9cd 100644
+--- a/g.txt
++++ b/g.txt
+@@ -6,5 +6,5 @@ g 5
+ g 6
+ g 7
+ g 8
+-g 9
++g nine
+ g 10
+";
+
+	const EDIT_G_3: &[u8] = b"diff --git a/g.txt b/g.txt
+index 73df9cd..fe6f7a6 100644
+--- a/g.txt
++++ b/g.txt
+@@ -1,6 +1,6 @@
+ g 1
+ g two
+-g 3
++g three
+ g 4
+ g 5
+ g 6
+";
+
+	const RENAME_F: &[u8] = b"diff --git a/f.txt b/h.txt
+similarity index 84%
+rename from f.txt
+rename to h.txt
+index de69fc1..687e2b4 100644
+--- a/f.txt
++++ b/h.txt
+@@ -6,5 +6,5 @@ f 5
+ f 6
+ f 7
+ f 8
+-f 9
++f nine
+ f 10
+";
+
+	fn combined_patches(patches_data: &[&[u8]]) -> Vec<CombinedPatch> {
+		patches_data.iter()
+				.map(|data| CombinedPatch {
+					info: ChangeSetInfo::default(),
+					patches: parse_combined_patch(data).unwrap(),
+					series_entry: None,
+					originally_empty: false,
+				})
+				.collect()
+	}
+
+	#[test]
+	fn test_dependencies() {
+		let combined_patches = combined_patches(&[RENAME_F, EDIT_G_3, EDIT_G_9, EDIT_F_2, EDIT_G_2, ADD_F]);
+		let graph = DependencyGraph::new(&combined_patches);
+
+		let expected_dependencies: Vec<BTreeSet<usize>> = vec![
+			vec![5].into_iter().collect(),
+			vec![4].into_iter().collect(),
+			BTreeSet::new(),
+			vec![5].into_iter().collect(),
+			BTreeSet::new(),
+			BTreeSet::new(),
+		];
+		assert_eq!(graph.dependencies, expected_dependencies);
+		assert_eq!(graph.dependents(5), vec![0, 3]);
+		assert_eq!(graph.independent_series(), vec![vec![0, 3, 5], vec![1, 4], vec![2]]);
+
+		assert!(graph.is_valid_order(&[1, 0, 3, 2, 4, 5]));
+		assert!(!graph.is_valid_order(&[0, 1, 5, 3, 2, 4]));
+	}
+}
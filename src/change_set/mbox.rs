@@ -0,0 +1,677 @@
+use std::string::FromUtf8Error;
+use std::io::{Error, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use time::{self, Timespec};
+
+use change_set::{CombinedPatch, ChangeSetInfo, PersonAction};
+use patch_editor::parser::{parse_unified_diff, ParseError};
+use patch_editor::patch::{Change, ModificationType};
+use patch_editor::diffstat::StatOptions;
+
+const PATCH_START: &[u8] = b"diff --git ";
+const DIFFSTAT_SEPARATOR: &[u8] = b"---";
+const SIGNATURE_SEPARATOR: &[u8] = b"-- ";
+const DATE_FORMATS: [&str; 2] = ["%a, %d %b %Y %H:%M:%S %z", "%d %b %Y %H:%M:%S %z"];
+const SIGNATURE: &str = "begitter";
+const MBOX_SEPARATOR_HASH: &str = "0000000000000000000000000000000000000000"; // The patches aren't committed yet
+const MBOX_SEPARATOR_DATE: &str = "Mon Sep 17 00:00:00 2001"; // The fixed date format-patch uses
+const MAX_FILE_NAME_SUBJECT_LENGTH: usize = 52;
+const DIFFSTAT_WIDTH: usize = 72;
+
+#[derive(Fail, Debug)]
+pub enum MailParsingError {
+	#[fail(display = "Message {}: the {} header is absent", _0, _1)]
+	HeaderAbsent(usize, &'static str),
+	#[fail(display = "Message {}: invalid date {:?}", _0, _1)]
+	InvalidDate(usize, String),
+	#[fail(display = "Message {}: unsupported transfer encoding {:?}", _0, _1)]
+	UnsupportedEncoding(usize, String),
+	#[fail(display = "Message {}: error when parsing Unicode input: {}", _0, _1)]
+	EncodingError(usize, FromUtf8Error),
+	#[fail(display = "Message {}: {}", _0, _1)]
+	PatchError(usize, ParseError),
+}
+
+struct Headers {
+	from: Option<String>,
+	date: Option<String>,
+	subject: Option<String>,
+	transfer_encoding: Option<String>,
+}
+
+// Reads an mbox, e.g. the output of "git format-patch --stdout", or a single patch file. The messages go in the order they are in the file.
+pub fn parse_mbox(data: &[u8]) -> Result<Vec<CombinedPatch>, MailParsingError> {
+	parse_mbox_with_strip_level(data, 1)
+}
+
+// The patches of a series may be applied with another strip level than the usual "a/" and "b/" prefixes need
+pub fn parse_mbox_with_strip_level(data: &[u8], strip: usize) -> Result<Vec<CombinedPatch>, MailParsingError> {
+	let mut combined_patches = Vec::new();
+	for (index, message) in split_messages(data).into_iter().enumerate() {
+		if let Some(combined_patch) = parse_message(index + 1, message, strip)? {
+			combined_patches.push(combined_patch);
+		}
+	}
+	Ok(combined_patches)
+}
+
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+	data.split(|byte| *byte == b'\n').collect()
+}
+
+// The same check as "git mailsplit" does, the separator has to end with the time and the year, like "From <hash> Mon Sep 17 00:00:00 2001"
+fn is_separator(line: &[u8]) -> bool {
+	if line.len() < 20 || !line.starts_with(b"From ") { return false; }
+
+	let colon = match line.iter().rposition(|byte| *byte == b':') {
+		Some(position) if position >= 9 && position + 3 < line.len() => position,
+		_ => return false
+	};
+	let is_digit = |position: usize| (line[position] as char).is_ascii_digit();
+	if ![colon - 4, colon - 2, colon - 1, colon + 1, colon + 2].iter().all(|position| is_digit(*position)) { return false; }
+
+	let year = String::from_utf8_lossy(&line[colon + 3..]);
+	year.trim().parse::<u32>().map_or(false, |year| year > 90)
+}
+
+fn split_messages(data: &[u8]) -> Vec<Vec<&[u8]>> {
+	let mut messages = Vec::new();
+	let mut message = Vec::new();
+	for line in split_lines(data) {
+		if is_separator(line) {
+			if !message.is_empty() {
+				messages.push(message);
+			}
+			message = Vec::new();
+		} else {
+			message.push(line);
+		}
+	}
+	if message.iter().any(|line| !line.is_empty()) {
+		messages.push(message);
+	}
+	messages
+}
+
+// Returns nothing for a cover letter, as it has no changes
+fn parse_message(index: usize, lines: Vec<&[u8]>, strip: usize) -> Result<Option<CombinedPatch>, MailParsingError> {
+	let header_end = lines.iter().position(|line| line.is_empty()).unwrap_or(lines.len());
+	let mut headers = parse_headers(index, &lines[..header_end])?;
+
+	let mut body_start = (header_end + 1).min(lines.len());
+	body_start += lines[body_start..].iter().take_while(|line| line.is_empty()).count();
+
+	// The author may be overridden in the body, when the patch is sent by someone else
+	let in_body_header_end = lines[body_start..].iter().position(|line| line.is_empty()).map(|position| body_start + position);
+	if let Some(in_body_header_end) = in_body_header_end {
+		let in_body_lines = &lines[body_start..in_body_header_end];
+		if !in_body_lines.is_empty() && in_body_lines.iter().all(|line| is_in_body_header(line)) {
+			let in_body_headers = parse_headers(index, in_body_lines)?;
+			headers.from = in_body_headers.from.or(headers.from);
+			headers.date = in_body_headers.date.or(headers.date);
+			headers.subject = in_body_headers.subject.or(headers.subject);
+			body_start = in_body_header_end + 1;
+		}
+	}
+
+	if let Some(encoding) = headers.transfer_encoding {
+		let encoding = encoding.to_lowercase();
+		if encoding != "7bit" && encoding != "8bit" && encoding != "binary" {
+			return Err(MailParsingError::UnsupportedEncoding(index, encoding));
+		}
+	}
+
+	let raw_subject = headers.subject.ok_or(MailParsingError::HeaderAbsent(index, "Subject"))?;
+	let subject = strip_subject_prefixes(&raw_subject);
+	let author = headers.from.ok_or(MailParsingError::HeaderAbsent(index, "From"))?;
+	let date = headers.date.ok_or(MailParsingError::HeaderAbsent(index, "Date"))?;
+	let (time, time_zone) = parse_date(&date).ok_or_else(|| MailParsingError::InvalidDate(index, date.clone()))?;
+
+	let body_lines = &lines[body_start..];
+	let patch_start = body_lines.iter().position(|line| line.starts_with(PATCH_START));
+	let message_end = body_lines.iter()
+			.take(patch_start.unwrap_or(body_lines.len()))
+			.position(|line| *line == DIFFSTAT_SEPARATOR)
+			.or(patch_start)
+			.unwrap_or(body_lines.len());
+
+	let patches = match patch_start {
+		Some(patch_start) => {
+			let patch_lines = strip_signature(&body_lines[patch_start..]);
+			let mut patch_data = patch_lines.join(&b"\n"[..]);
+			patch_data.push(b'\n');
+			parse_unified_diff(&patch_data, strip).map_err(|err| MailParsingError::PatchError(index, err))?
+		}
+		None if raw_subject.split(']').next().map_or(false, |prefix| prefix.starts_with('[') && prefix.contains(" 0/")) => return Ok(None),
+		None => Vec::new()
+	};
+
+	let body = body_lines[..message_end].join(&b"\n"[..]);
+	let body = String::from_utf8(body).map_err(|err| MailParsingError::EncodingError(index, err))?;
+	let body = body.trim_right();
+	let message = if body.is_empty() { format!("{}\n", subject) } else { format!("{}\n\n{}\n", subject, body) };
+
+	Ok(Some(CombinedPatch {
+		info: ChangeSetInfo {
+			author_action: PersonAction {
+				name: author,
+				time,
+				time_zone,
+			},
+			committer_action: PersonAction::default(), // The patches are committed later
+			message,
+		},
+		originally_empty: patches.is_empty(),
+		patches,
+		series_entry: None,
+	}))
+}
+
+fn is_in_body_header(line: &[u8]) -> bool {
+	[&b"From: "[..], b"Date: ", b"Subject: "].iter().any(|header| line.starts_with(header))
+}
+
+fn parse_headers(index: usize, lines: &[&[u8]]) -> Result<Headers, MailParsingError> {
+	let mut unfolded_lines: Vec<Vec<u8>> = Vec::new();
+	for line in lines {
+		let line = if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line };
+		match (line.first(), unfolded_lines.last_mut()) {
+			(Some(&b' '), Some(previous_line)) | (Some(&b'\t'), Some(previous_line)) => previous_line.extend_from_slice(line),
+			_ => unfolded_lines.push(line.to_vec())
+		}
+	}
+
+	let mut headers = Headers {
+		from: None,
+		date: None,
+		subject: None,
+		transfer_encoding: None,
+	};
+	for line in unfolded_lines {
+		let line = String::from_utf8(line).map_err(|err| MailParsingError::EncodingError(index, err))?;
+		let separator = match line.find(':') {
+			Some(separator) => separator,
+			None => continue
+		};
+
+		let value = decode_header_value(line[separator + 1..].trim());
+		match line[..separator].to_lowercase().as_str() {
+			"from" => headers.from = Some(unquote_name(&value)),
+			"date" => headers.date = Some(value),
+			"subject" => headers.subject = Some(value),
+			"content-transfer-encoding" => headers.transfer_encoding = Some(value),
+			_ => ()
+		}
+	}
+	Ok(headers)
+}
+
+// The names with special characters are quoted, like "Doe, John" <john@example.com>
+fn unquote_name(value: &str) -> String {
+	if !value.starts_with('"') { return value.into(); }
+
+	let mut name = String::new();
+	let mut chars = value[1..].chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'"' => return name + chars.as_str(),
+			'\\' => name.extend(chars.next()),
+			_ => name.push(ch)
+		}
+	}
+	value.into()
+}
+
+// Decodes the RFC 2047 encoded words, e.g. "=?UTF-8?q?=D0=9E=D0=B4=D0=B8=D0=BD?="
+fn decode_header_value(value: &str) -> String {
+	let mut decoded = String::new();
+	let mut rest = value;
+	let mut after_encoded_word = false;
+	while let Some(start) = rest.find("=?") {
+		let word = rest[start + 2..].splitn(4, '?').collect::<Vec<_>>();
+		let decoded_word = if word.len() == 4 && word[3].starts_with('=') {
+			decode_word(word[1], word[2])
+		} else {
+			None
+		};
+
+		match decoded_word {
+			Some(decoded_word) => {
+				// The whitespace between the encoded words is dropped
+				let gap = &rest[..start];
+				if !(after_encoded_word && gap.trim().is_empty()) {
+					decoded.push_str(gap);
+				}
+				decoded.push_str(&decoded_word);
+
+				let word_length = 2 + word[0].len() + 1 + word[1].len() + 1 + word[2].len() + 2;
+				rest = &rest[start + word_length..];
+				after_encoded_word = true;
+			}
+			None => {
+				decoded.push_str(&rest[..start + 2]);
+				rest = &rest[start + 2..];
+				after_encoded_word = false;
+			}
+		}
+	}
+	decoded.push_str(rest);
+	decoded
+}
+
+fn decode_word(encoding: &str, text: &str) -> Option<String> {
+	let bytes = match encoding {
+		"q" | "Q" => {
+			let mut bytes = Vec::with_capacity(text.len());
+			let mut position = 0;
+			let text = text.as_bytes();
+			while position < text.len() {
+				match text[position] {
+					b'_' => bytes.push(b' '),
+					b'=' => {
+						let hex = text.get(position + 1..position + 3)?;
+						bytes.push(u8::from_str_radix(&String::from_utf8_lossy(hex), 16).ok()?);
+						position += 2;
+					}
+					byte => bytes.push(byte)
+				}
+				position += 1;
+			}
+			bytes
+		}
+		"b" | "B" => decode_base64(text)?,
+		_ => return None
+	};
+	Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+	let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+	let mut buffer = 0u32;
+	let mut bits = 0;
+	for ch in text.bytes().take_while(|ch| *ch != b'=') {
+		let value = match ch {
+			b'A'..=b'Z' => ch - b'A',
+			b'a'..=b'z' => ch - b'a' + 26,
+			b'0'..=b'9' => ch - b'0' + 52,
+			b'+' => 62,
+			b'/' => 63,
+			_ => return None
+		};
+		buffer = buffer << 6 | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((buffer >> bits) as u8);
+			buffer &= (1 << bits) - 1;
+		}
+	}
+	Some(bytes)
+}
+
+// Removes the "[PATCH 1/2]"-like prefixes
+fn strip_subject_prefixes(subject: &str) -> String {
+	let mut subject = subject.trim();
+	while subject.starts_with('[') {
+		match subject.find(']') {
+			Some(end) => subject = subject[end + 1..].trim_left(),
+			None => break
+		}
+	}
+	subject.into()
+}
+
+// The time zone is stored as it's written, e.g. 300 for +0300, same as for the commits
+pub fn parse_date(date: &str) -> Option<(Timespec, i32)> {
+	let mut tm = DATE_FORMATS.iter().filter_map(|format| time::strptime(date, format).ok()).next()?;
+	let offset = tm.tm_utcoff;
+	tm.tm_utcoff = 0;
+	let time = Timespec { sec: tm.to_timespec().sec - offset as i64, nsec: 0 };
+
+	let offset_minutes = offset / 60;
+	Some((time, offset_minutes / 60 * 100 + offset_minutes % 60))
+}
+
+// Format-patch ends the messages with "-- " and the git version
+fn strip_signature<'a, 'b>(lines: &'b [&'a [u8]]) -> &'b [&'a [u8]] {
+	let content_end = lines.iter().rposition(|line| !line.is_empty()).map_or(0, |position| position + 1);
+	if content_end < 2 || lines[content_end - 2] != SIGNATURE_SEPARATOR { return &lines[..content_end]; }
+
+	let version = lines[content_end - 1];
+	if [b' ', b'+', b'-', b'\\', b'@'].iter().any(|prefix| version.starts_with(&[*prefix])) { return &lines[..content_end]; }
+	&lines[..content_end - 2]
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ExportFormat {
+	Mbox, // All the emails in one file
+	PatchDirectory, // One numbered file per email, like "0001-Add-a-file.patch"
+}
+
+// The combined patches go in the order they should be applied
+pub fn export<P: AsRef<Path>>(combined_patches: &[CombinedPatch], format: ExportFormat, path: P) -> Result<Vec<PathBuf>, Error> {
+	match format {
+		ExportFormat::Mbox => {
+			let mut file = File::create(&path)?;
+			write_mbox(combined_patches, &mut file)?;
+			Ok(vec![path.as_ref().to_path_buf()])
+		}
+		ExportFormat::PatchDirectory => {
+			fs::create_dir_all(&path)?;
+			let mut file_paths = Vec::new();
+			for (index, combined_patch) in combined_patches.iter().enumerate() {
+				let file_path = path.as_ref().join(patch_file_name(Some(index + 1), combined_patch));
+				let mut file = File::create(&file_path)?;
+				write_email(combined_patch, index + 1, combined_patches.len(), &mut file)?;
+				file_paths.push(file_path);
+			}
+			Ok(file_paths)
+		}
+	}
+}
+
+pub fn write_mbox<W: Write>(combined_patches: &[CombinedPatch], write: &mut W) -> Result<(), Error> {
+	for (index, combined_patch) in combined_patches.iter().enumerate() {
+		write_email(combined_patch, index + 1, combined_patches.len(), write)?;
+	}
+	Ok(())
+}
+
+pub fn write_email<W: Write>(combined_patch: &CombinedPatch, number: usize, count: usize, write: &mut W) -> Result<(), Error> {
+	let prefix = if count == 1 { String::from("[PATCH]") } else { format!("[PATCH {}/{}]", number, count) };
+
+	write.write_fmt(format_args!("From {} {}\n", MBOX_SEPARATOR_HASH, MBOX_SEPARATOR_DATE))?;
+	write_headers(&combined_patch.info, Some(&prefix), write)?;
+	write.write_all(b"MIME-Version: 1.0\nContent-Type: text/plain; charset=UTF-8\nContent-Transfer-Encoding: 8bit\n\n")?;
+	write_description_and_diff(combined_patch, 1, write)?;
+	write.write_fmt(format_args!("{}\n{}\n\n", str::from_utf8(SIGNATURE_SEPARATOR).unwrap(), SIGNATURE))
+}
+
+// The same as the email, but without the parts specific to mail, can be read back with parse_mbox_with_strip_level
+pub fn write_patch_file<W: Write>(combined_patch: &CombinedPatch, strip: usize, write: &mut W) -> Result<(), Error> {
+	write_headers(&combined_patch.info, None, write)?;
+	write.write_all(b"\n")?;
+	write_description_and_diff(combined_patch, strip, write)
+}
+
+fn write_headers<W: Write>(info: &ChangeSetInfo, subject_prefix: Option<&str>, write: &mut W) -> Result<(), Error> {
+	let (subject, _) = split_message(&info.message);
+	let subject = match subject_prefix {
+		Some(prefix) => format!("{} {}", prefix, encode_header_value(&subject)),
+		None => encode_header_value(&subject)
+	};
+
+	write.write_fmt(format_args!("From: {}\n", encode_address(&info.author_action.name)))?;
+	write.write_fmt(format_args!("Date: {}\n", format_date(info.author_action.time, info.author_action.time_zone)))?;
+	write.write_fmt(format_args!("Subject: {}\n", subject))
+}
+
+fn write_description_and_diff<W: Write>(combined_patch: &CombinedPatch, strip: usize, write: &mut W) -> Result<(), Error> {
+	let (_, body) = split_message(&combined_patch.info.message);
+	if !body.is_empty() {
+		write.write_fmt(format_args!("{}\n", body))?;
+	}
+	write.write_all(b"---\n")?;
+	write_diffstat(combined_patch, write)?;
+	write.write_all(b"\n")?;
+	combined_patch.write_with_prefixes(write, &"a/".repeat(strip), &"b/".repeat(strip))
+}
+
+// The first paragraph is the subject, as with format-patch
+fn split_message(message: &str) -> (String, &str) {
+	let message = message.trim();
+	let (subject, body) = match message.find("\n\n") {
+		Some(position) => (&message[..position], message[position..].trim()),
+		None => (message, "")
+	};
+	(subject.lines().map(str::trim).collect::<Vec<_>>().join(" "), body)
+}
+
+pub fn patch_file_name(number: Option<usize>, combined_patch: &CombinedPatch) -> String {
+	let (subject, _) = split_message(&combined_patch.info.message);
+	let mut name = String::new();
+	for ch in subject.chars() {
+		if ch.is_ascii_alphanumeric() || ch == '.' || ch == '_' {
+			name.push(ch);
+		} else if !name.is_empty() && !name.ends_with('-') {
+			name.push('-');
+		}
+	}
+
+	let mut name = name.trim_right_matches(|ch| ch == '-' || ch == '.').to_owned();
+	name.truncate(MAX_FILE_NAME_SUBJECT_LENGTH);
+	let name = name.trim_right_matches(|ch| ch == '-' || ch == '.');
+	match number {
+		Some(number) => format!("{:04}-{}.patch", number, name),
+		None => format!("{}.patch", name)
+	}
+}
+
+fn encode_header_value(value: &str) -> String {
+	if value.is_ascii() { return value.into(); }
+
+	let mut encoded = String::from("=?UTF-8?q?");
+	for byte in value.bytes() {
+		match byte {
+			b' ' => encoded.push('_'),
+			b'=' | b'?' | b'_' => encoded.push_str(&format!("={:02X}", byte)),
+			byte if byte.is_ascii_graphic() => encoded.push(byte as char),
+			byte => encoded.push_str(&format!("={:02X}", byte))
+		}
+	}
+	encoded + "?="
+}
+
+fn encode_address(address: &str) -> String {
+	let (name, email) = match address.rfind('<') {
+		Some(position) => (address[..position].trim(), &address[position..]),
+		None => return address.into()
+	};
+
+	if !name.is_ascii() {
+		format!("{} {}", encode_header_value(name), email)
+	} else if name.contains(|ch| "()<>[]:;@\\,.\"".contains(ch)) {
+		format!("\"{}\" {}", name.replace('\\', "\\\\").replace('"', "\\\""), email)
+	} else {
+		address.into()
+	}
+}
+
+fn format_date(time: Timespec, time_zone: i32) -> String {
+	let offset_seconds = (time_zone / 100 * 60 + time_zone % 100) * 60;
+	let tm = time::at_utc(Timespec { sec: time.sec + offset_seconds as i64, nsec: 0 });
+	let sign = if time_zone < 0 { '-' } else { '+' };
+	format!("{}, {} {} {}{:04}", tm.strftime("%a").unwrap(), tm.tm_mday, tm.strftime("%b %Y %H:%M:%S").unwrap(), sign, time_zone.abs())
+}
+
+fn write_diffstat<W: Write>(combined_patch: &CombinedPatch, write: &mut W) -> Result<(), Error> {
+	combined_patch.stat().write(&StatOptions { width: DIFFSTAT_WIDTH, ..Default::default() }, write)?;
+
+	for patch in &combined_patch.patches {
+		match patch.change {
+			Change::Addition { ref new_properties } => write.write_fmt(format_args!(" create mode {} {}\n", new_properties.mode, new_properties.name))?,
+			Change::Removal { ref old_properties } => write.write_fmt(format_args!(" delete mode {} {}\n", old_properties.mode, old_properties.name))?,
+			Change::Modification { ref modification_type, ref old_properties, ref new_properties } => {
+				let (action, similarity) = match *modification_type {
+					ModificationType::Renamed { similarity } => ("rename", similarity),
+					ModificationType::Copied { similarity } => ("copy", similarity),
+					_ => ("", None)
+				};
+				if !action.is_empty() {
+					let similarity = similarity.map_or(String::new(), |similarity| format!(" ({}%)", similarity));
+					write.write_fmt(format_args!(" {} {}{}\n", action, patch.stat().display_name(), similarity))?;
+				}
+				if old_properties.mode != new_properties.mode {
+					write.write_fmt(format_args!(" mode change {} => {} {}\n", old_properties.mode, new_properties.mode, new_properties.name))?;
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use tempdir::TempDir;
+
+	const MBOX_DATA: &[u8] = b"From 0e5bd0bf0c5c7e5c4d1d3be1b1e0d8a6f6d1f9aa Mon Sep 17 00:00:00 2001
+From: =?UTF-8?q?=D0=9E=D0=B4=D0=B8=D0=BD=20=D1=87=D1=83=D0=B2=D0=B0=D0=BA?=
+ <someone@example.com>
+Date: Tue, 9 Oct 2018 12:34:56 +0300
+Subject: [PATCH 1/2] Change the second line of the file with a rather long
+ subject
+
+The body of the message.
+
+From a paragraph starting with the word.
+---
+ f.txt | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,3 @@
+ f 1
+-f 2
++f two
+ f 3
+-- 
+2.19.0
+
+From 1f3c7e55e81c8a3e8a5e2bb2a8d0f1c6b1e1d0bb Mon Sep 17 00:00:00 2001
+From: Sender <sender@example.com>
+Date: Wed, 10 Oct 2018 08:00:00 -0130
+Subject: [PATCH 2/2] Remove a line
+
+From: \"Doe, John\" <john@example.com>
+
+---
+ f.txt | 1 -
+ 1 file changed, 1 deletion(-)
+
+diff --git a/f.txt b/f.txt
+index de69fc1..a5b1d0a 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,2 @@
+ f 1
+ f two
+-- 
+-- 
+2.19.0
+
+";
+
+	#[test]
+	fn test_parse_mbox() {
+		let combined_patches = parse_mbox(MBOX_DATA).unwrap();
+		assert_eq!(combined_patches.len(), 2);
+
+		let first_info = &combined_patches[0].info;
+		assert_eq!(first_info.author_action.name, "Один чувак <someone@example.com>");
+		assert_eq!(first_info.author_action.time, Timespec { sec: 1539077696, nsec: 0 });
+		assert_eq!(first_info.author_action.time_zone, 300);
+		assert_eq!(first_info.message, "Change the second line of the file with a rather long subject\n\n\
+			The body of the message.\n\nFrom a paragraph starting with the word.\n");
+		assert_eq!(combined_patches[0].patches.len(), 1);
+		assert_eq!(combined_patches[0].patches[0].hunks[0].lines.len(), 4);
+
+		let second_info = &combined_patches[1].info;
+		assert_eq!(second_info.author_action.name, "Doe, John <john@example.com>");
+		assert_eq!(second_info.author_action.time, Timespec { sec: 1539163800, nsec: 0 });
+		assert_eq!(second_info.author_action.time_zone, -130);
+		assert_eq!(second_info.message, "Remove a line\n");
+
+		// The removed line looks like the signature separator, but it's a part of the hunk
+		let removed_line = &combined_patches[1].patches[0].hunks[0].lines[2];
+		assert_eq!(removed_line.content, b"- \n".to_vec());
+	}
+
+	#[test]
+	fn test_parse_single_patch() {
+		let data = b"From: Someone <someone@example.com>
+Date: 9 Oct 2018 12:34:56 +0000
+Subject: [PATCH] Add a file
+
+diff --git a/g.txt b/g.txt
+new file mode 100644
+index 0000000..fde5c9b
+--- /dev/null
++++ b/g.txt
+@@ -0,0 +1 @@
++g 1
+";
+		let combined_patches = parse_mbox(data).unwrap();
+		assert_eq!(combined_patches.len(), 1);
+		assert_eq!(combined_patches[0].info.message, "Add a file\n");
+		assert_eq!(combined_patches[0].info.author_action.time, Timespec { sec: 1539088496, nsec: 0 });
+	}
+
+	#[test]
+	fn test_write_email() {
+		let mut combined_patch = parse_mbox(MBOX_DATA).unwrap().remove(0);
+		combined_patch.info.author_action.name = "Doe, John <john@example.com>".into();
+
+		let mut buf = Vec::new();
+		write_email(&combined_patch, 1, 1, &mut buf).unwrap();
+		assert_eq!(String::from_utf8(buf).unwrap(), "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: \"Doe, John\" <john@example.com>
+Date: Tue, 9 Oct 2018 12:34:56 +0300
+Subject: [PATCH] Change the second line of the file with a rather long subject
+MIME-Version: 1.0
+Content-Type: text/plain; charset=UTF-8
+Content-Transfer-Encoding: 8bit
+
+The body of the message.
+
+From a paragraph starting with the word.
+---
+ f.txt | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,3 @@
+ f 1
+-f 2
++f two
+ f 3
+-- 
+begitter
+
+");
+	}
+
+	#[test]
+	fn test_export_round_trip() {
+		let combined_patches = parse_mbox(MBOX_DATA).unwrap();
+		let temp_dir = TempDir::new("begitter").unwrap();
+
+		let mbox_path = temp_dir.path().join("series.mbox");
+		export(&combined_patches, ExportFormat::Mbox, &mbox_path).unwrap();
+		let file_paths = export(&combined_patches, ExportFormat::PatchDirectory, temp_dir.path().join("patches")).unwrap();
+		let file_names = file_paths.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>();
+		assert_eq!(file_names, vec!["0001-Change-the-second-line-of-the-file-with-a-rather-lon.patch", "0002-Remove-a-line.patch"]);
+
+		let mut exported_data = vec![fs::read(&mbox_path).unwrap()];
+		exported_data.extend(file_paths.iter().map(|path| fs::read(path).unwrap()));
+		let from_directory = exported_data[1..].concat();
+		for data in &[&exported_data[0], &from_directory] {
+			let exported_patches = parse_mbox(data).unwrap();
+			assert_eq!(exported_patches.len(), combined_patches.len());
+			for (exported_patch, combined_patch) in exported_patches.iter().zip(combined_patches.iter()) {
+				assert_eq!(exported_patch.info.author_action, combined_patch.info.author_action);
+				assert_eq!(exported_patch.info.message, combined_patch.info.message);
+				assert_eq!(exported_patch.patches, combined_patch.patches);
+			}
+		}
+	}
+}
@@ -1,8 +1,10 @@
 mod parser;
+pub mod dependencies;
+pub mod mbox;
+pub mod quilt;
+pub mod split;
 
 use std::io::{Error, Write};
-use std::collections::{HashSet, HashMap};
-use std::borrow::Borrow;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt;
@@ -11,175 +13,223 @@ use time::{self, Timespec};
 use failure;
 use nom::ErrorKind;
 
-use patch_editor::patch::{Patch, Change, ModificationType, OverlappingHunkError};
+use change_set::quilt::SeriesEntry;
+use patch_editor::patch_id::{patch_id, PatchId};
+use patch_editor::diffstat::DiffStat;
+use patch_editor::patch::{Patch, Change, ModificationType, CompositionError, InversionError, CommutationError};
 
-struct PatchClassification<T> {
-	file_addition: HashMap<String, T>,
-	file_removal_only: HashMap<String, T>,
-	modification: HashMap<String, T>,
-}
-
-impl<T: Borrow<Patch>> PatchClassification<T> {
-	fn classify(patch_iter: impl Iterator<Item=T>) -> PatchClassification<T> {
-		let mut classification = PatchClassification {
-			file_addition: HashMap::new(),
-			file_removal_only: HashMap::new(),
-			modification: HashMap::new(),
-		};
-
-		for patch in patch_iter {
-			let (name, hash_map) = {
-				if let Some(name) = get_added_file_name(patch.borrow()) {
-					(name.clone(), &mut classification.file_addition)
-				} else {
-					if let Some(name) = get_removed_file_name(patch.borrow()) {
-						(name.clone(), &mut classification.file_removal_only)
-					} else {
-						(get_modification_file_name(patch.borrow()).unwrap().clone(), &mut classification.modification)
-					}
-				}
-			};
-			hash_map.insert(name.clone(), patch);
-		}
-
-		classification
+// After a removal or a rename, the file is absent
+fn frees_file(patch: &Patch, name: &str) -> bool {
+	match patch.change {
+		Change::Removal { ref old_properties } => old_properties.name == name,
+		Change::Modification { modification_type: ModificationType::Renamed { .. }, ref old_properties, .. } => old_properties.name == name,
+		_ => false
 	}
 }
 
-fn classification_into_original_patch(classification: PatchClassification<Patch>, original_patch_info: Option<ChangeSetInfo>) -> CombinedPatch {
-	let patches = vec![classification.file_addition, classification.file_removal_only, classification.modification]
-			.into_iter()
-			.flat_map(|map| map.into_iter().map(|(_, patch)| patch).collect::<Vec<Patch>>())
-			.collect();
+// All the next patches change the files as they are after the patches, same as the patches of a diff do
+fn squash_patches(patches: &[Patch], next_patches: &[Patch]) -> Result<Vec<Patch>, AbsorbtionErrorVariant> {
+	let mut replacements = vec![None; patches.len()]; // Some(None) if the patch is cancelled out
+	let mut added_patches = Vec::new();
+
+	for next_patch in next_patches {
+		if let Some(name) = next_patch.new_file_name() {
+			let produced = patches.iter().any(|patch| patch.new_file_name() == Some(name));
+			let freed = next_patches.iter().any(|other_patch| frees_file(other_patch, name));
+			if next_patch.old_file_name() != Some(name) && produced && !freed {
+				return Err(AbsorbtionErrorVariant::ConflictingAdditions);
+			}
+		}
 
-	let info = if let Some(info) = original_patch_info {
-		info
-	} else {
-		ChangeSetInfo::default()
-	};
+		let (position, squashed_patch) = match next_patch.old_file_name() {
+			Some(name) => {
+				let position = match patches.iter().position(|patch| patch.new_file_name() == Some(name)) {
+					Some(position) => position,
+					None if patches.iter().any(|patch| frees_file(patch, name)) => return Err(AbsorbtionErrorVariant::ConflictingRemovals),
+					None => {
+						added_patches.push(next_patch.clone());
+						continue;
+					}
+				};
 
-	CombinedPatch {
-		info,
-		patches,
-	}
-}
+				let patch = &patches[position];
+				if next_patch.is_copy() { // The copied file stays, but the copy now starts from the original file
+					added_patches.push(patch.compose(next_patch).map_err(AbsorbtionErrorVariant::HunkError)?);
+					continue;
+				}
+				match (&patch.change, &next_patch.change) {
+					(&Change::Addition { .. }, &Change::Removal { .. }) => (position, None),
+					(&Change::Modification { .. }, &Change::Removal { .. }) if patch.is_copy() => (position, None),
+					_ => (position, Some(patch.compose(next_patch).map_err(AbsorbtionErrorVariant::HunkError)?))
+				}
+			}
+			None => { // An addition becomes a modification if the file was removed before
+				let name = next_patch.new_file_name().unwrap();
+				let position = match patches.iter().position(|patch| match patch.change {
+					Change::Removal { ref old_properties } => old_properties.name == *name,
+					_ => false
+				}) {
+					Some(position) => position,
+					None => {
+						added_patches.push(next_patch.clone());
+						continue;
+					}
+				};
+				(position, Some(patches[position].compose(next_patch).map_err(AbsorbtionErrorVariant::HunkError)?))
+			}
+		};
 
-fn get_added_file_name(patch: &Patch) -> Option<&String> {
-	match patch.change {
-		Change::Addition { ref new_properties } => Some(&new_properties.name),
-		Change::Modification { ref modification_type, ref new_properties, .. } => match modification_type {
-			ModificationType::Edited | ModificationType::ModeChanged => None,
-			_ => Some(&new_properties.name)
+		if replacements[position].is_some() {
+			return Err(AbsorbtionErrorVariant::HunkError(CompositionError::UnsupportedChanges)); // Two patches change the same file
 		}
-		Change::Removal { .. } => None
+		replacements[position] = Some(squashed_patch);
 	}
-}
 
-fn get_removed_file_name(patch: &Patch) -> Option<&String> {
-	match patch.change {
-		Change::Addition { .. } => None,
-		Change::Modification { ref modification_type, ref old_properties, .. } => match modification_type {
-			ModificationType::Renamed { .. } => Some(&old_properties.name),
-			_ => None
-		}
-		Change::Removal { ref old_properties } => Some(&old_properties.name),
-	}
+	Ok(patches.iter()
+			.zip(replacements)
+			.filter_map(|(patch, replacement)| match replacement {
+				Some(squashed_patch) => squashed_patch.filter(|patch| !patch.is_empty()),
+				None => Some(patch.clone())
+			})
+			.chain(added_patches.into_iter().filter(|patch| !patch.is_empty()))
+			.collect())
 }
 
-fn get_modification_file_name(patch: &Patch) -> Option<&String> {
-	if let Change::Modification { ref modification_type, ref new_properties, .. } = patch.change {
-		match modification_type {
-			ModificationType::Edited | ModificationType::ModeChanged { .. } => return Some(&new_properties.name),
-			_ => ()
-		}
+fn get_file_names(patch: &Patch) -> Vec<&String> {
+	match patch.change {
+		Change::Addition { ref new_properties } => vec![&new_properties.name],
+		Change::Removal { ref old_properties } => vec![&old_properties.name],
+		Change::Modification { ref old_properties, ref new_properties, .. } => vec![&old_properties.name, &new_properties.name]
 	}
-
-	None
 }
 
 #[derive(Debug, Clone)]
 pub struct CombinedPatch {
 	pub info: ChangeSetInfo,
 	pub patches: Vec<Patch>,
+	pub series_entry: Option<SeriesEntry>, // Only for the patches read from a series
+	pub originally_empty: bool, // E.g. an empty commit, kept when applied unlike the patches the edits leave empty
 }
 
 impl CombinedPatch {
 	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		self.write_with_prefixes(write, "a/", "b/")
+	}
+
+	pub fn write_with_prefixes<W: Write>(&self, write: &mut W, old_prefix: &str, new_prefix: &str) -> Result<(), Error> {
 		self.patches
 				.iter()
-				.map(|patch| patch.write(write))
+				.map(|patch| patch.write_with_prefixes(write, old_prefix, new_prefix))
 				.collect()
 	}
 
-	pub fn absorb(&mut self, CombinedPatch { info, patches }: CombinedPatch) -> Result<(), AbsorbtionError> {
-		self.absorb_patches(Some(info), patches.into_iter())
+	pub fn patch_id(&self) -> Option<PatchId> {
+		let mut buf = Vec::new();
+		self.write(&mut buf).expect("Writing to memory can't fail");
+		patch_id(&buf)
 	}
 
-	pub fn move_patches_to(&mut self, patch_positions: &[usize], combined_patch: &mut CombinedPatch) -> Result<(), AbsorbtionError> {
-		let mut transferred_patches = Vec::new();
-		for position in patch_positions.iter().rev() {
-			transferred_patches.push(combined_patch.patches.remove(*position));
-		}
+	pub fn stat(&self) -> DiffStat {
+		DiffStat { files: self.patches.iter().map(|patch| patch.stat()).collect() }
+	}
 
-		let result = self.absorb_patches(None, transferred_patches.into_iter());
-		match result {
-			Ok(_) => result,
-			Err(err) => {
-				let unprocessed_patches = err.combined_patch.unwrap().patches;
-				combined_patch.patches.extend(unprocessed_patches.into_iter()); // If it becomes inconvenient, we could even restore the original order
-
-				Err(AbsorbtionError {
-					combined_patch: None,
-					variant: err.variant,
-				})
-			}
-		}
+	// The file patches left without hunks, e.g. after moving the hunks out
+	pub fn prune_empty_patches(&mut self) {
+		self.patches.retain(|patch| !patch.is_empty());
 	}
 
-	fn absorb_patches(&mut self, original_patch_info: Option<ChangeSetInfo>, patches: impl Iterator<Item=Patch>) -> Result<(), AbsorbtionError> {
-		let (other_file_addition, other_file_removal_only, other_modification) = {
-			let mut classification = PatchClassification::classify(self.patches.iter_mut());
-			let mut other_classification = PatchClassification::classify(patches);
+	// The patch that changes the file with this name, unless it only copies it
+	pub fn find_patch_reading(&self, name: &str) -> Option<usize> {
+		self.patches.iter().position(|patch| patch.old_file_name().map_or(false, |old_name| old_name == name) && !patch.is_copy())
+	}
 
-			if classification.file_addition.keys().any(|key| other_classification.file_addition.contains_key(key)) {
-				let absorbtion_error = AbsorbtionError {
-					combined_patch: Some(classification_into_original_patch(other_classification, original_patch_info)),
-					variant: AbsorbtionErrorVariant::ConflictingAdditions,
-				};
-				return Err(absorbtion_error.into());
-			}
+	pub fn find_patch_producing(&self, name: &str) -> Option<usize> {
+		self.patches.iter().position(|patch| patch.new_file_name().map_or(false, |new_name| new_name == name))
+	}
 
-			// First do what we can with classification, the release it to operate on self.patches directly
-			let mut other_unmerged_modification_patches = Vec::new();
+	// Undoes the file patches in reverse, e.g. removes a re-added file before renaming the old one back
+	pub fn invert(&self) -> Result<CombinedPatch, InversionError> {
+		Ok(CombinedPatch {
+			info: self.info.clone(),
+			patches: self.patches.iter().rev().map(|patch| patch.invert()).collect::<Result<_, _>>()?,
+			series_entry: None,
+			originally_empty: self.originally_empty,
+		})
+	}
 
-			for (key, mut other_patch) in other_classification.modification {
-				match classification.modification.get_mut(&key) {
-					Some(patch) => {
-						let positions = (0..other_patch.hunks.len()).into_iter().collect::<Vec<_>>();
-						other_patch.move_hunks_to(&positions, patch).unwrap(); // We'll remove the overlapping hunks check later, so this won't matter
-					}
-					None => other_unmerged_modification_patches.push(other_patch)
-				}
-			}
-			let other_unmerged_modification_patches = other_unmerged_modification_patches;
+	// Returns the next combined patch rewritten to apply before this one, and this one rewritten to apply after it
+	pub fn commute(&self, next_combined_patch: &CombinedPatch) -> Result<(CombinedPatch, CombinedPatch), CommutationError> {
+		let mut new_patches = self.patches.clone();
+		let mut new_next_patches = next_combined_patch.patches.clone();
 
-			let other_file_addition = other_classification.file_addition.into_iter().map(|(_, patch)| patch);
+		for patch_index in 0..self.patches.len() {
+			for next_patch_index in 0..next_combined_patch.patches.len() {
+				// Each commutation builds on the results of the previous ones
+				let (new_next_patch, new_patch) = {
+					let (patch, next_patch) = (&new_patches[patch_index], &new_next_patches[next_patch_index]);
 
-			let removed_files = classification.file_removal_only.keys().cloned().collect::<HashSet<String>>();
-			let other_file_removal_patches = other_classification.file_removal_only
-					.into_iter()
-					.filter_map(|(key, patch)| if removed_files.contains(&key) { None } else { Some(patch) })
-					.collect::<Vec<_>>();
+					let file_names = get_file_names(patch);
+					if !get_file_names(next_patch).iter().any(|name| file_names.contains(name)) { continue; }
 
-			(other_file_addition, other_file_removal_patches, other_unmerged_modification_patches)
+					patch.commute(next_patch)?
+				};
+				new_next_patches[next_patch_index] = new_next_patch;
+				new_patches[patch_index] = new_patch;
+			}
+		}
+
+		let new_next_combined_patch = CombinedPatch {
+			info: next_combined_patch.info.clone(),
+			patches: new_next_patches,
+			series_entry: next_combined_patch.series_entry.clone(),
+			originally_empty: next_combined_patch.originally_empty,
+		};
+		let new_combined_patch = CombinedPatch {
+			info: self.info.clone(),
+			patches: new_patches,
+			series_entry: self.series_entry.clone(),
+			originally_empty: self.originally_empty,
 		};
+		Ok((new_next_combined_patch, new_combined_patch))
+	}
+
+	pub fn absorb(&mut self, CombinedPatch { info, patches, .. }: CombinedPatch) -> Result<(), AbsorbtionError> {
+		self.absorb_patches(Some(info), patches)
+	}
 
-		self.patches.extend(other_file_addition);
-		self.patches.extend(other_file_removal_only);
-		self.patches.extend(other_modification);
+	// The patches stay in place if they can't be moved
+	pub fn move_patches_to(&mut self, patch_positions: &[usize], combined_patch: &mut CombinedPatch) -> Result<(), AbsorbtionError> {
+		let mut patch_positions = patch_positions.to_vec();
+		patch_positions.sort();
+		patch_positions.dedup();
+
+		let transferred_patches = patch_positions.iter().map(|&position| self.patches[position].clone()).collect();
+		combined_patch.absorb_patches(None, transferred_patches).map_err(|err| err.into_patch().0)?;
 
+		for &position in patch_positions.iter().rev() {
+			self.patches.remove(position);
+		}
 		Ok(())
 	}
+
+	// The absorbed patches are applied on top of the patches of this combined patch. Nothing changes if they can't be.
+	fn absorb_patches(&mut self, original_patch_info: Option<ChangeSetInfo>, patches: Vec<Patch>) -> Result<(), AbsorbtionError> {
+		match squash_patches(&self.patches, &patches) {
+			Ok(squashed_patches) => {
+				self.patches = squashed_patches;
+				Ok(())
+			}
+			Err(variant) => Err(AbsorbtionError {
+				combined_patch: Some(CombinedPatch {
+					info: original_patch_info.unwrap_or_default(),
+					patches,
+					series_entry: None,
+					originally_empty: false,
+				}),
+				variant,
+			})
+		}
+	}
 }
 
 #[derive(Fail, Debug)]
@@ -200,14 +250,20 @@ impl AbsorbtionError {
 
 impl Display for AbsorbtionError {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-		write!(f, "Couldn't absorb patches as they contain conflicting additions")
+		let description = match self.variant {
+			AbsorbtionErrorVariant::ConflictingAdditions => "Couldn't absorb patches as they contain conflicting additions",
+			AbsorbtionErrorVariant::ConflictingRemovals => "Couldn't absorb patches as they change the files removed before them",
+			AbsorbtionErrorVariant::HunkError(_) => "Couldn't absorb patches as they contain contradicting hunks"
+		};
+		write!(f, "{}", description)
 	}
 }
 
 #[derive(Debug)]
 pub enum AbsorbtionErrorVariant {
 	ConflictingAdditions,
-	HunkError(OverlappingHunkError),
+	ConflictingRemovals,
+	HunkError(CompositionError),
 }
 
 #[derive(Clone)]
@@ -274,4 +330,275 @@ impl Commit {
 			info,
 		})
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use patch_editor::parser::parse_combined_patch;
+
+	const ADD_F: &[u8] = b"diff --git a/f.txt b/f.txt
+new file mode 100644
+index 0000000..e97f8c1
+--- /dev/null
++++ b/f.txt
+@@ -0,0 +1,10 @@
++f 1
++f 2
++f 3
++f 4
++f 5
++f 6
++f 7
++f 8
++f 9
++f 10
+";
+
+	const REMOVE_F: &[u8] = b"diff --git a/f.txt b/f.txt
+deleted file mode 100644
+index e97f8c1..0000000
+--- a/f.txt
++++ /dev/null
+@@ -1,10 +0,0 @@
+-f 1
+-f 2
+-f 3
+-f 4
+-f 5
+-f 6
+-f 7
+-f 8
+-f 9
+-f 10
+";
+
+	const EDIT_F_2: &[u8] = b"diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,5 +1,5 @@
+ f 1
+-f 2
++f two
+ f 3
+ f 4
+ f 5
+";
+
+	const REMOVE_EDITED_F: &[u8] = b"diff --git a/f.txt b/f.txt
+deleted file mode 100644
+index de69fc1..0000000
+--- a/f.txt
++++ /dev/null
+@@ -1,10 +0,0 @@
+-f 1
+-f two
+-f 3
+-f 4
+-f 5
+-f 6
+-f 7
+-f 8
+-f 9
+-f 10
+";
+
+	const RENAME_F: &[u8] = b"diff --git a/f.txt b/h.txt
+similarity index 84%
+rename from f.txt
+rename to h.txt
+index e97f8c1..37f355d 100644
+--- a/f.txt
++++ b/h.txt
+@@ -6,5 +6,5 @@ f 5
+ f 6
+ f 7
+ f 8
+-f 9
++f nine
+ f 10
+";
+
+	const EDIT_H_2: &[u8] = b"diff --git a/h.txt b/h.txt
+index 37f355d..687e2b4 100644
+--- a/h.txt
++++ b/h.txt
+@@ -1,5 +1,5 @@
+ f 1
+-f 2
++f two
+ f 3
+ f 4
+ f 5
+";
+
+	const RENAMED_AND_EDITED_F: &str = "diff --git a/f.txt b/h.txt
+rename from f.txt
+rename to h.txt
+index e97f8c1..687e2b4 100644
+--- a/f.txt
++++ b/h.txt
+@@ -1,10 +1,10 @@
+ f 1
+-f 2
++f two
+ f 3
+ f 4
+ f 5
+ f 6
+ f 7
+ f 8
+-f 9
++f nine
+ f 10
+";
+
+	fn as_copy(data: &[u8]) -> String {
+		String::from_utf8_lossy(data).replace("rename", "copy").replace("h.txt", "c.txt")
+	}
+
+	fn combined_patch(patches_data: &[&[u8]]) -> CombinedPatch {
+		CombinedPatch {
+			info: ChangeSetInfo::default(),
+			patches: patches_data.iter().flat_map(|data| parse_combined_patch(data).unwrap()).collect(),
+			series_entry: None,
+			originally_empty: false,
+		}
+	}
+
+	fn absorbed(patches_data: &[u8], next_patches_data: &[u8]) -> String {
+		let mut combined_patch = combined_patch(&[patches_data]);
+		combined_patch.absorb(self::combined_patch(&[next_patches_data])).unwrap();
+
+		let mut buf = Vec::new();
+		combined_patch.write(&mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn test_absorb_addition_and_modification() {
+		assert_eq!(absorbed(ADD_F, EDIT_F_2), String::from_utf8_lossy(ADD_F).replace("e97f8c1", "de69fc1").replace("+f 2\n", "+f two\n"));
+	}
+
+	#[test]
+	fn test_absorb_modification_and_removal() {
+		assert_eq!(absorbed(EDIT_F_2, REMOVE_EDITED_F), String::from_utf8_lossy(REMOVE_F));
+	}
+
+	#[test]
+	fn test_absorb_removal_and_addition() {
+		let edited_f = String::from_utf8_lossy(ADD_F).replace("e97f8c1", "de69fc1").replace("+f 2\n", "+f two\n");
+		assert_eq!(absorbed(REMOVE_F, edited_f.as_bytes()), String::from_utf8_lossy(EDIT_F_2));
+	}
+
+	#[test]
+	fn test_absorb_addition_and_removal() {
+		assert_eq!(absorbed(ADD_F, REMOVE_F), "");
+		assert_eq!(absorbed(EDIT_F_2, String::from_utf8_lossy(EDIT_F_2).replace("-f 2\n+f two", "-f two\n+f 2").as_bytes()), "");
+	}
+
+	#[test]
+	fn test_absorb_rename_and_edit() {
+		assert_eq!(absorbed(RENAME_F, EDIT_H_2), RENAMED_AND_EDITED_F);
+
+		let renamed_back_f = b"diff --git a/h.txt b/f.txt\nsimilarity index 84%\nrename from h.txt\nrename to f.txt\nindex 37f355d..e97f8c1 100644\n\
+			--- a/h.txt\n+++ b/f.txt\n@@ -6,5 +6,5 @@ f 5\n f 6\n f 7\n f 8\n-f nine\n+f 9\n f 10\n";
+		assert_eq!(absorbed(RENAME_F, renamed_back_f), "");
+	}
+
+	#[test]
+	fn test_absorb_copies() {
+		assert_eq!(absorbed(as_copy(RENAME_F).as_bytes(), as_copy(EDIT_H_2).as_bytes()), as_copy(RENAMED_AND_EDITED_F.as_bytes()));
+
+		// The copy is made from the edited file, so it gets the edit too
+		let expected = String::from_utf8_lossy(EDIT_F_2).into_owned() + &as_copy(RENAMED_AND_EDITED_F.as_bytes()).replace("687e2b4", "37f355d");
+		assert_eq!(absorbed(EDIT_F_2, as_copy(RENAME_F).as_bytes()), expected);
+
+		let removed_copy = String::from_utf8_lossy(REMOVE_F).replace("f.txt", "c.txt").replace("e97f8c1", "37f355d").replace("-f 9\n", "-f nine\n");
+		assert_eq!(absorbed(as_copy(RENAME_F).as_bytes(), removed_copy.as_bytes()), "");
+	}
+
+	#[test]
+	fn test_invert_rename_and_addition() {
+		let inverted = combined_patch(&[RENAME_F, ADD_F]).invert().unwrap();
+		match inverted.patches[0].change {
+			Change::Removal { ref old_properties } => assert_eq!(old_properties.name, "f.txt"),
+			ref change => panic!("Unexpected change: {:?}", change)
+		}
+		assert_eq!(inverted.patches[1].new_file_name(), Some(&String::from("f.txt")));
+	}
+
+	#[test]
+	fn test_commute_with_several_patches_of_file() {
+		let append_to_f = b"diff --git a/f.txt b/f.txt\nindex e97f8c1..b1d7f1b 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -8,3 +8,4 @@\n f 8\n f 9\n f 10\n+f 11\n";
+		let insert_into_f = b"diff --git a/f.txt b/f.txt\nindex b1d7f1b..0f5a3c2 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,3 @@\n f 1\n+f 1.5\n f 2\n\
+			diff --git a/f.txt b/f.txt\nindex 0f5a3c2..3b9e1d4 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -3,3 +3,4 @@\n f 2\n f 3\n+f 3.5\n f 4\n";
+		let (_, new_combined_patch) = combined_patch(&[append_to_f]).commute(&combined_patch(&[insert_into_f])).unwrap();
+
+		let mut buf = Vec::new();
+		new_combined_patch.write(&mut buf).unwrap();
+		assert_eq!(String::from_utf8(buf).unwrap(), "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -10,3 +10,4 @@\n f 8\n f 9\n f 10\n+f 11\n");
+	}
+
+	#[test]
+	fn test_find_patches() {
+		let edit_g = String::from_utf8_lossy(EDIT_H_2).replace("h.txt", "g.txt");
+		let combined_patch = combined_patch(&[RENAME_F, edit_g.as_bytes(), as_copy(RENAME_F).as_bytes()]);
+		assert_eq!(combined_patch.find_patch_reading("f.txt"), Some(0));
+		assert_eq!(combined_patch.find_patch_producing("h.txt"), Some(0));
+		assert_eq!(combined_patch.find_patch_reading("g.txt"), Some(1));
+		assert_eq!(combined_patch.find_patch_producing("c.txt"), Some(2));
+		assert_eq!(combined_patch.find_patch_reading("c.txt"), None);
+	}
+
+	#[test]
+	fn test_prune_empty_patches() {
+		let mut combined_patch = combined_patch(&[RENAME_F, EDIT_H_2, ADD_F]);
+		combined_patch.patches[0].hunks.clear();
+		combined_patch.patches[1].hunks.clear();
+		combined_patch.prune_empty_patches();
+		assert_eq!(combined_patch.patches.len(), 2);
+		assert!(combined_patch.find_patch_producing("h.txt").is_some());
+		assert!(combined_patch.find_patch_producing("f.txt").is_some());
+	}
+
+	#[test]
+	fn test_absorb_conflicting_patches() {
+		let mut combined_patch = combined_patch(&[REMOVE_F]);
+		let next_combined_patch = self::combined_patch(&[EDIT_H_2, EDIT_F_2]);
+		let err = combined_patch.absorb(next_combined_patch.clone()).unwrap_err();
+		match err.variant {
+			AbsorbtionErrorVariant::ConflictingRemovals => (),
+			ref variant => panic!("Unexpected error: {:?}", variant)
+		}
+		assert_eq!(err.combined_patch.unwrap().patches, next_combined_patch.patches);
+		assert_eq!(combined_patch.patches, self::combined_patch(&[REMOVE_F]).patches);
+
+		match combined_patch.absorb(self::combined_patch(&[EDIT_H_2, REMOVE_F])).unwrap_err().variant {
+			AbsorbtionErrorVariant::ConflictingRemovals => (),
+			ref variant => panic!("Unexpected error: {:?}", variant)
+		}
+
+		let mut combined_patch = self::combined_patch(&[ADD_F]);
+		match combined_patch.absorb(self::combined_patch(&[ADD_F])).unwrap_err().variant {
+			AbsorbtionErrorVariant::ConflictingAdditions => (),
+			ref variant => panic!("Unexpected error: {:?}", variant)
+		}
+	}
+
+	#[test]
+	fn test_move_patches_to() {
+		let mut combined_patch = combined_patch(&[EDIT_H_2, EDIT_F_2, as_copy(RENAME_F).as_bytes()]);
+		let mut other_combined_patch = self::combined_patch(&[RENAME_F]);
+
+		assert!(combined_patch.move_patches_to(&[1], &mut other_combined_patch).is_err());
+		assert_eq!(combined_patch.patches.len(), 3);
+
+		combined_patch.move_patches_to(&[0], &mut other_combined_patch).unwrap();
+		assert_eq!(combined_patch.patches, self::combined_patch(&[EDIT_F_2, as_copy(RENAME_F).as_bytes()]).patches);
+		let mut buf = Vec::new();
+		other_combined_patch.write(&mut buf).unwrap();
+		assert_eq!(String::from_utf8(buf).unwrap(), RENAMED_AND_EDITED_F);
+	}
+}
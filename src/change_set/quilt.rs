@@ -0,0 +1,300 @@
+use std::io::{self, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::string::FromUtf8Error;
+use std::str::SplitWhitespace;
+
+use change_set::{CombinedPatch, ChangeSetInfo, PersonAction};
+use change_set::mbox::{parse_mbox_with_strip_level, parse_date, write_patch_file, patch_file_name, MailParsingError};
+use patch_editor::parser::{parse_unified_diff, ParseError};
+
+pub const SERIES_FILE_NAME: &str = "series";
+const DEFAULT_PATCH_NAME: &str = "patch";
+const DEFAULT_STRIP_LEVEL: usize = 1;
+
+#[derive(Fail, Debug)]
+pub enum SeriesError {
+	#[fail(display = "I/O error: {}", _0)]
+	IoError(#[cause] io::Error),
+	#[fail(display = "Unsupported option {:?} for the patch {}", _1, _0)]
+	UnsupportedOption(String, String),
+	#[fail(display = "Error when parsing the header of the patch {}: {}", _0, _1)]
+	EncodingError(String, FromUtf8Error),
+	#[fail(display = "Error when parsing the patch {}: {}", _0, _1)]
+	MailParsingError(String, MailParsingError),
+	#[fail(display = "Error when parsing the patch {}: {}", _0, _1)]
+	PatchError(String, ParseError),
+}
+
+// The line of the series file the patch was read from, so that it's written back the same way
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SeriesEntry {
+	pub file_name: String,
+	pub strip: usize,
+}
+
+impl From<io::Error> for SeriesError {
+	fn from(error: io::Error) -> Self {
+		SeriesError::IoError(error)
+	}
+}
+
+// Reads the patches listed in the series file of a quilt or StGit patches directory, in the order they are applied
+pub fn read_series<P: AsRef<Path>>(patches_dir: P) -> Result<Vec<CombinedPatch>, SeriesError> {
+	let series = fs::read(patches_dir.as_ref().join(SERIES_FILE_NAME))?;
+	let series = String::from_utf8(series).map_err(|err| SeriesError::EncodingError(SERIES_FILE_NAME.into(), err))?;
+
+	let mut combined_patches = Vec::new();
+	for line in series.lines() {
+		let mut parts = series_line_parts(line);
+		let name = match parts.next() {
+			Some(name) => name,
+			None => continue
+		};
+		let mut strip = DEFAULT_STRIP_LEVEL;
+		for option in parts {
+			let level = if option.starts_with("-p") { option[2..].parse().ok() } else { None };
+			strip = level.ok_or_else(|| SeriesError::UnsupportedOption(name.into(), option.into()))?;
+		}
+
+		let data = fs::read(patches_dir.as_ref().join(name))?;
+		combined_patches.push(parse_patch_file(name, &data, strip)?);
+	}
+	Ok(combined_patches)
+}
+
+// The file name and the options, leaving out the comment
+fn series_line_parts(line: &str) -> SplitWhitespace {
+	line.split('#').next().unwrap().split_whitespace()
+}
+
+// The patches written by format-patch or by us have the mail headers, otherwise the header is free text describing the patch
+fn parse_patch_file(name: &str, data: &[u8], strip: usize) -> Result<CombinedPatch, SeriesError> {
+	if data.starts_with(b"From ") || data.starts_with(b"From: ") {
+		let mut combined_patches = parse_mbox_with_strip_level(data, strip).map_err(|err| SeriesError::MailParsingError(name.into(), err))?;
+		if combined_patches.len() == 1 {
+			return Ok(CombinedPatch {
+				series_entry: Some(SeriesEntry { file_name: name.into(), strip }),
+				..combined_patches.remove(0)
+			});
+		}
+	}
+
+	let lines = data.split(|byte| *byte == b'\n').collect::<Vec<_>>();
+	let patch_start = lines.iter().enumerate().position(|(index, line)| {
+		line.starts_with(b"diff ") || line.starts_with(b"Index: ") ||
+				(line.starts_with(b"--- ") && lines.get(index + 1).map_or(false, |next_line| next_line.starts_with(b"+++ ")))
+	}).unwrap_or(lines.len());
+
+	let header = lines[..patch_start].join(&b"\n"[..]);
+	let header = String::from_utf8(header).map_err(|err| SeriesError::EncodingError(name.into(), err))?;
+
+	let mut author_action = PersonAction::default();
+	let mut message_lines = Vec::new();
+	for line in header.lines().take_while(|line| *line != "---") { // The diffstat follows the separator
+		if line.starts_with("From: ") || line.starts_with("Author: ") {
+			author_action.name = line.splitn(2, ": ").nth(1).unwrap().trim().into();
+		} else if let Some((time, time_zone)) = Some(line).filter(|line| line.starts_with("Date: ")).and_then(|line| parse_date(&line[6..])) {
+			author_action.time = time;
+			author_action.time_zone = time_zone;
+		} else {
+			message_lines.push(line.trim_right());
+		}
+	}
+
+	let message = message_lines.join("\n");
+	let message = message.trim();
+	let message = if message.is_empty() { name.trim_right_matches(".patch").to_owned() } else { message.to_owned() };
+
+	let patches = if patch_start < lines.len() {
+		let patch_data = lines[patch_start..].join(&b"\n"[..]);
+		parse_unified_diff(&patch_data, strip).map_err(|err| SeriesError::PatchError(name.into(), err))?
+	} else {
+		Vec::new()
+	};
+	Ok(CombinedPatch {
+		info: ChangeSetInfo {
+			author_action,
+			committer_action: PersonAction::default(),
+			message: message + "\n",
+		},
+		originally_empty: patches.is_empty(),
+		patches,
+		series_entry: Some(SeriesEntry { file_name: name.into(), strip }),
+	})
+}
+
+// Writes the patches in the order they are applied, replacing the series file. The patches read from a series keep their file names and options,
+// the names of the new ones come from their subjects. The other files stay, same as quilt keeps the files of the deleted patches.
+pub fn write_series<P: AsRef<Path>>(combined_patches: &[CombinedPatch], patches_dir: P) -> Result<Vec<PathBuf>, SeriesError> {
+	fs::create_dir_all(&patches_dir)?;
+
+	// The kept names are taken first, so that the generated ones don't overwrite them
+	let mut used_names = HashSet::new();
+	let kept_names = combined_patches.iter()
+			.map(|combined_patch| combined_patch.series_entry.as_ref().and_then(|entry| {
+				if used_names.insert(entry.file_name.clone()) { Some(entry.file_name.clone()) } else { None }
+			}))
+			.collect::<Vec<_>>();
+
+	let mut file_paths = Vec::new();
+	let mut series = File::create(patches_dir.as_ref().join(SERIES_FILE_NAME))?;
+	for (combined_patch, kept_name) in combined_patches.iter().zip(kept_names) {
+		let name = match kept_name {
+			Some(name) => name,
+			None => generate_patch_name(combined_patch, &mut used_names)
+		};
+		let strip = combined_patch.series_entry.as_ref().map_or(DEFAULT_STRIP_LEVEL, |entry| entry.strip);
+
+		let file_path = patches_dir.as_ref().join(&name);
+		write_patch_file(combined_patch, strip, &mut File::create(&file_path)?)?;
+		if strip == DEFAULT_STRIP_LEVEL {
+			series.write_fmt(format_args!("{}\n", name))?;
+		} else {
+			series.write_fmt(format_args!("{} -p{}\n", name, strip))?;
+		}
+		file_paths.push(file_path);
+	}
+	Ok(file_paths)
+}
+
+fn generate_patch_name(combined_patch: &CombinedPatch, used_names: &mut HashSet<String>) -> String {
+	let mut base_name = patch_file_name(None, combined_patch).trim_right_matches(".patch").to_owned();
+	if base_name.is_empty() {
+		base_name = DEFAULT_PATCH_NAME.into();
+	}
+
+	let mut name = format!("{}.patch", base_name);
+	let mut suffix = 1;
+	while !used_names.insert(name.clone()) {
+		suffix += 1;
+		name = format!("{}-{}.patch", base_name, suffix);
+	}
+	name
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use tempdir::TempDir;
+	use patch_editor::patch::Change;
+	use change_set::mbox::parse_mbox;
+
+	const FREE_TEXT_PATCH: &[u8] = b"Fix the second line
+
+The line was wrong.
+
+Author: Someone <someone@example.com>
+---
+ f.txt | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,3 @@
+ f 1
+-f 2
++f two
+ f 3
+";
+
+	const EMAIL_PATCH: &[u8] = b"From: Someone <someone@example.com>
+Date: Tue, 9 Oct 2018 12:34:56 +0300
+Subject: [PATCH] Add a file
+
+diff --git a/g.txt b/g.txt
+new file mode 100644
+index 0000000..fde5c9b
+--- /dev/null
++++ b/g.txt
+@@ -0,0 +1 @@
++g 1
+";
+
+	#[test]
+	fn test_series_round_trip() {
+		let temp_dir = TempDir::new("begitter").unwrap();
+		let patches_dir = temp_dir.path().join("patches");
+		fs::create_dir(&patches_dir).unwrap();
+		fs::write(patches_dir.join("fix.patch"), FREE_TEXT_PATCH).unwrap();
+		fs::write(patches_dir.join("add.diff"), EMAIL_PATCH).unwrap();
+		fs::write(patches_dir.join(SERIES_FILE_NAME), "# The vendor patches\nfix.patch\n\nadd.diff -p1\n").unwrap();
+
+		let combined_patches = read_series(&patches_dir).unwrap();
+		assert_eq!(combined_patches.len(), 2);
+		assert_eq!(combined_patches[0].info.message, "Fix the second line\n\nThe line was wrong.\n");
+		assert_eq!(combined_patches[0].info.author_action.name, "Someone <someone@example.com>");
+		assert_eq!(combined_patches[1].info.message, "Add a file\n");
+		assert_eq!(combined_patches[1].info.author_action.time_zone, 300);
+
+		let output_dir = temp_dir.path().join("output");
+		let file_paths = write_series(&combined_patches, &output_dir).unwrap();
+		assert_eq!(file_paths, vec![output_dir.join("fix.patch"), output_dir.join("add.diff")]);
+		assert_eq!(fs::read_to_string(output_dir.join(SERIES_FILE_NAME)).unwrap(), "fix.patch\nadd.diff\n");
+
+		let written_patches = read_series(&output_dir).unwrap();
+		for (written_patch, combined_patch) in written_patches.iter().zip(combined_patches.iter()) {
+			assert_eq!(written_patch.info.message, combined_patch.info.message);
+			assert_eq!(written_patch.info.author_action.name, combined_patch.info.author_action.name);
+			assert_eq!(written_patch.patches, combined_patch.patches);
+		}
+	}
+
+	#[test]
+	fn test_plain_diff_series() {
+		let temp_dir = TempDir::new("begitter").unwrap();
+		fs::write(temp_dir.path().join("quilt.patch"), "Change the line\n\nIndex: pkg/f.txt\n\
+			===================================================================\n--- pkg.orig/f.txt\n+++ pkg/f.txt\n@@ -1 +1 @@\n-x\n+y\n").unwrap();
+		fs::write(temp_dir.path().join("new.patch"), "--- /dev/null\n+++ dir/n.txt\n@@ -0,0 +1 @@\n+n\n").unwrap();
+		fs::write(temp_dir.path().join(SERIES_FILE_NAME), "quilt.patch\nnew.patch -p0\n").unwrap();
+
+		let combined_patches = read_series(temp_dir.path()).unwrap();
+		assert_eq!(combined_patches[0].info.message, "Change the line\n");
+		assert_eq!(combined_patches[0].patches[0].get_edit_patch_file_name(), Some(&String::from("f.txt")));
+		assert_eq!(combined_patches[1].info.message, "new\n");
+		match combined_patches[1].patches[0].change {
+			Change::Addition { ref new_properties } => assert_eq!(new_properties.name, "dir/n.txt"),
+			ref change => panic!("Unexpected change: {:?}", change)
+		}
+	}
+
+	#[test]
+	fn test_write_series_in_place() {
+		let temp_dir = TempDir::new("begitter").unwrap();
+		fs::write(temp_dir.path().join("fix.patch"), FREE_TEXT_PATCH).unwrap();
+		fs::write(temp_dir.path().join("dropped.patch"), "Change the line\n\nIndex: pkg/f.txt\n\
+			===================================================================\n--- pkg.orig/f.txt\n+++ pkg/f.txt\n@@ -1 +1 @@\n-x\n+y\n").unwrap();
+		fs::write(temp_dir.path().join("new.patch"), "--- /dev/null\n+++ dir/n.txt\n@@ -0,0 +1 @@\n+n\n").unwrap();
+		fs::write(temp_dir.path().join(SERIES_FILE_NAME), "fix.patch\ndropped.patch\nnew.patch -p0\n").unwrap();
+
+		let mut combined_patches = read_series(temp_dir.path()).unwrap();
+		combined_patches.remove(1);
+		combined_patches.push(parse_mbox(EMAIL_PATCH).unwrap().remove(0));
+
+		let file_paths = write_series(&combined_patches, temp_dir.path()).unwrap();
+		assert_eq!(file_paths, vec![temp_dir.path().join("fix.patch"), temp_dir.path().join("new.patch"), temp_dir.path().join("Add-a-file.patch")]);
+		assert_eq!(fs::read_to_string(temp_dir.path().join(SERIES_FILE_NAME)).unwrap(), "fix.patch\nnew.patch -p0\nAdd-a-file.patch\n");
+		assert!(temp_dir.path().join("dropped.patch").exists());
+
+		let written_patches = read_series(temp_dir.path()).unwrap();
+		assert_eq!(written_patches.len(), 3);
+		for (written_patch, combined_patch) in written_patches.iter().zip(combined_patches.iter()) {
+			assert_eq!(written_patch.info.message, combined_patch.info.message);
+			assert_eq!(written_patch.patches, combined_patch.patches);
+		}
+		assert_eq!(written_patches[1].series_entry, Some(SeriesEntry { file_name: "new.patch".into(), strip: 0 }));
+	}
+
+	#[test]
+	fn test_unsupported_option() {
+		let temp_dir = TempDir::new("begitter").unwrap();
+		fs::write(temp_dir.path().join(SERIES_FILE_NAME), "fix.patch -R\n").unwrap();
+		match read_series(temp_dir.path()) {
+			Err(SeriesError::UnsupportedOption(name, option)) => assert_eq!((name.as_str(), option.as_str()), ("fix.patch", "-R")),
+			result => panic!("Unexpected result: {:?}", result.map(|patches| patches.len()))
+		}
+	}
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeSet;
+
+use patch_editor::patch::{HunkLineKind, InseparableLinesError};
+use change_set::{CombinedPatch, ChangeSetInfo};
+
+// The positions of the file patch, of the hunk in it, and of the line in the hunk, counting the context lines too
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Selector {
+	File(usize),
+	Hunk(usize, usize),
+	Line(usize, usize, usize),
+}
+
+#[derive(Fail, Debug)]
+pub enum SplitError {
+	#[fail(display = "No changes to select at {:?}", _0)]
+	InvalidSelector(Selector),
+	#[fail(display = "The changes at {:?} are selected for more than one part", _0)]
+	DuplicateSelection(Selector),
+	#[fail(display = "The patch of {} adds, removes or copies the file, so it can only go to one part as a whole", _0)]
+	UnsplittablePatch(String),
+	#[fail(display = "{}", _0)]
+	InseparableLines(#[cause] InseparableLinesError),
+}
+
+impl From<InseparableLinesError> for SplitError {
+	fn from(error: InseparableLinesError) -> Self {
+		SplitError::InseparableLines(error)
+	}
+}
+
+fn select(part_slot: &mut Option<usize>, part: usize, selector: Selector) -> Result<(), SplitError> {
+	match *part_slot {
+		Some(other_part) if other_part != part => Err(SplitError::DuplicateSelection(selector)),
+		_ => {
+			*part_slot = Some(part);
+			Ok(())
+		}
+	}
+}
+
+// The partitions go in the order they are applied, and the changes nobody selects make the last part.
+// Returns the parts in the same order, leaving out those without changes. The parts are numbered in the subjects if there are several.
+pub fn split_combined_patch(combined_patch: &CombinedPatch, partitions: &[Vec<Selector>]) -> Result<Vec<CombinedPatch>, SplitError> {
+	let patches = &combined_patch.patches;
+	let part_count = partitions.len() + 1;
+
+	let mut file_parts = vec![None; patches.len()];
+	let mut line_parts = patches.iter()
+			.map(|patch| patch.hunks.iter().map(|hunk| vec![None; hunk.lines.len()]).collect::<Vec<_>>())
+			.collect::<Vec<_>>();
+
+	for (part, selectors) in partitions.iter().enumerate() {
+		for &selector in selectors {
+			let (file, hunk, line) = match selector {
+				Selector::File(file) => (file, None, None),
+				Selector::Hunk(file, hunk) => (file, Some(hunk), None),
+				Selector::Line(file, hunk, line) => (file, Some(hunk), Some(line))
+			};
+			let patch = patches.get(file).ok_or(SplitError::InvalidSelector(selector))?;
+			let hunk_exists = hunk.map_or(true, |hunk| hunk < patch.hunks.len());
+			if !hunk_exists || !line.map_or(true, |line| line < patch.hunks[hunk.unwrap()].lines.len()) {
+				return Err(SplitError::InvalidSelector(selector));
+			}
+			if hunk.is_none() {
+				select(&mut file_parts[file], part, selector)?;
+			}
+
+			for (hunk_position, hunk_data) in patch.hunks.iter().enumerate().filter(|&(position, _)| hunk.map_or(true, |hunk| hunk == position)) {
+				for (line_position, line_data) in hunk_data.lines.iter().enumerate().filter(|&(position, _)| line.map_or(true, |line| line == position)) {
+					if line_data.kind == HunkLineKind::Context {
+						if line.is_some() { return Err(SplitError::InvalidSelector(selector)); }
+						continue;
+					}
+					select(&mut line_parts[file][hunk_position][line_position], part, selector)?;
+				}
+			}
+		}
+	}
+
+	let mut parts = vec![Vec::new(); part_count];
+	for ((patch, file_part), line_parts) in patches.iter().zip(file_parts).zip(line_parts) {
+		let default_part = file_part.unwrap_or(part_count - 1);
+		let line_parts = line_parts.into_iter()
+				.map(|hunk_line_parts| hunk_line_parts.into_iter().map(|part| part.unwrap_or(default_part)).collect::<Vec<_>>())
+				.collect::<Vec<_>>();
+
+		let mut used_parts = BTreeSet::new();
+		for (hunk, hunk_line_parts) in patch.hunks.iter().zip(&line_parts) {
+			used_parts.extend(hunk.lines.iter().zip(hunk_line_parts).filter(|&(line, _)| line.kind != HunkLineKind::Context).map(|(_, &part)| part));
+		}
+
+		if used_parts.len() <= 1 {
+			parts[used_parts.into_iter().next().unwrap_or(default_part)].push(patch.clone());
+		} else if !patch.has_movable_hunks() {
+			let name = patch.new_file_name().or(patch.old_file_name()).unwrap();
+			return Err(SplitError::UnsplittablePatch(name.clone()));
+		} else {
+			for (part, part_patch) in patch.split_lines(&line_parts, part_count)?.into_iter().enumerate() {
+				parts[part].extend(part_patch);
+			}
+		}
+	}
+
+	let parts = parts.into_iter().filter(|patches| !patches.is_empty()).collect::<Vec<_>>();
+	if parts.len() <= 1 {
+		return Ok(vec![combined_patch.clone()]);
+	}
+
+	let count = parts.len();
+	Ok(parts.into_iter().enumerate().map(|(index, patches)| {
+		CombinedPatch {
+			info: numbered_info(&combined_patch.info, index + 1, count),
+			patches,
+			series_entry: None,
+			originally_empty: false,
+		}
+	}).collect())
+}
+
+fn numbered_info(info: &ChangeSetInfo, number: usize, count: usize) -> ChangeSetInfo {
+	let mut lines = info.message.splitn(2, '\n');
+	let subject = lines.next().unwrap();
+	let rest = lines.next().map_or(String::new(), |rest| format!("\n{}", rest));
+
+	ChangeSetInfo {
+		message: format!("{} ({}/{}){}", subject, number, count, rest),
+		..info.clone()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use patch_editor::parser::parse_combined_patch;
+
+	const EDIT_F_AND_G: &[u8] = b"diff --git a/f.txt b/f.txt
+index 3e7c6fb..0b7f2a4 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,4 +1,4 @@
+-f 1
++f one
+ f 2
+-f 3
++f three
+ f 4
+@@ -8,3 +8,4 @@ f 7
+ f 8
+ f 9
+ f 10
++f 11
+diff --git a/g.txt b/g.txt
+new file mode 100644
+index 0000000..fde5c9b
+--- /dev/null
++++ b/g.txt
+@@ -0,0 +1,2 @@
++g 1
++g 2
+";
+
+	fn combined_patch() -> CombinedPatch {
+		CombinedPatch {
+			info: ChangeSetInfo {
+				message: "Change the files\n\nThe details.\n".into(),
+				..ChangeSetInfo::default()
+			},
+			patches: parse_combined_patch(EDIT_F_AND_G).unwrap(),
+			series_entry: None,
+			originally_empty: false,
+		}
+	}
+
+	fn written(combined_patch: &CombinedPatch) -> String {
+		let mut buf = Vec::new();
+		combined_patch.write(&mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn test_split_by_files_and_hunks() {
+		let combined_patch = combined_patch();
+		let parts = split_combined_patch(&combined_patch, &[vec![Selector::File(1)], vec![Selector::Hunk(0, 1)]]).unwrap();
+		assert_eq!(parts.len(), 3);
+		assert_eq!(parts[0].info.message, "Change the files (1/3)\n\nThe details.\n");
+		assert_eq!(parts[2].info.message, "Change the files (3/3)\n\nThe details.\n");
+		assert_eq!(parts[0].patches, vec![combined_patch.patches[1].clone()]);
+		assert_eq!(written(&parts[1]), "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -8,3 +8,4 @@ f 7\n f 8\n f 9\n f 10\n+f 11\n");
+		assert!(written(&parts[2]).contains("@@ -1,4 +1,4 @@\n-f 1\n+f one\n"));
+
+		let mut squashed = parts[0].clone();
+		squashed.absorb(parts[1].clone()).unwrap();
+		squashed.absorb(parts[2].clone()).unwrap();
+		assert_eq!(squashed.patches.iter().map(|patch| &patch.hunks).collect::<Vec<_>>(),
+			combined_patch.patches.iter().rev().map(|patch| &patch.hunks).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn test_split_lines() {
+		let parts = split_combined_patch(&combined_patch(), &[vec![Selector::Line(0, 0, 3), Selector::Line(0, 0, 4), Selector::Line(0, 1, 3)]]).unwrap();
+		assert_eq!(parts.len(), 2);
+		assert_eq!(written(&parts[0]), "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n\
+			@@ -1,4 +1,4 @@\n f 1\n f 2\n-f 3\n+f three\n f 4\n@@ -8,3 +8,4 @@ f 7\n f 8\n f 9\n f 10\n+f 11\n");
+		assert_eq!(written(&parts[1]), "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n\
+			@@ -1,4 +1,4 @@\n-f 1\n+f one\n f 2\n f three\n f 4\n\
+			diff --git a/g.txt b/g.txt\nnew file mode 100644\nindex 0000000..fde5c9b\n--- /dev/null\n+++ b/g.txt\n@@ -0,0 +1,2 @@\n+g 1\n+g 2\n");
+	}
+
+	#[test]
+	fn test_split_nothing() {
+		let combined_patch = combined_patch();
+		let parts = split_combined_patch(&combined_patch, &[vec![], vec![Selector::File(0), Selector::File(1)]]).unwrap();
+		assert_eq!(parts.len(), 1);
+		assert_eq!(parts[0].info, combined_patch.info);
+	}
+
+	#[test]
+	fn test_invalid_splits() {
+		let combined_patch = combined_patch();
+		match split_combined_patch(&combined_patch, &[vec![Selector::Line(0, 0, 2)]]) {
+			Err(SplitError::InvalidSelector(Selector::Line(0, 0, 2))) => (),
+			result => panic!("Unexpected result: {:?}", result.map(|parts| parts.len()))
+		}
+		match split_combined_patch(&combined_patch, &[vec![Selector::Hunk(0, 2)]]) {
+			Err(SplitError::InvalidSelector(Selector::Hunk(0, 2))) => (),
+			result => panic!("Unexpected result: {:?}", result.map(|parts| parts.len()))
+		}
+		match split_combined_patch(&combined_patch, &[vec![Selector::File(0)], vec![Selector::Hunk(0, 0)]]) {
+			Err(SplitError::DuplicateSelection(Selector::Hunk(0, 0))) => (),
+			result => panic!("Unexpected result: {:?}", result.map(|parts| parts.len()))
+		}
+		match split_combined_patch(&combined_patch, &[vec![Selector::Line(1, 0, 0)]]) {
+			Err(SplitError::UnsplittablePatch(name)) => assert_eq!(name, "g.txt"),
+			result => panic!("Unexpected result: {:?}", result.map(|parts| parts.len()))
+		}
+	}
+}
@@ -30,6 +30,78 @@ pub enum PatchApplicationMode {
 	WorkingDirectoryWithRejects,
 }
 
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum GitDiffAlgorithm {
+	Myers,
+	Minimal,
+	Patience,
+	Histogram,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum GitWhitespaceMode {
+	Exact,
+	IgnoreAtEndOfLine,
+	IgnoreChanges,
+	IgnoreAll,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct GitDiffOptions {
+	pub context_lines: usize,
+	pub algorithm: GitDiffAlgorithm,
+	pub rename_threshold: Option<u8>, // Similarity percentage, no detection if absent
+	pub copy_threshold: Option<u8>,
+	pub whitespace_mode: GitWhitespaceMode,
+	pub pathspecs: Vec<String>,
+}
+
+impl Default for GitDiffOptions {
+	fn default() -> GitDiffOptions {
+		GitDiffOptions {
+			context_lines: 3,
+			algorithm: GitDiffAlgorithm::Myers,
+			rename_threshold: Some(50),
+			copy_threshold: None,
+			whitespace_mode: GitWhitespaceMode::Exact,
+			pathspecs: Vec::new(),
+		}
+	}
+}
+
+impl GitDiffOptions {
+	// The diffs made this way leave some changes out, so the commits can't be rewritten from them
+	pub fn is_lossy(&self) -> bool {
+		self.whitespace_mode != GitWhitespaceMode::Exact || !self.pathspecs.is_empty()
+	}
+
+	fn to_args(&self) -> Vec<String> {
+		let algorithm = match self.algorithm {
+			GitDiffAlgorithm::Myers => "myers",
+			GitDiffAlgorithm::Minimal => "minimal",
+			GitDiffAlgorithm::Patience => "patience",
+			GitDiffAlgorithm::Histogram => "histogram"
+		};
+		let mut args = vec![format!("-U{}", self.context_lines), format!("--diff-algorithm={}", algorithm)];
+
+		if let Some(threshold) = self.rename_threshold {
+			args.push(format!("--find-renames={}%", threshold));
+		}
+		if let Some(threshold) = self.copy_threshold {
+			args.push(format!("--find-copies={}%", threshold));
+		}
+
+		match self.whitespace_mode {
+			GitWhitespaceMode::Exact => (),
+			GitWhitespaceMode::IgnoreAtEndOfLine => args.push("--ignore-space-at-eol".into()),
+			GitWhitespaceMode::IgnoreChanges => args.push("--ignore-space-change".into()),
+			GitWhitespaceMode::IgnoreAll => args.push("--ignore-all-space".into())
+		}
+
+		args
+	}
+}
+
 pub struct Git {
 	repo_dir: OsString
 }
@@ -158,6 +230,15 @@ impl Git {
 				.collect())
 	}
 
+	// The commits reachable from the first spec but not from the second one, skipping the merges
+	pub fn rev_list_excluding(&self, commit_spec: &str, excluded_commit_spec: &str) -> Result<Vec<String>> {
+		let exclusion = String::from("^") + excluded_commit_spec;
+		let output_text = self.run_command(&["rev-list", "--no-merges", commit_spec, &exclusion])?;
+		Ok(output_text.split_terminator('\n')
+				.map(|string| string.to_owned())
+				.collect())
+	}
+
 	pub fn symbolic_ref(&self, ref_name: &str) -> Result<String> {
 		let result = self.run_command(&["symbolic-ref", "--quiet", ref_name])?;
 		Ok(result.trim().into())
@@ -172,8 +253,24 @@ impl Git {
 		Ok(())
 	}
 
-	pub fn diff_tree(&self, commit_spec: &str) -> Result<String> {
-		self.run_command(&["diff-tree", "--no-commit-id", "--find-renames", "--patch", "-r", commit_spec])
+	pub fn diff_tree(&self, commit_spec: &str, options: &GitDiffOptions) -> Result<String> {
+		// A root commit shows as adding its files, so that only the empty commits have no diff
+		let mut args: Vec<String> = vec!["diff-tree".into(), "--no-commit-id".into(), "--root".into(), "--patch".into(), "-r".into()];
+		args.extend(options.to_args());
+		args.push(commit_spec.into());
+
+		if !options.pathspecs.is_empty() {
+			args.push("--".into());
+			args.extend(options.pathspecs.iter().cloned());
+		}
+
+		self.run_command(&args)
+	}
+
+	// Dense diffs only list the changes which differ from all the parents, i.e. the conflict resolutions
+	pub fn diff_tree_combined(&self, commit_spec: &str, dense: bool) -> Result<String> {
+		let diff_type = if dense { "--cc" } else { "-c" };
+		self.run_command(&["diff-tree", "--no-commit-id", diff_type, "--patch", "-r", commit_spec])
 	}
 
 	pub fn diff_index_names(&self, commit_spec: &str) -> Result<Vec<String>> {
@@ -195,8 +292,12 @@ impl Git {
 		Ok(())
 	}
 
-	pub fn apply(&self, patch: &[u8], mode: PatchApplicationMode) -> Result<()> {
+	// The hunks without context lines can only be applied at the exact positions, which git has to be told about
+	pub fn apply(&self, patch: &[u8], mode: PatchApplicationMode, zero_context: bool) -> Result<()> {
 		let mut args = vec!["apply"];
+		if zero_context {
+			args.push("--unidiff-zero");
+		}
 
 		match mode {
 			PatchApplicationMode::IndexOnly => args.push("--cached"),
@@ -341,7 +442,7 @@ index 9944a9f..e9459b0 100644
 		git.checkout_index().unwrap();
 
 		let mode = if use_rejects { PatchApplicationMode::WorkingDirectoryWithRejects } else { PatchApplicationMode::WorkingDirectory3Way };
-		let apply_result = git.apply(PATCH, mode);
+		let apply_result = git.apply(PATCH, mode, false);
 		assert!(apply_result.is_err());
 	}
 
@@ -431,15 +532,48 @@ index afe0cb3..9944a9f 100644
 "[..];
 
 		let (git, _temp_dir) = create_git();
-		let result = git.diff_tree("HEAD").unwrap();
+		let result = git.diff_tree("HEAD", &GitDiffOptions::default()).unwrap();
+		assert_eq!(result, expected);
+	}
+
+	#[test]
+	fn test_diff_tree_with_options() {
+		let expected = &"diff --git a/Test file.txt b/Test file.txt
+index afe0cb3..9944a9f 100644
+--- a/Test file.txt\t
++++ b/Test file.txt\t
+@@ -1 +1 @@
+-This is a test file - modified
+\\ No newline at end of file
++This is a test file
+\\ No newline at end of file
+"[..];
+
+		let (git, _temp_dir) = create_git();
+		let options = GitDiffOptions {
+			context_lines: 0,
+			algorithm: GitDiffAlgorithm::Histogram,
+			rename_threshold: None,
+			whitespace_mode: GitWhitespaceMode::IgnoreChanges,
+			pathspecs: vec!["Test file.txt".into()],
+			..GitDiffOptions::default()
+		};
+		let result = git.diff_tree("HEAD", &options).unwrap();
 		assert_eq!(result, expected);
 	}
 
+	#[test]
+	fn test_diff_tree_combined() {
+		let (git, _temp_dir) = create_git();
+		let result = git.diff_tree_combined("951534891c74c587db9f233763f5604724fa726f", true).unwrap();
+		assert_eq!(result, ""); // The merge is clean, so it has nothing to show
+	}
+
 	#[test]
 	fn test_apply() {
 		let (git, _temp_dir) = create_git();
 		git.read_tree(Some("refs/tags/reading-tests")).unwrap();
-		git.apply(PATCH, PatchApplicationMode::IndexOnly).unwrap();
+		git.apply(PATCH, PatchApplicationMode::IndexOnly, false).unwrap();
 
 		assert!(!git.diff_index_names("refs/tags/reading-tests").unwrap().is_empty());
 	}
@@ -2,27 +2,47 @@ use std::ffi::{OsStr, OsString};
 use std::sync::Arc;
 use std::path::{PathBuf, Path};
 use std::collections::HashMap;
+use std::fs;
 
 use failure::{self, Backtrace};
 
-use git::{self, Git, PatchApplicationMode};
-use change_set::{Commit, CombinedPatch};
-use patch_editor::parser::parse_combined_patch;
+use git::{self, Git, PatchApplicationMode, GitDiffOptions};
+use change_set::{Commit, CombinedPatch, ChangeSetInfo, PersonAction};
+use change_set::dependencies::DependencyGraph;
+use change_set::mbox::{self, parse_mbox, ExportFormat};
+use change_set::quilt::{read_series, write_series};
+use change_set::split::{split_combined_patch, Selector};
+use patch_editor::patch::CommutationError;
+use patch_editor::patch_id::{patch_id, PatchId};
+use patch_editor::parser::{parse_combined_patch, parse_combined_diffs, parse_unified_diff};
+use patch_editor::combined_diff::CombinedDiff;
 use model::{Model, View};
 
 #[derive(Clone)]
 enum Command {
 	GetBranches,
-	ImportCommits(Vec<Commit>),
+	ImportCommits(Vec<Commit>, GitDiffOptions),
+	ImportEmails(Vec<PathBuf>, usize),
+	ExportEmails(PathBuf, ExportFormat),
+	ImportSeries(PathBuf, usize),
+	ImportDiff(PathBuf, usize, usize),
+	FindDuplicates(String),
+	ExportSeries(PathBuf),
 	SetPatchMessage(usize, String),
-	MovePatch(usize, usize),
+	MovePatch(usize, usize, bool),
+	FindConflictingMoves(usize),
 	DeletePatch(usize),
+	InsertRevert(usize, usize),
+	SplitPatch(usize, Vec<Vec<Selector>>),
+	GetDependencyGraph,
+	GetMergeDiff(String, bool),
 	ApplyCommits(Commit),
 	ContinueApplication(Vec<String>),
 	ResolveConflicts,
 	AbortApplication,
 	SwitchToBranch(String),
-	UpdatePatches(Vec<CombinedPatch>)
+	UpdatePatches(Vec<CombinedPatch>),
+	SetAllowEmpty(bool)
 }
 
 #[derive(PartialEq)]
@@ -37,6 +57,9 @@ struct State {
 	combined_patches: Vec<CombinedPatch>,
 	branch_under_update: Option<String>,
 	conflicts: Vec<String>,
+	allow_empty: bool, // Whether the patches changing nothing become empty commits, like with "git commit --allow-empty", or get dropped
+	lossy_import: bool, // Whether some of the patches were imported ignoring whitespace or outside the pathspecs
+	import_diff_options: Vec<GitDiffOptions>, // The upstream commits are diffed the same ways to find the duplicates
 }
 
 #[derive(Clone)]
@@ -53,6 +76,9 @@ impl MainModel {
 				combined_patches: Vec::new(),
 				branch_under_update: None,
 				conflicts: Vec::new(),
+				allow_empty: false,
+				lossy_import: false,
+				import_diff_options: vec![GitDiffOptions::default()], // As the emails and the series usually come from format-patch
 			})
 		}, MainModel::perform_command);
 
@@ -65,50 +91,152 @@ impl MainModel {
 	}
 
 	fn perform_command(view: &impl MainViewReceiver, ref mut state: &mut State, command: Command) -> Result<(), failure::Error> {
-		fn show_combined_patches(view: &impl MainViewReceiver, combined_patches: &Vec<CombinedPatch>) -> Result<(), failure::Error> {
-			view.show_combined_patches(combined_patches.iter().cloned().collect())
-		}
-
 		match command {
 			Command::GetBranches => {
 				MainModel::get_branches_and_commits(view, state)?;
 			}
-			Command::ImportCommits(commits) => {
+			Command::ImportCommits(commits, diff_options) => {
 				let mut new_combined_patches = Vec::<CombinedPatch>::new();
 				for commit in commits {
-					let combined_patch_data = state.git.diff_tree(&commit.hash)?;
-					let patches = parse_combined_patch(combined_patch_data.as_bytes())?;
+					let combined_patch_data = state.git.diff_tree(&commit.hash, &diff_options)?;
+					let patches = if combined_patch_data.is_empty() { Vec::new() } else { parse_combined_patch(combined_patch_data.as_bytes())? };
 					let combined_patch = CombinedPatch {
 						info: commit.info.change_set_info,
+						originally_empty: patches.is_empty(),
 						patches,
+						series_entry: None,
 					};
 					new_combined_patches.push(combined_patch);
 				}
 
+				// The earlier lossy imports only stop mattering when their patches are all gone
+				state.lossy_import = diff_options.is_lossy() || (state.lossy_import && !state.combined_patches.is_empty());
+				if !state.import_diff_options.contains(&diff_options) {
+					state.import_diff_options.push(diff_options);
+				}
 				state.combined_patches.extend(new_combined_patches);
-				show_combined_patches(view, &state.combined_patches)?;
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::ImportEmails(paths, insertion_position) => {
+				check_insertion_position(&state.combined_patches, insertion_position)?;
+				let mut new_combined_patches = Vec::<CombinedPatch>::new();
+				for path in paths {
+					new_combined_patches.extend(parse_mbox(&fs::read(path)?)?);
+				}
+
+				// The emails go from the oldest to the newest, unlike the series
+				for combined_patch in new_combined_patches {
+					state.combined_patches.insert(insertion_position, combined_patch);
+				}
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::ExportEmails(path, format) => {
+				let combined_patches = state.combined_patches.iter().rev().cloned().collect::<Vec<_>>();
+				mbox::export(&combined_patches, format, path)?;
+			}
+			Command::ImportSeries(patches_dir, insertion_position) => {
+				check_insertion_position(&state.combined_patches, insertion_position)?;
+				for combined_patch in read_series(patches_dir)? { // The series goes from the first applied patch to the last one
+					state.combined_patches.insert(insertion_position, combined_patch);
+				}
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::ImportDiff(path, strip, insertion_position) => {
+				check_insertion_position(&state.combined_patches, insertion_position)?;
+				let patches = parse_unified_diff(&fs::read(&path)?, strip)?;
+				let message = path.file_stem().map_or(String::new(), |name| name.to_string_lossy().into_owned()); // The plain diffs have no description
+				let combined_patch = CombinedPatch {
+					info: ChangeSetInfo {
+						author_action: PersonAction::default(),
+						committer_action: PersonAction::default(),
+						message: message + "\n",
+					},
+					patches,
+					series_entry: None,
+					originally_empty: false,
+				};
+
+				state.combined_patches.insert(insertion_position, combined_patch);
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::FindDuplicates(upstream) => {
+				let mut upstream_patch_ids = HashMap::new();
+				for commit_hash in state.git.rev_list_excluding(&upstream, "HEAD")? {
+					for diff_options in &state.import_diff_options {
+						let diff = state.git.diff_tree(&commit_hash, diff_options)?;
+						if let Some(patch_id) = patch_id(diff.as_bytes()) {
+							upstream_patch_ids.insert(patch_id, commit_hash.clone());
+						}
+					}
+				}
+				view.show_duplicates(find_duplicates(&state.combined_patches, &upstream_patch_ids))?;
+			}
+			Command::ExportSeries(patches_dir) => {
+				let combined_patches = state.combined_patches.iter().rev().cloned().collect::<Vec<_>>();
+				write_series(&combined_patches, patches_dir)?;
 			}
 			Command::SetPatchMessage(patch_index, message) => {
 				state.combined_patches[patch_index].info.message = message;
-				show_combined_patches(view, &state.combined_patches)?;
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
 			}
-			Command::MovePatch(source_position, insertion_position) => {
+			Command::MovePatch(source_position, insertion_position, shift_hunks) => {
 				if source_position != insertion_position {
-					let mut adjusted_insertion_position = insertion_position;
-					if source_position < insertion_position {
-						adjusted_insertion_position -= 1;
-					}
+					check_patch_index(&state.combined_patches, source_position)?;
+					check_insertion_position(&state.combined_patches, insertion_position)?;
 
-					let patch = state.combined_patches.remove(source_position);
-					state.combined_patches.insert(adjusted_insertion_position, patch);
-					show_combined_patches(view, &state.combined_patches)?;
+					// The patches that don't commute are moved as they are, and the conflicts are left for the application
+					let commuted_patches = if shift_hunks { commute_moved_patch(&state.combined_patches, source_position, insertion_position).ok() } else { None };
+					match commuted_patches {
+						Some(combined_patches) => state.combined_patches = combined_patches,
+						None => {
+							let mut adjusted_insertion_position = insertion_position;
+							if source_position < insertion_position {
+								adjusted_insertion_position -= 1;
+							}
+
+							let patch = state.combined_patches.remove(source_position);
+							state.combined_patches.insert(adjusted_insertion_position, patch);
+						}
+					}
+					MainModel::show_combined_patches(view, &state.combined_patches)?;
 				}
 			}
+			Command::FindConflictingMoves(source_position) => {
+				check_patch_index(&state.combined_patches, source_position)?;
+				view.show_conflicting_moves(source_position, find_conflicting_moves(&state.combined_patches, source_position))?;
+			}
 			Command::DeletePatch(patch_index) => {
 				state.combined_patches.remove(patch_index);
-				show_combined_patches(view, &state.combined_patches)?;
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::InsertRevert(patch_index, insertion_position) => {
+				check_patch_index(&state.combined_patches, patch_index)?;
+				check_insertion_position(&state.combined_patches, insertion_position)?;
+				let mut revert = state.combined_patches[patch_index].invert()?;
+				let subject = revert.info.message.lines().next().unwrap_or("").to_owned();
+				revert.info.message = format!("Revert \"{}\"\n", subject);
+
+				state.combined_patches.insert(insertion_position, revert);
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::SplitPatch(patch_index, partitions) => {
+				check_patch_index(&state.combined_patches, patch_index)?;
+				let parts = split_combined_patch(&state.combined_patches[patch_index], &partitions)?;
+				state.combined_patches.remove(patch_index);
+				for part in parts { // The parts go from the first applied one to the last one, unlike the series
+					state.combined_patches.insert(patch_index, part);
+				}
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
+			}
+			Command::GetDependencyGraph => view.show_dependency_graph(DependencyGraph::new(&state.combined_patches))?,
+			Command::GetMergeDiff(commit_hash, dense) => {
+				let combined_diff_data = state.git.diff_tree_combined(&commit_hash, dense)?;
+				view.show_merge_diff(commit_hash, parse_combined_diffs(combined_diff_data.as_bytes())?)?;
 			}
 			Command::ApplyCommits(first_commit_to_replace) => {
+				if state.lossy_import && !state.combined_patches.is_empty() { // Writing them back would drop the changes left out
+					return Err(MainModelError::LossyImport(Backtrace::new()).into());
+				}
 				let active_branch = state.git.symbolic_ref("HEAD")?;
 				state.branch_under_update = Some(active_branch.clone());
 				let target_commit = first_commit_to_replace.info.parent;
@@ -132,10 +260,14 @@ impl MainModel {
 				state.conflicts.clear();
 				MainModel::get_branches_and_commits(view, state)?;
 			}
-			Command::UpdatePatches(patches) => {
+			Command::UpdatePatches(mut patches) => {
+				for combined_patch in &mut patches {
+					combined_patch.prune_empty_patches();
+				}
 				state.combined_patches = patches;
-				view.show_combined_patches(state.combined_patches.iter().cloned().collect())?;
+				MainModel::show_combined_patches(view, &state.combined_patches)?;
 			}
+			Command::SetAllowEmpty(allow_empty) => state.allow_empty = allow_empty,
 		}
 		Ok(())
 	}
@@ -163,6 +295,10 @@ impl MainModel {
 			commits.push(commit);
 		}
 		view.show_commits(commits)?;
+		MainModel::show_combined_patches(view, combined_patches)
+	}
+
+	fn show_combined_patches(view: &impl MainViewReceiver, combined_patches: &Vec<CombinedPatch>) -> Result<(), failure::Error> {
 		view.show_combined_patches(combined_patches.iter().cloned().collect())
 	}
 
@@ -177,15 +313,29 @@ impl MainModel {
 			if !has_unapplied_patch_in_cache {
 				state.git.read_tree(target_commit.clone())?;
 
-				let mut patch_data: Vec<u8> = Vec::new();
-				patch.write(&mut patch_data)?;
-				last_patch_application_state = MainModel::apply_patch(&state.git, &*patch_data)?;
+				if !patch.patches.is_empty() { // Git refuses to apply an empty patch
+					let mut patch_data: Vec<u8> = Vec::new();
+					patch.write(&mut patch_data)?;
+					let zero_context = patch.patches.iter().any(|patch| patch.has_hunks_without_context());
+					last_patch_application_state = MainModel::apply_patch(&state.git, &*patch_data, zero_context)?;
 
-				if last_patch_application_state != PatchApplicationState::Applied {
-					break;
+					if last_patch_application_state != PatchApplicationState::Applied {
+						break;
+					}
 				}
 			}
 
+			// The empty commits are kept, same as with rebase, only the patches the edits or the upstream changes leave empty are dropped
+			let became_empty = !patch.originally_empty && match target_commit {
+				Some(ref commit) => state.git.diff_index_names(commit)?.is_empty(),
+				None => patch.patches.is_empty()
+			};
+			if became_empty && !state.allow_empty {
+				applied_patches += 1;
+				has_unapplied_patch_in_cache = false;
+				continue;
+			}
+
 			let tree = state.git.write_tree()?;
 			let commit = state.git.commit_tree(&tree, target_commit.as_ref(), &patch.info.message)?;
 			state.git.update_ref("HEAD", &commit)?;
@@ -196,6 +346,11 @@ impl MainModel {
 		}
 
 		let patches_left = state.combined_patches.len() - applied_patches;
+		if patches_left == 0 && target_commit.is_none() { // The branch can't point to nothing, so it stays as it was, and so do the patches
+			state.git.read_tree(Some(branch_under_update))?;
+			state.branch_under_update = None;
+			return Err(MainModelError::NoCommitsLeft(Backtrace::new()).into());
+		}
 		state.combined_patches.truncate(patches_left);
 
 		if patches_left == 0 {
@@ -217,8 +372,8 @@ impl MainModel {
 		Ok(())
 	}
 
-	fn apply_patch(git: &Git, patch_data: &[u8]) -> Result<PatchApplicationState, failure::Error> {
-		let result = git.apply(patch_data, PatchApplicationMode::IndexOnly);
+	fn apply_patch(git: &Git, patch_data: &[u8], zero_context: bool) -> Result<PatchApplicationState, failure::Error> {
+		let result = git.apply(patch_data, PatchApplicationMode::IndexOnly, zero_context);
 		match result {
 			Err(ref err) if err.to_status() == Some(1) => (),
 			Err(err) => return Err(err.into()),
@@ -227,9 +382,9 @@ impl MainModel {
 
 		git.checkout_index()?; // TODO: can the index have a half-applied patch at this point?
 
-		fn apply_and_check(git: &Git, patch_data: &[u8], use_3_way: bool) -> Result<(), failure::Error> {
+		fn apply_and_check(git: &Git, patch_data: &[u8], zero_context: bool, use_3_way: bool) -> Result<(), failure::Error> {
 			let mode = if use_3_way { PatchApplicationMode::WorkingDirectory3Way } else { PatchApplicationMode::WorkingDirectoryWithRejects };
-			let result = git.apply(patch_data, mode);
+			let result = git.apply(patch_data, mode, zero_context);
 			match result {
 				Err(ref err) if err.to_status() == Some(1) => Ok(()),
 				Err(err) => Err(err.into()),
@@ -238,11 +393,11 @@ impl MainModel {
 			}
 		}
 
-		apply_and_check(git, patch_data, true)?;
+		apply_and_check(git, patch_data, zero_context, true)?;
 
 		let conflicts = git.status_conflicts()?;
 		if conflicts.is_empty() { // 3-way merge didn't work, let's try to edit rejects
-			apply_and_check(git, patch_data, false)?;
+			apply_and_check(git, patch_data, zero_context, false)?;
 			Ok(PatchApplicationState::Rejects)
 		} else {
 			Ok(PatchApplicationState::Conflicts(conflicts))
@@ -275,22 +430,70 @@ impl MainModel {
 		Ok(())
 	}
 
-	pub fn import_commits(&self, commits: Vec<Commit>) {
-		self.base.send(Command::ImportCommits(commits));
+	pub fn import_commits(&self, commits: Vec<Commit>, diff_options: GitDiffOptions) {
+		self.base.send(Command::ImportCommits(commits, diff_options));
+	}
+
+	pub fn import_emails(&self, paths: Vec<PathBuf>, insertion_position: usize) {
+		self.base.send(Command::ImportEmails(paths, insertion_position));
+	}
+
+	pub fn export_emails(&self, path: PathBuf, format: ExportFormat) {
+		self.base.send(Command::ExportEmails(path, format));
+	}
+
+	pub fn import_series(&self, patches_dir: PathBuf, insertion_position: usize) {
+		self.base.send(Command::ImportSeries(patches_dir, insertion_position));
+	}
+
+	pub fn import_diff(&self, path: PathBuf, strip: usize, insertion_position: usize) {
+		self.base.send(Command::ImportDiff(path, strip, insertion_position));
+	}
+
+	// Like rebase skipping the cherry-picked commits, finds the patches already in the upstream branch or earlier in the series
+	pub fn find_duplicates(&self, upstream: &str) {
+		self.base.send(Command::FindDuplicates(String::from(upstream)));
+	}
+
+	pub fn export_series(&self, patches_dir: PathBuf) {
+		self.base.send(Command::ExportSeries(patches_dir));
 	}
 
 	pub fn set_patch_message(&self, patch_index: usize, message: String) {
 		self.base.send(Command::SetPatchMessage(patch_index, message));
 	}
 
-	pub fn move_patch(&self, source_position: usize, insertion_position: usize) {
-		self.base.send(Command::MovePatch(source_position, insertion_position));
+	// Shifting the hunks of the patches the moved one passes makes them apply exactly, if the patches commute
+	pub fn move_patch(&self, source_position: usize, insertion_position: usize, shift_hunks: bool) {
+		self.base.send(Command::MovePatch(source_position, insertion_position, shift_hunks));
+	}
+
+	pub fn find_conflicting_moves(&self, source_position: usize) {
+		self.base.send(Command::FindConflictingMoves(source_position));
 	}
 
 	pub fn delete(&self, patch_index: usize) {
 		self.base.send(Command::DeletePatch(patch_index));
 	}
 
+	pub fn insert_revert(&self, patch_index: usize, insertion_position: usize) {
+		self.base.send(Command::InsertRevert(patch_index, insertion_position));
+	}
+
+	// Each partition selects the changes of one new patch, in the order they are applied. The rest of the changes go last.
+	pub fn split_patch(&self, patch_index: usize, partitions: Vec<Vec<Selector>>) {
+		self.base.send(Command::SplitPatch(patch_index, partitions));
+	}
+
+	pub fn request_dependency_graph(&self) {
+		self.base.send(Command::GetDependencyGraph);
+	}
+
+	// The dense diff only has the hunks that differ from every parent, e.g. the conflict resolutions
+	pub fn request_merge_diff(&self, commit_hash: &str, dense: bool) {
+		self.base.send(Command::GetMergeDiff(String::from(commit_hash), dense));
+	}
+
 	pub fn apply_patches(&self, first_commit_to_replace: Commit) {
 		self.base.send(Command::ApplyCommits(first_commit_to_replace));
 	}
@@ -311,6 +514,10 @@ impl MainModel {
 		self.base.send(Command::SwitchToBranch(String::from(ref_name)));
 	}
 
+	pub fn set_allow_empty(&self, allow_empty: bool) {
+		self.base.send(Command::SetAllowEmpty(allow_empty));
+	}
+
 	pub fn update_patches(&self, patches: Vec<CombinedPatch>) {
 		self.base.send(Command::UpdatePatches(patches));
 	}
@@ -320,20 +527,106 @@ impl MainModel {
 	}
 }
 
+// The patches are stored newest first, so moving a patch up means swapping it with the patches applied after it
+fn commute_moved_patch(combined_patches: &[CombinedPatch], source_position: usize, insertion_position: usize) -> Result<Vec<CombinedPatch>, CommutationError> {
+	let mut new_combined_patches = combined_patches.to_vec();
+	let mut position = source_position;
+	while position > insertion_position {
+		let (newer_patch, moved_patch) = new_combined_patches[position].commute(&new_combined_patches[position - 1])?;
+		new_combined_patches[position] = newer_patch;
+		new_combined_patches[position - 1] = moved_patch;
+		position -= 1;
+	}
+	while position + 1 < insertion_position {
+		let (moved_patch, older_patch) = new_combined_patches[position + 1].commute(&new_combined_patches[position])?;
+		new_combined_patches[position] = older_patch;
+		new_combined_patches[position + 1] = moved_patch;
+		position += 1;
+	}
+	Ok(new_combined_patches)
+}
+
+// Tells which insertion positions would make the patch at the source position conflict with the others
+fn find_conflicting_moves(combined_patches: &[CombinedPatch], source_position: usize) -> Vec<bool> {
+	let mut conflicting_moves = vec![false; combined_patches.len() + 1];
+
+	let mut moved_patch = Some(combined_patches[source_position].clone());
+	for position in (0..source_position).rev() {
+		moved_patch = moved_patch
+				.and_then(|patch| patch.commute(&combined_patches[position]).ok())
+				.map(|(_, moved_patch)| moved_patch);
+		conflicting_moves[position] = moved_patch.is_none();
+	}
+
+	let mut moved_patch = Some(combined_patches[source_position].clone());
+	for position in source_position + 1..combined_patches.len() {
+		moved_patch = moved_patch
+				.and_then(|patch| combined_patches[position].commute(&patch).ok())
+				.map(|(moved_patch, _)| moved_patch);
+		conflicting_moves[position + 1] = moved_patch.is_none();
+	}
+
+	conflicting_moves
+}
+
+// The patches are stored newest first, so a duplicate within the series refers to a later position
+fn find_duplicates(combined_patches: &[CombinedPatch], upstream_patch_ids: &HashMap<PatchId, String>) -> Vec<Option<Duplicate>> {
+	let patch_ids = combined_patches.iter().map(|combined_patch| combined_patch.patch_id()).collect::<Vec<_>>();
+	patch_ids
+			.iter()
+			.enumerate()
+			.map(|(position, patch_id)| {
+				let patch_id = (*patch_id)?;
+				if let Some(commit_hash) = upstream_patch_ids.get(&patch_id) {
+					return Some(Duplicate::Upstream(commit_hash.clone()));
+				}
+				patch_ids.iter().rposition(|other_patch_id| *other_patch_id == Some(patch_id))
+						.filter(|original_position| *original_position > position)
+						.map(Duplicate::InSeries)
+			})
+			.collect()
+}
+
 #[derive(Fail, Debug)]
 enum MainModelError {
 	#[fail(display = "Error when applying patches: {}", _0)]
 	ApplyPatchesError(String, Backtrace),
+	#[fail(display = "There's no patch at the position {}", _0)]
+	InvalidPatchIndex(usize, Backtrace),
+	#[fail(display = "Can't insert a patch at the position {}", _0)]
+	InvalidInsertionPosition(usize, Backtrace),
+	#[fail(display = "The patches imported ignoring whitespace or some of the files can't replace the commits")]
+	LossyImport(Backtrace),
+	#[fail(display = "All the patches from the root commit are empty and would be dropped, leaving no commits for the branch")]
+	NoCommitsLeft(Backtrace),
+}
+
+fn check_patch_index(combined_patches: &[CombinedPatch], patch_index: usize) -> Result<(), MainModelError> {
+	if patch_index < combined_patches.len() { Ok(()) } else { Err(MainModelError::InvalidPatchIndex(patch_index, Backtrace::new())) }
+}
+
+fn check_insertion_position(combined_patches: &[CombinedPatch], insertion_position: usize) -> Result<(), MainModelError> {
+	if insertion_position <= combined_patches.len() { Ok(()) } else { Err(MainModelError::InvalidInsertionPosition(insertion_position, Backtrace::new())) }
 }
 
 pub trait MainViewReceiver: View {
 	fn show_branches(&self, branches: Vec<BranchItem>) -> Result<(), failure::Error>;
 	fn show_commits(&self, commits: Vec<Commit>) -> Result<(), failure::Error>;
 	fn show_combined_patches(&self, combined_patches: Vec<CombinedPatch>) -> Result<(), failure::Error>;
+	fn show_conflicting_moves(&self, source_position: usize, conflicting_moves: Vec<bool>) -> Result<(), failure::Error>; // One per insertion position
+	fn show_dependency_graph(&self, dependency_graph: DependencyGraph) -> Result<(), failure::Error>;
+	fn show_merge_diff(&self, commit_hash: String, combined_diffs: Vec<CombinedDiff>) -> Result<(), failure::Error>; // CombinedDiff::parent_hunks gives the changes against each parent
+	fn show_duplicates(&self, duplicates: Vec<Option<Duplicate>>) -> Result<(), failure::Error>; // One per combined patch
 	fn resolve_rejects(&self) -> Result<(), failure::Error>;
 	fn notify_conflicts(&self) -> Result<(), failure::Error>;
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Duplicate {
+	Upstream(String), // The hash of the upstream commit with the same changes
+	InSeries(usize), // The position of the patch with the same changes applied earlier
+}
+
 pub enum BranchItem {
 	Folder {
 		display_name: String,
@@ -400,4 +693,255 @@ impl BranchItem {
 		branch_items.extend(branches.into_iter());
 		branch_items
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::process;
+	use std::sync::Mutex;
+	use tempdir::TempDir;
+	use patch_editor::patch::HunkLineKind;
+
+	const ADD_F: &[u8] = b"diff --git a/f.txt b/f.txt
+new file mode 100644
+index 0000000..4d1ae35
+--- /dev/null
++++ b/f.txt
+@@ -0,0 +1,2 @@
++f 1
++f 2
+";
+
+	const EDIT_F: &[u8] = b"diff --git a/f.txt b/f.txt
+index 4d1ae35..71d8a6e 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,2 +1,2 @@
+ f 1
+-f 2
++f two
+";
+
+	#[derive(Default)]
+	struct TestView {
+		merge_diffs: Mutex<Vec<CombinedDiff>>,
+		duplicates: Mutex<Vec<Option<Duplicate>>>,
+	}
+
+	impl View for TestView {
+		fn error(&self, error: failure::Error) {
+			panic!("Unexpected error: {}", error);
+		}
+	}
+
+	impl MainViewReceiver for TestView {
+		fn show_branches(&self, _: Vec<BranchItem>) -> Result<(), failure::Error> { Ok(()) }
+		fn show_commits(&self, _: Vec<Commit>) -> Result<(), failure::Error> { Ok(()) }
+		fn show_combined_patches(&self, _: Vec<CombinedPatch>) -> Result<(), failure::Error> { Ok(()) }
+		fn show_conflicting_moves(&self, _: usize, _: Vec<bool>) -> Result<(), failure::Error> { Ok(()) }
+		fn show_dependency_graph(&self, _: DependencyGraph) -> Result<(), failure::Error> { Ok(()) }
+		fn show_merge_diff(&self, _: String, combined_diffs: Vec<CombinedDiff>) -> Result<(), failure::Error> {
+			*self.merge_diffs.lock().unwrap() = combined_diffs;
+			Ok(())
+		}
+		fn show_duplicates(&self, duplicates: Vec<Option<Duplicate>>) -> Result<(), failure::Error> {
+			*self.duplicates.lock().unwrap() = duplicates;
+			Ok(())
+		}
+		fn resolve_rejects(&self) -> Result<(), failure::Error> { Ok(()) }
+		fn notify_conflicts(&self) -> Result<(), failure::Error> { Ok(()) }
+	}
+
+	// The branch has the root commit adding a file, an edit of it and an empty commit, the newest commit first
+	fn create_state() -> (State, Vec<Commit>, TempDir) {
+		let temp_dir = TempDir::new("begitter").unwrap();
+		let run_git = |args: &[&str]| assert!(process::Command::new("git").arg("-C").arg(temp_dir.path()).args(args).status().unwrap().success());
+		run_git(&["init", "--quiet"]);
+		run_git(&["config", "user.name", "Someone"]);
+		run_git(&["config", "user.email", "someone@example.com"]);
+
+		let git = Git::new(temp_dir.path());
+		let mut parent: Option<String> = None;
+		let mut commits = Vec::new();
+		for &(patch_data, message) in [(ADD_F, "Add f\n"), (EDIT_F, "Edit f\n"), (&b""[..], "Do nothing\n")].iter() {
+			git.read_tree(parent.as_ref()).unwrap();
+			if !patch_data.is_empty() {
+				git.apply(patch_data, PatchApplicationMode::IndexOnly, false).unwrap();
+			}
+			let tree = git.write_tree().unwrap();
+			let hash = git.commit_tree(&tree, parent.as_ref(), message).unwrap();
+			commits.insert(0, Commit::from_data(hash.clone(), git.cat_file(&hash).unwrap().as_bytes()).unwrap());
+			parent = Some(hash);
+		}
+		git.update_ref("refs/heads/test-branch", parent.as_ref().unwrap()).unwrap();
+		git.symbolic_ref_update("HEAD", "refs/heads/test-branch").unwrap();
+
+		let state = State {
+			git,
+			combined_patches: Vec::new(),
+			branch_under_update: None,
+			conflicts: Vec::new(),
+			allow_empty: false,
+			lossy_import: false,
+			import_diff_options: vec![GitDiffOptions::default()],
+		};
+		(state, commits, temp_dir)
+	}
+
+	// Imports the commits and moves all the changes out of the oldest one
+	fn import_and_empty(state: &mut State, commits: &[Commit]) {
+		MainModel::perform_command(&TestView::default(), state, Command::ImportCommits(commits.to_vec(), GitDiffOptions::default())).unwrap();
+		let mut combined_patches = state.combined_patches.clone();
+		combined_patches.last_mut().unwrap().patches.clear();
+		MainModel::perform_command(&TestView::default(), state, Command::UpdatePatches(combined_patches)).unwrap();
+	}
+
+	fn branch_commits(git: &Git) -> Vec<Commit> {
+		let mut commits = Vec::new();
+		let mut hash = Some(git.show_ref("refs/heads/test-branch").unwrap());
+		while let Some(commit_hash) = hash {
+			let commit = Commit::from_data(commit_hash.clone(), git.cat_file(&commit_hash).unwrap().as_bytes()).unwrap();
+			hash = commit.info.parent.clone();
+			commits.push(commit);
+		}
+		commits
+	}
+
+	fn messages(commits: &[Commit]) -> Vec<&str> {
+		commits.iter().map(|commit| commit.info.change_set_info.message.as_str()).collect()
+	}
+
+	#[test]
+	fn test_import_emails_at_invalid_position() {
+		let (mut state, _, temp_dir) = create_state();
+		let path = temp_dir.path().join("add-g.patch");
+		fs::write(&path, "From: Someone <someone@example.com>\nDate: Tue, 9 Oct 2018 12:34:56 +0300\nSubject: [PATCH] Add g\n\n\
+			diff --git a/g.txt b/g.txt\nnew file mode 100644\nindex 0000000..01e79c3\n--- /dev/null\n+++ b/g.txt\n@@ -0,0 +1 @@\n+g\n").unwrap();
+
+		match MainModel::perform_command(&TestView::default(), &mut state, Command::ImportEmails(vec![path.clone()], 1)) {
+			Err(err) => match err.downcast::<MainModelError>() {
+				Ok(MainModelError::InvalidInsertionPosition(1, _)) => (),
+				result => panic!("Unexpected result: {:?}", result)
+			},
+			Ok(()) => panic!("There's no position 1 to insert at")
+		}
+		assert!(state.combined_patches.is_empty());
+
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ImportEmails(vec![path], 0)).unwrap();
+		assert_eq!(state.combined_patches[0].info.message, "Add g\n");
+	}
+
+	#[test]
+	fn test_split_patch_at_invalid_index() {
+		let (mut state, commits, _temp_dir) = create_state();
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ImportCommits(commits[1..2].to_vec(), GitDiffOptions::default())).unwrap();
+
+		match MainModel::perform_command(&TestView::default(), &mut state, Command::SplitPatch(1, vec![vec![Selector::File(0)]])) {
+			Err(err) => match err.downcast::<MainModelError>() {
+				Ok(MainModelError::InvalidPatchIndex(1, _)) => (),
+				result => panic!("Unexpected result: {:?}", result)
+			},
+			Ok(()) => panic!("There's no patch to split")
+		}
+		assert_eq!(state.combined_patches.len(), 1);
+	}
+
+	#[test]
+	fn test_move_dependent_patch() {
+		let (mut state, commits, _temp_dir) = create_state();
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ImportCommits(commits[1..].to_vec(), GitDiffOptions::default())).unwrap();
+
+		// The edit depends on the addition, so there's nothing to shift, but the patch is moved anyway
+		MainModel::perform_command(&TestView::default(), &mut state, Command::MovePatch(1, 0, true)).unwrap();
+		assert_eq!(state.combined_patches.iter().map(|patch| patch.info.message.as_str()).collect::<Vec<_>>(), vec!["Add f\n", "Edit f\n"]);
+		assert_eq!(find_conflicting_moves(&state.combined_patches, 1), vec![true, false, false]);
+	}
+
+	#[test]
+	fn test_merge_diff() {
+		let (mut state, commits, temp_dir) = create_state();
+
+		// The other branch adds g.txt, and the merge changes f.txt on top of taking both sides
+		state.git.read_tree(Some(&commits[2].hash)).unwrap();
+		state.git.apply(b"diff --git a/g.txt b/g.txt\nnew file mode 100644\nindex 0000000..01e79c3\n--- /dev/null\n+++ b/g.txt\n@@ -0,0 +1 @@\n+g\n",
+			PatchApplicationMode::IndexOnly, false).unwrap();
+		let other_commit = state.git.commit_tree(&state.git.write_tree().unwrap(), Some(&commits[2].hash), "Add g\n").unwrap();
+		state.git.apply(b"diff --git a/f.txt b/f.txt\nindex 4d1ae35..93f6e5e 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,2 @@\n-f 1\n+f one\n f 2\n",
+			PatchApplicationMode::IndexOnly, false).unwrap();
+		state.git.apply(b"diff --git a/f.txt b/f.txt\nindex 93f6e5e..e23a9ad 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,2 @@\n f one\n-f 2\n+f two\n",
+			PatchApplicationMode::IndexOnly, false).unwrap();
+		let output = process::Command::new("git").arg("-C").arg(temp_dir.path())
+				.args(&["commit-tree", &state.git.write_tree().unwrap(), "-p", &commits[1].hash, "-p", &other_commit, "-m", "Merge"])
+				.output().unwrap();
+		let merge_commit = String::from_utf8(output.stdout).unwrap().trim().to_owned();
+
+		let view = TestView::default();
+		MainModel::perform_command(&view, &mut state, Command::GetMergeDiff(merge_commit, false)).unwrap();
+		let merge_diffs = view.merge_diffs.lock().unwrap();
+		assert_eq!(merge_diffs.len(), 1);
+		assert_eq!(merge_diffs[0].name, "f.txt");
+		assert_eq!(merge_diffs[0].parent_count(), 2);
+		assert_eq!(merge_diffs[0].parent_hunks(0).len(), 1);
+		assert_eq!(merge_diffs[0].parent_hunks(0)[0].lines.iter().filter(|line| line.kind != HunkLineKind::Context).count(), 2);
+		assert_eq!(merge_diffs[0].parent_hunks(1)[0].lines.iter().filter(|line| line.kind != HunkLineKind::Context).count(), 4);
+	}
+
+	#[test]
+	fn test_find_duplicates_without_context() {
+		let (mut state, commits, _temp_dir) = create_state();
+		let upstream_commit = state.git.commit_tree(&commits[1].info.tree, Some(&commits[2].hash), "Edit f upstream\n").unwrap();
+		state.git.update_ref("refs/heads/upstream", &upstream_commit).unwrap();
+		state.git.update_ref("refs/heads/test-branch", &commits[2].hash).unwrap();
+
+		let diff_options = GitDiffOptions { context_lines: 0, ..GitDiffOptions::default() };
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ImportCommits(commits[1..2].to_vec(), diff_options)).unwrap();
+
+		let view = TestView::default();
+		MainModel::perform_command(&view, &mut state, Command::FindDuplicates("refs/heads/upstream".into())).unwrap();
+		assert_eq!(*view.duplicates.lock().unwrap(), vec![Some(Duplicate::Upstream(upstream_commit))]);
+	}
+
+	#[test]
+	fn test_apply_dropping_emptied_patches() {
+		let (mut state, commits, _temp_dir) = create_state();
+		import_and_empty(&mut state, &commits[..2]);
+		assert!(state.combined_patches[0].originally_empty);
+
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ApplyCommits(commits[1].clone())).unwrap();
+		let branch_commits = branch_commits(&state.git);
+		assert_eq!(messages(&branch_commits), vec!["Do nothing\n", "Add f\n"]);
+		assert_eq!(branch_commits[0].info.tree, commits[2].info.tree);
+		assert_eq!(state.git.symbolic_ref("HEAD").unwrap(), "refs/heads/test-branch");
+	}
+
+	#[test]
+	fn test_apply_keeping_emptied_patches() {
+		let (mut state, commits, _temp_dir) = create_state();
+		MainModel::perform_command(&TestView::default(), &mut state, Command::SetAllowEmpty(true)).unwrap();
+		import_and_empty(&mut state, &commits[..2]);
+
+		MainModel::perform_command(&TestView::default(), &mut state, Command::ApplyCommits(commits[1].clone())).unwrap();
+		let branch_commits = branch_commits(&state.git);
+		assert_eq!(messages(&branch_commits), vec!["Do nothing\n", "Edit f\n", "Add f\n"]);
+		assert_eq!(branch_commits[1].info.tree, commits[2].info.tree);
+	}
+
+	#[test]
+	fn test_apply_dropping_all_patches_from_root() {
+		let (mut state, commits, _temp_dir) = create_state();
+		let branch_commit = state.git.show_ref("refs/heads/test-branch").unwrap();
+		import_and_empty(&mut state, &commits[2..]);
+
+		match MainModel::perform_command(&TestView::default(), &mut state, Command::ApplyCommits(commits[2].clone())) {
+			Err(err) => match err.downcast::<MainModelError>() {
+				Ok(MainModelError::NoCommitsLeft(_)) => (),
+				result => panic!("Unexpected result: {:?}", result)
+			},
+			Ok(()) => panic!("The branch has no commits left")
+		}
+		assert_eq!(state.git.show_ref("refs/heads/test-branch").unwrap(), branch_commit);
+		assert_eq!(state.git.symbolic_ref("HEAD").unwrap(), "refs/heads/test-branch");
+		assert_eq!(state.combined_patches.len(), 1);
+	}
+}
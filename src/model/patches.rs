@@ -1,11 +1,10 @@
-use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 
 use uuid::{Uuid, UuidVersion};
 use failure;
 
 use model::View;
-use patch_editor::patch::OverlappingHunkError;
+use patch_editor::patch::{Patch, Change, ModificationType, FileProperties, OverlappingHunkError};
 use change_set::CombinedPatch;
 
 macro_rules! check_presence {
@@ -22,6 +21,13 @@ pub enum TargetSide {
 	Right,
 }
 
+// In the order the patches are applied
+#[derive(Copy, Clone)]
+pub enum Placement {
+	Before(Uuid),
+	After(Uuid),
+}
+
 #[derive(Copy, Clone)]
 struct Side {
 	selected_combined_patch: Option<Uuid>,
@@ -38,7 +44,7 @@ impl Default for Side {
 }
 
 pub struct PatchesModel<T: PatchesViewReceiver> {
-	patches: HashMap<Uuid, CombinedPatch>,
+	patches: Vec<(Uuid, CombinedPatch)>, // In the order of the main model, the newest patch first. The IDs stay the same while the patches change.
 	left: Side,
 	right: Side,
 
@@ -47,12 +53,12 @@ pub struct PatchesModel<T: PatchesViewReceiver> {
 
 impl<T: PatchesViewReceiver> PatchesModel<T> {
 	pub fn new(view: T, patches: Vec<CombinedPatch>) -> PatchesModel<T> {
-		let patches_map = patches.into_iter()
+		let patches = patches.into_iter()
 				.map(|patch| (Uuid::new(UuidVersion::Random).unwrap(), patch))
 				.collect();
 
 		let mut model = PatchesModel {
-			patches: patches_map,
+			patches,
 			left: Side::default(),
 			right: Side::default(),
 			view,
@@ -61,9 +67,9 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 		{
 			let mut iter = model.patches.iter().take(2);
 
-			fn side_from_iter<'a, 'b>(side: &mut Side, iter: &mut impl Iterator<Item=(&'a Uuid, &'b CombinedPatch)>) {
-				if let Some((uuid, patch)) = iter.next() {
-					side.selected_combined_patch = Some(*uuid);
+			fn side_from_iter<'a>(side: &mut Side, iter: &mut impl Iterator<Item=&'a (Uuid, CombinedPatch)>) {
+				if let Some(&(uuid, ref patch)) = iter.next() {
+					side.selected_combined_patch = Some(uuid);
 					if patch.patches.len() > 0 {
 						side.selected_patch = Some(0);
 					}
@@ -85,6 +91,26 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 		self.patches.into_iter().map(|(_, patch)| patch).collect()
 	}
 
+	fn position(&self, id: Uuid) -> usize {
+		self.patches.iter().position(|&(patch_id, _)| patch_id == id).unwrap()
+	}
+
+	fn combined_patch(&self, id: Uuid) -> &CombinedPatch {
+		&self.patches[self.position(id)].1
+	}
+
+	fn combined_patch_mut(&mut self, id: Uuid) -> &mut CombinedPatch {
+		let position = self.position(id);
+		&mut self.patches[position].1
+	}
+
+	pub fn selected_combined_patch(&self, target_side: TargetSide) -> Option<Uuid> {
+		match target_side {
+			TargetSide::Left => self.left.selected_combined_patch,
+			TargetSide::Right => self.right.selected_combined_patch
+		}
+	}
+
 	fn get_sides_by_target_side(&self, direction: TargetSide) -> (Side, Side) {
 		match direction {
 			TargetSide::Left => (self.right, self.left),
@@ -104,7 +130,7 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 			if side.selected_combined_patch == other_side.selected_combined_patch {
 				let other_patch = self.patches
 						.iter()
-						.map(|(&uuid, _)| uuid)
+						.map(|&(uuid, _)| uuid)
 						.find(|&uuid| uuid != side.selected_combined_patch.unwrap());
 				other_side.selected_combined_patch = other_patch;
 				other_side.selected_patch = None;
@@ -127,13 +153,7 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 	}
 
 	fn show_patches(&self) -> Result<(), failure::Error> {
-		let mut entries = self.patches
-				.iter()
-				.map(|(&uuid, patch)| (uuid, patch.clone()))
-				.collect::<Vec<_>>();
-		entries.sort_by_key(|&(id, _)| id);
-
-		self.view.view_combined_patches(entries, self.left.selected_combined_patch, self.right.selected_combined_patch)?;
+		self.view.view_combined_patches(self.patches.clone(), self.left.selected_combined_patch, self.right.selected_combined_patch)?;
 		self.view.view_patches(self.left.selected_combined_patch, TargetSide::Left)?;
 		self.view.view_patches(self.right.selected_combined_patch, TargetSide::Right)?;
 
@@ -156,24 +176,20 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 		let (Side { selected_combined_patch: source, .. }, Side { selected_combined_patch: destination, .. }) = self.get_sides_by_target_side(direction);
 		check_presence!(source, destination);
 
-		let source_patch = self.patches.remove(&source).unwrap();
-
-		let result = {
-			let destination_patch = self.patches.get_mut(&destination).unwrap();
-			destination_patch.absorb(source_patch)
-		};
+		// The source is only removed once all its changes are in the destination
+		let source_patch = self.combined_patch(source).clone();
+		let result = self.combined_patch_mut(destination).absorb(source_patch);
 
 		match result {
-			Err(err) => {
-				let (err, original_patch) = err.into_patch();
-
-				if let Some(combined_patch) = original_patch {
-					self.patches.insert(source, combined_patch);
+			Ok(()) => {
+				let position = self.position(source);
+				self.patches.remove(position);
+				match direction {
+					TargetSide::Left => self.right = Side::default(),
+					TargetSide::Right => self.left = Side::default()
 				}
-
-				self.view.error(err.into());
 			}
-			_ => ()
+			Err(err) => self.view.error(err.into_patch().0.into())
 		}
 
 		self.show_patches()?;
@@ -185,14 +201,12 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 		let (Side { selected_combined_patch: source, .. }, Side { selected_combined_patch: destination, .. }) = self.get_sides_by_target_side(direction);
 		check_presence!(source, destination);
 
-		let mut source_patch = self.patches.remove(&source).unwrap();
+		let source_position = self.position(source);
+		let (_, mut source_patch) = self.patches.remove(source_position);
 
-		let result = {
-			let destination_patch = self.patches.get_mut(&destination).unwrap();
-			source_patch.move_patches_to(patch_positions, destination_patch)
-		};
+		let result = source_patch.move_patches_to(patch_positions, self.combined_patch_mut(destination));
 
-		self.patches.insert(source, source_patch);
+		self.patches.insert(source_position, (source, source_patch));
 		match result {
 			Err(err) => self.view.error(err.into()),
 			_ => ()
@@ -208,62 +222,160 @@ impl<T: PatchesViewReceiver> PatchesModel<T> {
 			Side { selected_combined_patch: destination_id, .. }) = self.get_sides_by_target_side(direction);
 		check_presence!(source_id, source_patch_pos, destination_id);
 
-		let mut source_combined_patch = self.patches.remove(&source_id).unwrap();
-		let result = {
-			let source_patch = &mut source_combined_patch.patches[source_patch_pos];
-			let found_destination_patch = {
-				let source_patch_file_name = match source_patch.get_edit_patch_file_name() {
-					Some(name) => name,
-					None => return Err(HunkTransferringError::SourcePatchIsNotModification.into())
-				};
-
-				let destination_combined_patch = self.patches.get_mut(&destination_id).unwrap();
-				destination_combined_patch.patches.iter_mut().find(|patch| {
-					patch.get_edit_patch_file_name().map_or(false, |name| name == source_patch_file_name)
-				})
-			};
+		let (properties, changing_patches) = {
+			let source_patch = &self.combined_patch(source_id).patches[source_patch_pos];
+			if !source_patch.has_movable_hunks() {
+				return Err(HunkTransferringError::SourcePatchIsNotModification.into());
+			}
+			self.trace_file(source_id, source_patch, destination_id).ok_or(HunkTransferringError::DestinationPatchNotFoundOrNotModification)?
+		};
 
-			let destination_patch = match found_destination_patch {
-				Some(patch) => patch,
-				None => return Err(HunkTransferringError::DestinationPatchNotFoundOrNotModification.into())
-			};
+		let destination_patch_pos = match changing_patches.last() {
+			Some(&(id, position)) if id == destination_id => {
+				match self.combined_patch(id).patches[position].change {
+					Change::Modification { .. } => Some(position),
+					_ => return Err(HunkTransferringError::DestinationPatchNotFoundOrNotModification.into())
+				}
+			}
+			_ => None
+		};
 
-			source_patch.move_hunks_to(&hunks.collect::<Vec<_>>(), destination_patch) // TODO: can't we muff the original patch here? Check it
+		let source_position = self.position(source_id);
+		let (_, mut source_combined_patch) = self.patches.remove(source_position);
+		let result = {
+			let source_patch = &mut source_combined_patch.patches[source_patch_pos];
+			let destination_combined_patch = self.combined_patch_mut(destination_id);
+			let hunks = hunks.collect::<Vec<_>>();
+
+			match destination_patch_pos {
+				Some(position) => source_patch.move_hunks_to(&hunks, &mut destination_combined_patch.patches[position]),
+				None => { // The destination didn't change the file before
+					let mut destination_patch = Patch {
+						change: Change::Modification {
+							modification_type: ModificationType::Edited,
+							old_properties: properties.clone(),
+							new_properties: properties,
+						},
+						hunks: Vec::new(),
+					};
+					source_patch.move_hunks_to(&hunks, &mut destination_patch)
+							.map(|_| destination_combined_patch.patches.push(destination_patch))
+				}
+			}
 		};
-		self.patches.insert(source_id, source_combined_patch);
+		if result.is_ok() {
+			source_combined_patch.patches[source_patch_pos].remove_all_indexes();
+		}
+		self.patches.insert(source_position, (source_id, source_combined_patch));
 
 		result?;
+		// The file is different now for every patch between the source and the destination
+		for (id, position) in changing_patches {
+			self.combined_patch_mut(id).patches[position].remove_all_indexes();
+		}
+		self.prune_empty_patches(source_id);
 		self.show_patches()?;
 
 		Ok(())
 	}
 
+	// Follows the file through the patches between the source and the destination, as they can rename it or change its mode.
+	// Returns the file as the destination sees it, and the patches changing it on the way, the one of the destination last.
+	fn trace_file(&self, source_id: Uuid, source_patch: &Patch, destination_id: Uuid) -> Option<(FileProperties, Vec<(Uuid, usize)>)> {
+		let source_position = self.position(source_id);
+		let destination_position = self.position(destination_id);
+		let forward = destination_position < source_position; // The destination is applied after the source
+
+		let (mut properties, combined_patches) = if forward {
+			(source_patch.new_properties()?.clone(), self.patches[destination_position..source_position].iter().rev().collect::<Vec<_>>())
+		} else {
+			(source_patch.old_properties()?.clone(), self.patches[source_position + 1..destination_position + 1].iter().collect())
+		};
+
+		let mut changing_patches = Vec::new();
+		for &(id, ref combined_patch) in combined_patches {
+			let position = if forward { combined_patch.find_patch_reading(&properties.name) } else { combined_patch.find_patch_producing(&properties.name) };
+			if let Some(position) = position {
+				changing_patches.push((id, position));
+				if id == destination_id { break; }
+
+				let patch = &combined_patch.patches[position];
+				if !forward && patch.is_copy() { return None; } // The file didn't exist before the copy
+				properties = if forward { patch.new_properties() } else { patch.old_properties() }?.clone();
+			}
+		}
+
+		properties.index = None;
+		Some((properties, changing_patches))
+	}
+
 	pub fn delete_hunks(&mut self, target_side: TargetSide, hunks: impl Iterator<Item=usize>) -> Result<(), failure::Error> {
 		let (_, Side { selected_combined_patch: id, selected_patch: patch_pos }) = self.get_sides_by_target_side(target_side);
 		check_presence!(id, patch_pos);
 
 		{
-			let patches = &mut self.patches.get_mut(&id).unwrap().patches[patch_pos];
+			let patches = &mut self.combined_patch_mut(id).patches[patch_pos];
 			patches.remove_hunks(&hunks.collect::<Vec<usize>>());
 		}
 
-		self.show_hunks(target_side)?;
+		if self.prune_empty_patches(id) {
+			self.show_patches()?;
+		} else {
+			self.show_hunks(target_side)?;
+		}
 
 		Ok(())
 	}
 
-	pub fn new_patch(&mut self) -> Result<(), failure::Error> {
-		if let Some(ref id) = self.left.selected_combined_patch {
-			let info = self.patches[id].info.clone();
-			self.patches.insert(Uuid::new(UuidVersion::Random).unwrap(), CombinedPatch {
-				info,
-				patches: Vec::new()
-			});
+	// Drops the file patches that do nothing anymore, and the selection of them. Returns whether any were dropped.
+	fn prune_empty_patches(&mut self, id: Uuid) -> bool {
+		let pruned = {
+			let combined_patch = self.combined_patch_mut(id);
+			let patch_count = combined_patch.patches.len();
+			combined_patch.prune_empty_patches();
+			combined_patch.patches.len() != patch_count
+		};
 
-			self.show_patches()?;
+		if pruned {
+			for side in [&mut self.left, &mut self.right].iter_mut() {
+				if side.selected_combined_patch == Some(id) {
+					side.selected_patch = None;
+				}
+			}
 		}
+		pruned
+	}
 
-		Ok(())
+	// The new patch takes the description of its neighbour, e.g. of the patch it splits
+	pub fn new_patch(&mut self, placement: Placement) -> Result<Uuid, failure::Error> {
+		let (neighbour_id, offset) = match placement {
+			Placement::Before(id) => (id, 1),
+			Placement::After(id) => (id, 0)
+		};
+		let neighbour_position = self.patches.iter()
+				.position(|&(patch_id, _)| patch_id == neighbour_id)
+				.ok_or(PatchCreationError::UnknownNeighbour(neighbour_id))?;
+		let position = neighbour_position + offset;
+
+		let new_id = Uuid::new(UuidVersion::Random).unwrap();
+		let info = self.patches[neighbour_position].1.info.clone();
+		self.patches.insert(position, (new_id, CombinedPatch {
+			info,
+			patches: Vec::new(),
+			series_entry: None,
+			originally_empty: false,
+		}));
+
+		// The new patch is ready to receive the changes of the selected one
+		let side = if self.left.selected_combined_patch.is_some() { &mut self.right } else { &mut self.left };
+		*side = Side {
+			selected_combined_patch: Some(new_id),
+			selected_patch: None,
+		};
+
+		self.show_patches()?;
+
+		Ok(new_id)
 	}
 }
 
@@ -273,6 +385,12 @@ pub trait PatchesViewReceiver: View {
 	fn view_hunks(&self, combined_patch_id: Option<Uuid>, patch_pos: Option<usize>, target_side: TargetSide) -> Result<(), failure::Error>;
 }
 
+#[derive(Fail, Debug)]
+pub enum PatchCreationError {
+	#[fail(display = "There's no patch {} to place the new one next to", _0)]
+	UnknownNeighbour(Uuid),
+}
+
 #[derive(Fail, Debug)]
 pub enum HunkTransferringError {
 	SourcePatchIsNotModification,
@@ -291,10 +409,148 @@ impl Display for HunkTransferringError {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
 		let description = match self {
 			HunkTransferringError::SourcePatchIsNotModification => "Can't modify patches which are not modifications",
-			HunkTransferringError::DestinationPatchNotFoundOrNotModification => "Couldn't find a matching patch, or it was not a modification",
+			HunkTransferringError::DestinationPatchNotFoundOrNotModification => "The file is absent in the destination, or the destination adds or removes it",
 			HunkTransferringError::OverlappingHunks(_) => "Hunks in the patches are overlapping",
 			HunkTransferringError::UnspecifiedSourceOrDestination => "Source and/or destination not specified, select patches and/or changes first"
 		};
 		write!(f, "{}", description)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use change_set::ChangeSetInfo;
+	use patch_editor::parser::parse_combined_patch;
+
+	const EDIT_F_AND_G: &[u8] = b"diff --git a/f.txt b/f.txt
+index 1111111..2222222 100644
+--- a/f.txt
++++ b/f.txt
+@@ -8,3 +8,3 @@
+ f 8
+-f 9
++f nine
+ f 10
+diff --git a/g.txt b/g.txt
+index 3333333..4444444 100644
+--- a/g.txt
++++ b/g.txt
+@@ -1 +1 @@
+-g
++G
+";
+
+	const EDIT_F: &[u8] = b"diff --git a/f.txt b/f.txt
+index 0000001..1111111 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,3 @@
+-f 1
++f one
+ f 2
+ f 3
+";
+
+	struct TestView;
+
+	impl View for TestView {
+		fn error(&self, error: failure::Error) {
+			panic!("Unexpected error: {}", error);
+		}
+	}
+
+	impl PatchesViewReceiver for TestView {
+		fn view_combined_patches(&self, _: Vec<(Uuid, CombinedPatch)>, _: Option<Uuid>, _: Option<Uuid>) -> Result<(), failure::Error> {
+			Ok(())
+		}
+
+		fn view_patches(&self, _: Option<Uuid>, _: TargetSide) -> Result<(), failure::Error> {
+			Ok(())
+		}
+
+		fn view_hunks(&self, _: Option<Uuid>, _: Option<usize>, _: TargetSide) -> Result<(), failure::Error> {
+			Ok(())
+		}
+	}
+
+	fn combined_patch(message: &str, data: &[u8]) -> CombinedPatch {
+		CombinedPatch {
+			info: ChangeSetInfo {
+				message: message.into(),
+				..ChangeSetInfo::default()
+			},
+			patches: if data.is_empty() { Vec::new() } else { parse_combined_patch(data).unwrap() },
+			series_entry: None,
+			originally_empty: false,
+		}
+	}
+
+	fn model(messages: &[&str]) -> PatchesModel<TestView> {
+		PatchesModel::new(TestView, messages.iter().map(|message| combined_patch(message, b"")).collect())
+	}
+
+	fn messages(patches: &[CombinedPatch]) -> Vec<&str> {
+		patches.iter().map(|patch| patch.info.message.as_str()).collect()
+	}
+
+	#[test]
+	fn test_into_patches() {
+		let model = model(&["Third\n", "Second\n", "First\n"]);
+		assert_eq!(messages(&model.into_patches()), vec!["Third\n", "Second\n", "First\n"]);
+	}
+
+	#[test]
+	fn test_new_patch() {
+		let mut model = model(&["Second\n", "First\n"]);
+		let (second_id, first_id) = (model.patches[0].0, model.patches[1].0);
+
+		// Applied before the second patch, so it goes after it in the newest first order
+		let before_id = model.new_patch(Placement::Before(second_id)).unwrap();
+		let after_id = model.new_patch(Placement::After(first_id)).unwrap();
+		assert_eq!(model.patches.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![second_id, before_id, after_id, first_id]);
+		assert_eq!(model.selected_combined_patch(TargetSide::Right), Some(after_id));
+
+		let patches = model.into_patches();
+		assert_eq!(messages(&patches), vec!["Second\n", "Second\n", "First\n", "First\n"]);
+		assert!(patches[1].patches.is_empty());
+	}
+
+	#[test]
+	fn test_delete_all_hunks() {
+		let mut model = PatchesModel::new(TestView, vec![combined_patch("Second\n", EDIT_F_AND_G), combined_patch("First\n", EDIT_F)]);
+		model.delete_hunks(TargetSide::Left, 0..1).unwrap();
+		assert_eq!(model.left.selected_patch, None);
+
+		let patches = model.into_patches();
+		assert_eq!(patches[0].patches.len(), 1);
+		assert_eq!(patches[0].patches[0].new_file_name(), Some(&String::from("g.txt")));
+	}
+
+	#[test]
+	fn test_transfer_all_hunks() {
+		let mut model = PatchesModel::new(TestView, vec![combined_patch("Second\n", EDIT_F_AND_G), combined_patch("First\n", EDIT_F)]);
+		model.transfer_hunks(TargetSide::Right, 0..1).unwrap();
+		assert_eq!(model.left.selected_patch, None);
+
+		let patches = model.into_patches();
+		assert_eq!(patches[0].patches.len(), 1);
+		assert_eq!(patches[0].patches[0].new_file_name(), Some(&String::from("g.txt")));
+		assert_eq!(patches[1].patches.len(), 1);
+		assert_eq!(patches[1].patches[0].hunks.len(), 2);
+	}
+
+	#[test]
+	fn test_new_patch_next_to_unknown_patch() {
+		let mut model = model(&["First\n"]);
+		let unknown_id = Uuid::new(UuidVersion::Random).unwrap();
+		match model.new_patch(Placement::After(unknown_id)) {
+			Err(err) => match err.downcast::<PatchCreationError>() {
+				Ok(PatchCreationError::UnknownNeighbour(id)) => assert_eq!(id, unknown_id),
+				Err(err) => panic!("Unexpected error: {}", err)
+			},
+			Ok(id) => panic!("Unexpected patch: {}", id)
+		}
+		assert_eq!(model.patches.len(), 1);
+	}
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::path::{Path, PathBuf};
 use std::fs::{read_dir, read};
 use std::iter::repeat;
+use std::slice;
 
 use failure;
 use pathdiff::diff_paths;
@@ -10,9 +11,16 @@ use pathdiff::diff_paths;
 use model::{Model, View};
 use patch_editor::patch::Hunk;
 use patch_editor::parser::parse_rejects;
+use patch_editor::applier::{apply_hunks, ApplicationOptions, WhitespaceMode};
 use std::fs::write;
 use std::fs::remove_file;
 
+// The rejected hunks have already failed to apply strictly, so the rest of the file has probably changed around them
+const REJECTED_HUNK_APPLICATION_OPTIONS: ApplicationOptions = ApplicationOptions {
+	fuzz: 2,
+	whitespace_mode: WhitespaceMode::IgnoreChanges,
+};
+
 #[derive(Clone)]
 enum Command {
 	ScanFiles,
@@ -20,6 +28,7 @@ enum Command {
 	SwitchToFile(usize),
 	SwitchHunk(usize),
 	AcceptHunk(usize, usize, bool),
+	ApplyHunk(usize, usize),
 	Reset(usize),
 	SaveAndQuit
 }
@@ -100,6 +109,18 @@ impl RejectsModel {
 				RejectsModel::update_hunks_and_file_data_view(view, state);
 				RejectsModel::update_hunk_view(view, state);
 			}
+			Command::ApplyHunk(file_pos, hunk_pos) => {
+				let application = {
+					let file = &state.rejected_files[file_pos];
+					apply_hunks(slice::from_ref(&*file.hunks[hunk_pos].0), &file.updated_file_data, &REJECTED_HUNK_APPLICATION_OPTIONS)
+				};
+				if !application.is_complete() {
+					return Err(RejectsModelError::HunkNotApplicable(hunk_pos).into());
+				}
+
+				state.rejected_files[file_pos].updated_file_data = Arc::new(application.data);
+				RejectsModel::perform_command(view, state, Command::AcceptHunk(file_pos, hunk_pos, true))?;
+			}
 			Command::Reset(file_pos) => {
 				{
 					let mut file = &mut state.rejected_files[file_pos];
@@ -166,6 +187,10 @@ impl RejectsModel {
 		self.base.worker_sink.send(Command::AcceptHunk(file_pos, hunk_pos, accepted)).unwrap();
 	}
 
+	pub fn apply_hunk(&self, file_pos: usize, hunk_pos: usize) {
+		self.base.worker_sink.send(Command::ApplyHunk(file_pos, hunk_pos)).unwrap();
+	}
+
 	pub fn reset(&self, file_pos: usize) {
 		self.base.worker_sink.send(Command::Reset(file_pos)).unwrap();
 	}
@@ -226,6 +251,12 @@ fn scan_directory(dir_path: impl AsRef<Path>) -> Result<Vec<RejectedFile>, failu
 	Ok(files)
 }
 
+#[derive(Fail, Debug)]
+enum RejectsModelError {
+	#[fail(display = "Hunk {} doesn't apply to the file even with the offset and the fuzz", _0)]
+	HunkNotApplicable(usize),
+}
+
 pub trait RejectsViewReceiver: View {
 	fn show_files(&self, files: Vec<(String, bool)>);
 	fn show_file_hunks(&self, hunks: Vec<(Arc<Hunk>, bool)>);
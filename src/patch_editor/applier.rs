@@ -0,0 +1,274 @@
+use std::cmp::min;
+
+use super::patch::{Patch, Hunk, HunkLine, HunkLineKind, range_begin};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum WhitespaceMode {
+	Exact,
+	IgnoreChanges,
+	IgnoreAll,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ApplicationOptions {
+	pub fuzz: usize, // How many context lines can be ignored at each end of the hunk
+	pub whitespace_mode: WhitespaceMode,
+}
+
+impl Default for ApplicationOptions {
+	fn default() -> ApplicationOptions {
+		ApplicationOptions {
+			fuzz: 0,
+			whitespace_mode: WhitespaceMode::Exact,
+		}
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HunkOutcome {
+	Applied { offset: isize, fuzz: usize }, // The offset is in the lines of the original file
+	Rejected,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Application {
+	pub data: Vec<u8>,
+	pub hunk_outcomes: Vec<HunkOutcome>,
+}
+
+impl Application {
+	pub fn is_complete(&self) -> bool {
+		self.hunk_outcomes.iter().all(|outcome| *outcome != HunkOutcome::Rejected)
+	}
+}
+
+pub fn apply_patch(patch: &Patch, data: &[u8], options: &ApplicationOptions) -> Application {
+	apply_hunks(&patch.hunks, data, options)
+}
+
+// The rejected hunks are skipped, so the result contains the changes of the applied ones only
+pub fn apply_hunks(hunks: &[Hunk], data: &[u8], options: &ApplicationOptions) -> Application {
+	let lines = split_lines(data);
+
+	let mut result = Vec::with_capacity(data.len());
+	let mut hunk_outcomes = Vec::with_capacity(hunks.len());
+	let mut processed_lines = 0;
+	let mut last_offset = 0isize;
+	for hunk in hunks {
+		let found_position = (0..options.fuzz + 1)
+				.filter_map(|fuzz| find_hunk(&lines, processed_lines, last_offset, hunk, fuzz, options.whitespace_mode))
+				.next();
+
+		let HunkPosition { line, expected_line, leading_lines_skipped, trailing_lines_skipped, fuzz } = match found_position {
+			Some(position) => position,
+			None => {
+				hunk_outcomes.push(HunkOutcome::Rejected);
+				continue;
+			}
+		};
+
+		for file_line in &lines[processed_lines..line] {
+			result.extend_from_slice(file_line);
+		}
+
+		let mut position = line;
+		for hunk_line in &hunk.lines[leading_lines_skipped..hunk.lines.len() - trailing_lines_skipped] {
+			match hunk_line.kind {
+				HunkLineKind::Context => {
+					result.extend_from_slice(lines[position]);
+					position += 1;
+				}
+				HunkLineKind::Removed => position += 1,
+				HunkLineKind::Added => result.extend_from_slice(&hunk_line.content)
+			}
+		}
+		processed_lines = position;
+
+		last_offset = line as isize - expected_line as isize;
+		hunk_outcomes.push(HunkOutcome::Applied {
+			offset: last_offset,
+			fuzz,
+		});
+	}
+
+	for file_line in &lines[processed_lines..] {
+		result.extend_from_slice(file_line);
+	}
+
+	Application {
+		data: result,
+		hunk_outcomes,
+	}
+}
+
+struct HunkPosition {
+	line: usize,
+	expected_line: usize,
+	leading_lines_skipped: usize,
+	trailing_lines_skipped: usize,
+	fuzz: usize,
+}
+
+// Searches the closest place to where the hunk is expected, but never before the end of the previous hunk
+fn find_hunk(lines: &[&[u8]], min_line: usize, last_offset: isize, hunk: &Hunk, fuzz: usize, whitespace_mode: WhitespaceMode) -> Option<HunkPosition> {
+	let leading_context = hunk.lines.iter().take_while(|line| line.kind == HunkLineKind::Context).count();
+	let trailing_context = hunk.lines.iter().rev().take_while(|line| line.kind == HunkLineKind::Context).count();
+	let leading_lines_skipped = min(fuzz, leading_context);
+	let trailing_lines_skipped = min(fuzz, min(trailing_context, hunk.lines.len() - leading_lines_skipped));
+
+	let old_lines = hunk.lines[leading_lines_skipped..hunk.lines.len() - trailing_lines_skipped]
+			.iter()
+			.filter(|line| line.kind != HunkLineKind::Added)
+			.collect::<Vec<_>>();
+	if old_lines.len() > lines.len() { return None; }
+
+	let expected_line = range_begin(&hunk.old_file_range) - 1 + leading_lines_skipped;
+	let last_line = lines.len() - old_lines.len();
+	let matches_at = |line: usize| {
+		// Without the fuzz, uneven context means the hunk is at the beginning or the end of the file, same as in git
+		if fuzz == 0 && leading_context < trailing_context && line != 0 { return false; }
+		if fuzz == 0 && trailing_context < leading_context && line != last_line { return false; }
+
+		old_lines.iter()
+				.zip(&lines[line..])
+				.all(|(hunk_line, file_line)| lines_match(hunk_line, file_line, whitespace_mode))
+	};
+
+	let center = min(last_line as isize, (expected_line as isize + last_offset).max(min_line as isize)) as usize;
+	for distance in 0..lines.len() + 1 {
+		let candidates = [Some(center + distance), center.checked_sub(distance)];
+		let candidates = if distance == 0 { &candidates[..1] } else { &candidates[..] };
+		for &line in candidates.iter().filter_map(|line| line.as_ref()) {
+			if line >= min_line && line <= last_line && matches_at(line) {
+				return Some(HunkPosition {
+					line,
+					expected_line,
+					leading_lines_skipped,
+					trailing_lines_skipped,
+					fuzz,
+				});
+			}
+		}
+	}
+
+	None
+}
+
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+	let mut lines = Vec::new();
+	let mut line_start = 0;
+	for (position, byte) in data.iter().enumerate() {
+		if *byte == b'\n' {
+			lines.push(&data[line_start..position + 1]);
+			line_start = position + 1;
+		}
+	}
+	if line_start < data.len() {
+		lines.push(&data[line_start..]);
+	}
+	lines
+}
+
+fn lines_match(hunk_line: &HunkLine, file_line: &[u8], whitespace_mode: WhitespaceMode) -> bool {
+	match whitespace_mode {
+		WhitespaceMode::Exact => &*hunk_line.content == file_line,
+		WhitespaceMode::IgnoreChanges => normalize_whitespace(&hunk_line.content) == normalize_whitespace(file_line),
+		WhitespaceMode::IgnoreAll => {
+			let is_not_whitespace = |byte: &&u8| !(**byte as char).is_ascii_whitespace();
+			hunk_line.content.iter().filter(is_not_whitespace).eq(file_line.iter().filter(is_not_whitespace))
+		}
+	}
+}
+
+// Collapses the runs of whitespace and drops the trailing one, including the line terminator
+fn normalize_whitespace(line: &[u8]) -> Vec<u8> {
+	let mut normalized = Vec::with_capacity(line.len());
+	let mut pending_whitespace = false;
+	for &byte in line {
+		if (byte as char).is_ascii_whitespace() {
+			pending_whitespace = true;
+		} else {
+			if pending_whitespace && !normalized.is_empty() {
+				normalized.push(b' ');
+			}
+			pending_whitespace = false;
+			normalized.push(byte);
+		}
+	}
+	normalized
+}
+
+#[cfg(test)]
+mod test {
+	use std::ops::Range;
+
+	use super::*;
+	use super::super::test_data::lines_from_data;
+
+	const FILE_DATA: &[u8] = b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+
+	fn hunk(old_file_range: Range<usize>, new_file_range: Range<usize>, data: &[u8]) -> Hunk {
+		Hunk::new(old_file_range, new_file_range, lines_from_data(data))
+	}
+
+	#[test]
+	fn test_apply_exactly() {
+		let hunks = vec![hunk(2..5, 2..5, b" b\n-c\n+C\n d\n"), hunk(9..11, 9..12, b" i\n+I\n j\n")];
+		let application = apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default());
+
+		assert_eq!(application.data, b"a\nb\nC\nd\ne\nf\ng\nh\ni\nI\nj\n".to_vec());
+		assert_eq!(application.hunk_outcomes, vec![HunkOutcome::Applied { offset: 0, fuzz: 0 }, HunkOutcome::Applied { offset: 0, fuzz: 0 }]);
+	}
+
+	#[test]
+	fn test_apply_at_offset() {
+		let hunks = vec![hunk(4..7, 4..7, b" b\n-c\n+C\n d\n")];
+		let application = apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default());
+
+		assert_eq!(application.data, b"a\nb\nC\nd\ne\nf\ng\nh\ni\nj\n".to_vec());
+		assert_eq!(application.hunk_outcomes, vec![HunkOutcome::Applied { offset: -2, fuzz: 0 }]);
+	}
+
+	#[test]
+	fn test_apply_with_fuzz() {
+		let hunks = vec![hunk(2..5, 2..5, b" x\n-c\n+C\n d\n")];
+		assert!(!apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default()).is_complete());
+
+		let options = ApplicationOptions {
+			fuzz: 1,
+			..ApplicationOptions::default()
+		};
+		let application = apply_hunks(&hunks, FILE_DATA, &options);
+		assert_eq!(application.data, b"a\nb\nC\nd\ne\nf\ng\nh\ni\nj\n".to_vec());
+		assert_eq!(application.hunk_outcomes, vec![HunkOutcome::Applied { offset: 0, fuzz: 1 }]);
+	}
+
+	#[test]
+	fn test_apply_ignoring_whitespace() {
+		let hunks = vec![hunk(2..5, 2..5, b" b \n-c\r\n+C\n  d\n")];
+		assert!(!apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default()).is_complete());
+
+		let options = ApplicationOptions {
+			whitespace_mode: WhitespaceMode::IgnoreChanges,
+			..ApplicationOptions::default()
+		};
+		let application = apply_hunks(&hunks, FILE_DATA, &options);
+		assert_eq!(application.data, b"a\nb\nC\nd\ne\nf\ng\nh\ni\nj\n".to_vec());
+	}
+
+	#[test]
+	fn test_reject_hunk() {
+		let hunks = vec![hunk(2..5, 2..5, b" b\n-x\n+X\n d\n"), hunk(9..11, 9..12, b" i\n+I\n j\n")];
+		let application = apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default());
+
+		assert_eq!(application.data, b"a\nb\nc\nd\ne\nf\ng\nh\ni\nI\nj\n".to_vec());
+		assert_eq!(application.hunk_outcomes, vec![HunkOutcome::Rejected, HunkOutcome::Applied { offset: 0, fuzz: 0 }]);
+	}
+
+	#[test]
+	fn test_apply_to_beginning() {
+		let hunks = vec![hunk(1..4, 1..5, b"+start\n a\n b\n c\n")];
+		let application = apply_hunks(&hunks, FILE_DATA, &ApplicationOptions::default());
+		assert_eq!(application.data, b"start\na\nb\nc\nd\ne\nf\ng\nh\ni\nj\n".to_vec());
+	}
+}
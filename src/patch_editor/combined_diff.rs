@@ -0,0 +1,226 @@
+use std::io::{Error, Write};
+use std::ops::Range;
+
+use super::patch::{Hunk, HunkLine, HunkLineKind, FILE_NAME_PLACEHOLDER, format_name};
+
+// The diff of a merge result against all of its parents, as produced by "git diff-tree -c" or "git diff-tree --cc"
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CombinedDiff {
+	pub dense: bool, // Only the hunks different from all the parents are listed, as with --cc
+	pub name: String,
+	pub change: CombinedChange,
+	pub old_indexes: Vec<String>,
+	pub new_index: String,
+	pub old_names: Vec<Option<String>>, // Either one for all the parents, or one per parent if git was asked to list them all
+	pub new_name: Option<String>, // No names for an absent file
+	pub hunks: Vec<CombinedHunk>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum CombinedChange {
+	Addition { new_mode: String },
+	Removal { old_modes: Vec<String> },
+	Modification { modes: Option<(Vec<String>, String)> }, // The modes are only listed when they differ
+}
+
+impl CombinedDiff {
+	pub fn parent_count(&self) -> usize {
+		self.old_indexes.len()
+	}
+
+	// The changes the merge made relative to one of the parents. With the dense format, some of them may be absent.
+	pub fn parent_hunks(&self, parent: usize) -> Vec<Hunk> {
+		self.hunks
+				.iter()
+				.map(|hunk| hunk.parent_hunk(parent))
+				.filter(|hunk| hunk.lines.iter().any(|line| line.kind != HunkLineKind::Context))
+				.collect()
+	}
+
+	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		let diff_type = if self.dense { "cc" } else { "combined" };
+		write.write_fmt(format_args!("diff --{} {}\n", diff_type, format_name(&self.name)))?;
+		write.write_fmt(format_args!("index {}..{}\n", self.old_indexes.join(","), self.new_index))?;
+
+		match self.change {
+			CombinedChange::Addition { ref new_mode } => write.write_fmt(format_args!("new file mode {}\n", new_mode))?,
+			CombinedChange::Removal { ref old_modes } => write.write_fmt(format_args!("deleted file mode {}\n", old_modes.join(",")))?,
+			CombinedChange::Modification { modes: Some((ref old_modes, ref new_mode)) } => {
+				write.write_fmt(format_args!("mode {}..{}\n", old_modes.join(","), new_mode))?
+			}
+			CombinedChange::Modification { modes: None } => ()
+		}
+
+		let prefixed_name = |prefix: &str, name: &Option<String>| match *name {
+			Some(ref name) => format_name(&(String::from(prefix) + name)).into_owned(),
+			None => FILE_NAME_PLACEHOLDER.into()
+		};
+		for old_name in &self.old_names {
+			write.write_fmt(format_args!("--- {}\n", prefixed_name("a/", old_name)))?;
+		}
+		write.write_fmt(format_args!("+++ {}\n", prefixed_name("b/", &self.new_name)))?;
+
+		for hunk in &self.hunks {
+			hunk.write(write)?;
+		}
+
+		Ok(())
+	}
+}
+
+// The ranges follow the same convention as in the two-way hunks, although git writes the start of the empty ones differently here
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CombinedHunk {
+	pub old_file_ranges: Vec<Range<usize>>,
+	pub new_file_range: Range<usize>,
+	pub section_heading: Option<String>,
+	pub lines: Vec<CombinedHunkLine>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CombinedHunkLine {
+	pub kinds: Vec<HunkLineKind>, // One per parent. The context kind means the parent has the line, or doesn't have it for the removed lines.
+	pub content: Vec<u8>, // Git doesn't mark the absent newlines in the combined diffs, so the terminator is always there
+}
+
+impl CombinedHunkLine {
+	pub fn is_removed(&self) -> bool {
+		self.kinds.iter().any(|kind| *kind == HunkLineKind::Removed)
+	}
+}
+
+fn combined_range_to_str(range: &Range<usize>) -> String {
+	let length = range.end - range.start;
+	let start = if length == 0 { range.start + 1 } else { range.start };
+	format!("{},{}", start, length)
+}
+
+impl CombinedHunk {
+	pub fn parent_hunk(&self, parent: usize) -> Hunk {
+		let lines = self.lines
+				.iter()
+				.filter_map(|line| {
+					let kind = line.kinds[parent];
+					if line.is_removed() && kind != HunkLineKind::Removed { return None; }
+					Some(HunkLine::new(kind, line.content.clone()))
+				})
+				.collect();
+
+		let mut hunk = Hunk::new(self.old_file_ranges[parent].clone(), self.new_file_range.clone(), lines);
+		hunk.section_heading = self.section_heading.clone();
+		hunk
+	}
+
+	pub fn header(&self) -> String {
+		let markers = "@".repeat(self.old_file_ranges.len() + 1);
+		let old_file_ranges_str = self.old_file_ranges
+				.iter()
+				.map(|range| format!(" -{}", combined_range_to_str(range)))
+				.collect::<String>();
+		let new_file_range_str = combined_range_to_str(&self.new_file_range);
+		match self.section_heading {
+			Some(ref section_heading) => format!("{}{} +{} {} {}\n", markers, old_file_ranges_str, new_file_range_str, markers, section_heading),
+			None => format!("{}{} +{} {}\n", markers, old_file_ranges_str, new_file_range_str, markers)
+		}
+	}
+
+	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		write.write_all(self.header().as_bytes())?;
+		for line in &self.lines {
+			let prefix = line.kinds.iter().map(|kind| kind.prefix()).collect::<Vec<_>>();
+			write.write_all(&prefix)?;
+			write.write_all(&line.content)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::parser::parse_combined_diffs;
+
+	const DENSE_DIFF_DATA: &[u8] = b"diff --cc f.txt
+index 83bd82b,3c1cec2..3250867
+--- a/f.txt
++++ b/f.txt
+@@@ -1,5 -1,5 +1,6 @@@
+  a
+- B main
+ -B side
+++B merged
+++extra
+  c
+  d
+  e
+diff --cc n.txt
+index 0000000,0000000..975fbec
+new file mode 100644
+--- /dev/null
++++ b/n.txt
+@@@ -1,0 -1,0 +1,1 @@@
+++y
+";
+
+	const COMBINED_DIFF_DATA: &[u8] = b"diff --combined f.txt
+index 83bd82b,3c1cec2..3250867
+--- a/f.txt
++++ b/f.txt
+@@@ -1,6 -1,6 +1,7 @@@
+  a
+- B main
+ -B side
+++B merged
+++extra
+  c
+  d
+  e
+ -f
+ +f
+";
+
+	fn hunks_to_string(hunks: &[Hunk]) -> String {
+		let mut buf = Vec::new();
+		for hunk in hunks {
+			hunk.write(&mut buf).unwrap();
+		}
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn test_round_trip() {
+		for data in &[DENSE_DIFF_DATA, COMBINED_DIFF_DATA] {
+			let mut buf = Vec::new();
+			for diff in parse_combined_diffs(data).unwrap() {
+				diff.write(&mut buf).unwrap();
+			}
+			assert_eq!(String::from_utf8_lossy(&buf), String::from_utf8_lossy(data));
+		}
+	}
+
+	#[test]
+	fn test_parse_combined_diffs() {
+		let diffs = parse_combined_diffs(DENSE_DIFF_DATA).unwrap();
+		assert_eq!(diffs.len(), 2);
+		assert!(diffs[0].dense);
+		assert_eq!(diffs[0].parent_count(), 2);
+		assert_eq!(diffs[0].hunks[0].old_file_ranges, vec![1..6, 1..6]);
+		assert_eq!(diffs[0].hunks[0].lines[1].kinds, vec![HunkLineKind::Removed, HunkLineKind::Context]);
+		assert_eq!(diffs[1].change, CombinedChange::Addition { new_mode: "100644".into() });
+		assert_eq!(diffs[1].old_names, vec![None]);
+		assert_eq!(diffs[1].hunks[0].old_file_ranges, vec![0..0, 0..0]);
+	}
+
+	#[test]
+	fn test_parent_hunks() {
+		let diffs = parse_combined_diffs(DENSE_DIFF_DATA).unwrap();
+		assert_eq!(hunks_to_string(&diffs[0].parent_hunks(0)), "@@ -1,5 +1,6 @@\n a\n-B main\n+B merged\n+extra\n c\n d\n e\n");
+		assert_eq!(hunks_to_string(&diffs[0].parent_hunks(1)), "@@ -1,5 +1,6 @@\n a\n-B side\n+B merged\n+extra\n c\n d\n e\n");
+		assert_eq!(hunks_to_string(&diffs[1].parent_hunks(1)), "@@ -0,0 +1 @@\n+y\n");
+
+		let diffs = parse_combined_diffs(COMBINED_DIFF_DATA).unwrap();
+		assert_eq!(hunks_to_string(&diffs[0].parent_hunks(0)),
+			"@@ -1,6 +1,7 @@\n a\n-B main\n+B merged\n+extra\n c\n d\n e\n f\n");
+	}
+}
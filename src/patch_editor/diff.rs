@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+
+use super::patch::{Patch, Change, Hunk, HunkLine, HunkLineKind, range_from_begin};
+use super::applier::split_lines;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum DiffAlgorithm {
+	Myers,
+	Patience,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DiffOptions {
+	pub context_lines: usize,
+	pub algorithm: DiffAlgorithm,
+}
+
+impl Default for DiffOptions {
+	fn default() -> DiffOptions {
+		DiffOptions {
+			context_lines: 3,
+			algorithm: DiffAlgorithm::Myers,
+		}
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Operation {
+	Equal,
+	Removed,
+	Added,
+}
+
+pub fn diff_patch(change: Change, old_data: &[u8], new_data: &[u8], options: &DiffOptions) -> Patch {
+	Patch {
+		change,
+		hunks: diff(old_data, new_data, options),
+	}
+}
+
+pub fn diff(old_data: &[u8], new_data: &[u8], options: &DiffOptions) -> Vec<Hunk> {
+	let old_lines = split_lines(old_data);
+	let new_lines = split_lines(new_data);
+
+	let mut operations = Vec::with_capacity(old_lines.len() + new_lines.len());
+	match options.algorithm {
+		DiffAlgorithm::Myers => diff_trimmed(&old_lines, &new_lines, &mut operations, myers),
+		DiffAlgorithm::Patience => patience(&old_lines, &new_lines, &mut operations)
+	}
+	put_removals_first(&mut operations);
+
+	build_hunks(&old_lines, &new_lines, &operations, options.context_lines)
+}
+
+// Handles the common prefix and suffix separately, as they are often most of the file
+fn diff_trimmed(old_lines: &[&[u8]], new_lines: &[&[u8]], operations: &mut Vec<Operation>, algorithm: fn(&[&[u8]], &[&[u8]], &mut Vec<Operation>)) {
+	let prefix_length = old_lines.iter().zip(new_lines).take_while(|&(old_line, new_line)| old_line == new_line).count();
+	let suffix_length = old_lines[prefix_length..].iter().rev()
+			.zip(new_lines[prefix_length..].iter().rev())
+			.take_while(|&(old_line, new_line)| old_line == new_line)
+			.count();
+
+	operations.extend((0..prefix_length).map(|_| Operation::Equal));
+	algorithm(&old_lines[prefix_length..old_lines.len() - suffix_length], &new_lines[prefix_length..new_lines.len() - suffix_length], operations);
+	operations.extend((0..suffix_length).map(|_| Operation::Equal));
+}
+
+// The linear space version: finds the middle snake of a shortest edit script, then diffs the parts before and after it the same way
+fn myers(old_lines: &[&[u8]], new_lines: &[&[u8]], operations: &mut Vec<Operation>) {
+	if old_lines.is_empty() || new_lines.is_empty() {
+		operations.extend(old_lines.iter().map(|_| Operation::Removed));
+		operations.extend(new_lines.iter().map(|_| Operation::Added));
+		return;
+	}
+
+	// Both parts have shorter edit scripts once the common prefix and suffix are trimmed, so the recursion ends
+	let ((begin_x, begin_y), (end_x, end_y)) = find_middle_snake(old_lines, new_lines);
+	diff_trimmed(&old_lines[..begin_x], &new_lines[..begin_y], operations, myers);
+	operations.extend((begin_x..end_x).map(|_| Operation::Equal));
+	diff_trimmed(&old_lines[end_x..], &new_lines[end_y..], operations, myers);
+}
+
+// Searches from both ends at once, keeping the furthest reaching paths for each diagonal k = x - y.
+// The backward paths are kept as if both sides were reversed. Returns the beginning and the end of the snake where the paths meet.
+fn find_middle_snake(old_lines: &[&[u8]], new_lines: &[&[u8]]) -> ((usize, usize), (usize, usize)) {
+	let (old_length, new_length) = (old_lines.len() as isize, new_lines.len() as isize);
+	let delta = old_length - new_length;
+	let odd_delta = delta % 2 != 0;
+	let max_distance = (old_length + new_length + 1) / 2;
+
+	let offset = max_distance + 1;
+	let mut forward_x = vec![0isize; (2 * max_distance + 3) as usize];
+	let mut backward_x = vec![0isize; (2 * max_distance + 3) as usize];
+	let index = |k: isize| (offset + k) as usize;
+
+	for distance in 0..max_distance + 1 {
+		for k in (-distance..distance + 1).filter(|k| (k + distance) % 2 == 0) {
+			let begin_x = if k == -distance || (k != distance && forward_x[index(k - 1)] < forward_x[index(k + 1)]) {
+				forward_x[index(k + 1)]
+			} else {
+				forward_x[index(k - 1)] + 1
+			};
+			let begin_y = begin_x - k;
+			let (mut x, mut y) = (begin_x, begin_y);
+			while x < old_length && y < new_length && old_lines[x as usize] == new_lines[y as usize] {
+				x += 1;
+				y += 1;
+			}
+			forward_x[index(k)] = x;
+
+			let backward_k = delta - k;
+			if odd_delta && backward_k.abs() < distance && x + backward_x[index(backward_k)] >= old_length {
+				return ((begin_x as usize, begin_y as usize), (x as usize, y as usize));
+			}
+		}
+
+		for k in (-distance..distance + 1).filter(|k| (k + distance) % 2 == 0) {
+			let begin_x = if k == -distance || (k != distance && backward_x[index(k - 1)] < backward_x[index(k + 1)]) {
+				backward_x[index(k + 1)]
+			} else {
+				backward_x[index(k - 1)] + 1
+			};
+			let begin_y = begin_x - k;
+			let (mut x, mut y) = (begin_x, begin_y);
+			while x < old_length && y < new_length && old_lines[(old_length - x - 1) as usize] == new_lines[(new_length - y - 1) as usize] {
+				x += 1;
+				y += 1;
+			}
+			backward_x[index(k)] = x;
+
+			let forward_k = delta - k;
+			if !odd_delta && forward_k.abs() <= distance && x + forward_x[index(forward_k)] >= old_length {
+				return (((old_length - x) as usize, (new_length - y) as usize), ((old_length - begin_x) as usize, (new_length - begin_y) as usize));
+			}
+		}
+	}
+	unreachable!("The paths from both ends always meet")
+}
+
+// Matches the lines which are unique on both sides first, then diffs the parts between them
+fn patience(old_lines: &[&[u8]], new_lines: &[&[u8]], operations: &mut Vec<Operation>) {
+	diff_trimmed(old_lines, new_lines, operations, |old_lines, new_lines, operations| {
+		let anchors = find_unique_common_lines(old_lines, new_lines);
+		if anchors.is_empty() {
+			myers(old_lines, new_lines, operations);
+			return;
+		}
+
+		let (mut old_position, mut new_position) = (0, 0);
+		for (old_index, new_index) in anchors {
+			patience(&old_lines[old_position..old_index], &new_lines[new_position..new_index], operations);
+			operations.push(Operation::Equal);
+			old_position = old_index + 1;
+			new_position = new_index + 1;
+		}
+		patience(&old_lines[old_position..], &new_lines[new_position..], operations);
+	});
+}
+
+// Returns the longest sequence of the unique lines going in the same order on both sides
+fn find_unique_common_lines(old_lines: &[&[u8]], new_lines: &[&[u8]]) -> Vec<(usize, usize)> {
+	let mut occurrences: HashMap<&[u8], (usize, usize, usize, usize)> = HashMap::new();
+	for (index, line) in old_lines.iter().enumerate() {
+		let entry = occurrences.entry(line).or_insert((0, 0, 0, 0));
+		entry.0 += 1;
+		entry.1 = index;
+	}
+	for (index, line) in new_lines.iter().enumerate() {
+		if let Some(entry) = occurrences.get_mut(line) {
+			entry.2 += 1;
+			entry.3 = index;
+		}
+	}
+
+	let mut unique_lines = occurrences.values()
+			.filter(|&&(old_count, _, new_count, _)| old_count == 1 && new_count == 1)
+			.map(|&(_, old_index, _, new_index)| (old_index, new_index))
+			.collect::<Vec<_>>();
+	unique_lines.sort_unstable();
+
+	// Patience sorting: each pile keeps the line with the smallest new index for the sequence length, and a link to the previous pile
+	let mut pile_tops: Vec<usize> = Vec::new();
+	let mut previous_in_sequence: Vec<Option<usize>> = Vec::with_capacity(unique_lines.len());
+	for (line_position, &(_, new_index)) in unique_lines.iter().enumerate() {
+		let pile = match pile_tops.binary_search_by_key(&new_index, |&top| unique_lines[top].1) {
+			Ok(pile) | Err(pile) => pile
+		};
+		previous_in_sequence.push(if pile > 0 { Some(pile_tops[pile - 1]) } else { None });
+		if pile == pile_tops.len() {
+			pile_tops.push(line_position);
+		} else {
+			pile_tops[pile] = line_position;
+		}
+	}
+
+	let mut sequence = Vec::with_capacity(pile_tops.len());
+	let mut line_position = pile_tops.last().cloned();
+	while let Some(position) = line_position {
+		sequence.push(unique_lines[position]);
+		line_position = previous_in_sequence[position];
+	}
+	sequence.reverse();
+	sequence
+}
+
+// The removed lines of every change go before the added ones, as in git
+fn put_removals_first(operations: &mut [Operation]) {
+	for change in operations.split_mut(|operation| *operation == Operation::Equal) {
+		change.sort_unstable_by_key(|operation| *operation != Operation::Removed);
+	}
+}
+
+fn build_hunks(old_lines: &[&[u8]], new_lines: &[&[u8]], operations: &[Operation], context_lines: usize) -> Vec<Hunk> {
+	let mut positions = Vec::with_capacity(operations.len() + 1);
+	let (mut old_position, mut new_position) = (0, 0);
+	for operation in operations {
+		positions.push((old_position, new_position));
+		match *operation {
+			Operation::Equal => {
+				old_position += 1;
+				new_position += 1;
+			}
+			Operation::Removed => old_position += 1,
+			Operation::Added => new_position += 1
+		}
+	}
+	positions.push((old_position, new_position));
+
+	let changes = operations.iter()
+			.enumerate()
+			.filter(|&(_, operation)| *operation != Operation::Equal)
+			.map(|(index, _)| index)
+			.collect::<Vec<_>>();
+
+	let mut hunks = Vec::new();
+	let mut change_position = 0;
+	while change_position < changes.len() {
+		let first_change = changes[change_position];
+		let mut last_change = first_change;
+		change_position += 1;
+		while change_position < changes.len() && changes[change_position] - last_change - 1 <= 2 * context_lines {
+			last_change = changes[change_position];
+			change_position += 1;
+		}
+
+		let begin = first_change.saturating_sub(context_lines);
+		let end = (last_change + 1 + context_lines).min(operations.len());
+		let lines = (begin..end)
+				.map(|index| {
+					let (old_position, new_position) = positions[index];
+					match operations[index] {
+						Operation::Equal => hunk_line(HunkLineKind::Context, old_lines, old_position),
+						Operation::Removed => hunk_line(HunkLineKind::Removed, old_lines, old_position),
+						Operation::Added => hunk_line(HunkLineKind::Added, new_lines, new_position)
+					}
+				})
+				.collect();
+
+		let (old_begin, new_begin) = positions[begin];
+		let (old_end, new_end) = positions[end];
+		hunks.push(Hunk::new(range_from_begin(old_begin + 1, old_end - old_begin), range_from_begin(new_begin + 1, new_end - new_begin), lines));
+	}
+
+	hunks
+}
+
+fn hunk_line(kind: HunkLineKind, lines: &[&[u8]], position: usize) -> HunkLine {
+	let content = lines[position];
+	let mut line = HunkLine::new(kind, content.to_vec());
+	line.no_newline_at_eof = !content.ends_with(b"\n");
+	line
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use patch_editor::applier::{apply_hunks, ApplicationOptions};
+
+	const OLD_DATA: &[u8] = b"line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\nline 8\nline 9\nline 10
+line 11\nline 12\nline 13\nline 14\nline 15\nline 16\nline 17\nline 18\nline 19\nline 20\n";
+
+	const NEW_DATA: &[u8] = b"line 1\nline two\nline 3\nline 4\nline 5\nadded a\nadded b\nline 6\nline 7\nline 8\nline 9\nline 10
+line 11\nline 12\nline 13\nline 14\nline 15\nadded c\nline 16\nline 17\nline 18\nline 19\nline 20\n";
+
+	const PATIENCE_OLD_DATA: &[u8] = b"#include <stdio.h>
+
+void foo() {
+	bar();
+}
+
+void baz() {
+	qux();
+}
+
+int main() {
+	foo();
+	return 0;
+}
+";
+
+	const PATIENCE_NEW_DATA: &[u8] = b"#include <stdio.h>
+
+int fib(int n) {
+	if (n < 2) {
+		return n;
+	}
+	return fib(n - 1) + fib(n - 2);
+}
+
+void foo() {
+	bar();
+}
+
+void baz() {
+	qux();
+}
+
+int main() {
+	foo();
+	return fib(10);
+}
+";
+
+	fn hunks_to_string(hunks: &[Hunk]) -> String {
+		let mut buf = Vec::new();
+		for hunk in hunks {
+			hunk.write(&mut buf).unwrap();
+		}
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn test_diff() {
+		let hunks = diff(OLD_DATA, NEW_DATA, &DiffOptions::default());
+		assert_eq!(hunks_to_string(&hunks), "@@ -1,8 +1,10 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
++added a
++added b
+ line 6
+ line 7
+ line 8
+@@ -13,6 +15,7 @@
+ line 13
+ line 14
+ line 15
++added c
+ line 16
+ line 17
+ line 18
+");
+	}
+
+	#[test]
+	fn test_diff_without_context() {
+		let options = DiffOptions {
+			context_lines: 0,
+			..DiffOptions::default()
+		};
+		let hunks = diff(OLD_DATA, NEW_DATA, &options);
+		assert_eq!(hunks_to_string(&hunks), "@@ -2 +2 @@
+-line 2
++line two
+@@ -5,0 +6,2 @@
++added a
++added b
+@@ -15,0 +18 @@
++added c
+");
+	}
+
+	#[test]
+	fn test_diff_no_new_line() {
+		let options = DiffOptions {
+			context_lines: 1,
+			..DiffOptions::default()
+		};
+		let hunks = diff(b"a\nb\nc\nd\n", b"a\nb\nc\nD", &options);
+		assert_eq!(hunks_to_string(&hunks), "@@ -3,2 +3,2 @@
+ c
+-d
++D
+\\ No newline at end of file
+");
+	}
+
+	#[test]
+	fn test_diff_patience() {
+		let options = DiffOptions {
+			algorithm: DiffAlgorithm::Patience,
+			..DiffOptions::default()
+		};
+		let hunks = diff(PATIENCE_OLD_DATA, PATIENCE_NEW_DATA, &options);
+		assert_eq!(hunks_to_string(&hunks), "@@ -1,5 +1,12 @@
+ #include <stdio.h>
+ 
++int fib(int n) {
++	if (n < 2) {
++		return n;
++	}
++	return fib(n - 1) + fib(n - 2);
++}
++
+ void foo() {
+ 	bar();
+ }
+@@ -10,5 +17,5 @@
+ 
+ int main() {
+ 	foo();
+-	return 0;
++	return fib(10);
+ }
+");
+	}
+
+	#[test]
+	fn test_diff_shortest() {
+		let options = DiffOptions {
+			context_lines: 0,
+			..DiffOptions::default()
+		};
+		let hunks = diff(b"a\nb\nc\na\nb\nb\na\n", b"c\nb\na\nb\na\nc\n", &options);
+		let changed_lines = hunks.iter().map(|hunk| hunk.lines.len()).sum::<usize>();
+		assert_eq!(changed_lines, 5);
+
+		let old_data = (0..2000).map(|number| format!("line {}\n", number % 7)).collect::<String>();
+		let new_data = (0..2000).map(|number| format!("line {}\n", number % 5)).collect::<String>();
+		let hunks = diff(old_data.as_bytes(), new_data.as_bytes(), &DiffOptions::default());
+		let application = apply_hunks(&hunks, old_data.as_bytes(), &ApplicationOptions::default());
+		assert!(application.is_complete());
+		assert_eq!(application.data, new_data.as_bytes());
+	}
+
+	#[test]
+	fn test_diff_from_and_to_empty_file() {
+		let addition = diff(b"", b"a\nb\n", &DiffOptions::default());
+		assert_eq!(hunks_to_string(&addition), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+
+		let removal = diff(b"a\nb\n", b"", &DiffOptions::default());
+		assert_eq!(hunks_to_string(&removal), "@@ -1,2 +0,0 @@\n-a\n-b\n");
+	}
+}
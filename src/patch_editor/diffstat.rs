@@ -0,0 +1,298 @@
+use std::io::{Error, Write};
+
+const DEFAULT_WIDTH: usize = 80; // Git uses the terminal width, or 80 columns if it's not a terminal
+const MIN_GRAPH_WIDTH: usize = 6;
+const MIN_NAME_AND_GRAPH_WIDTH: usize = 16;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FileStat {
+	pub name: String,
+	pub old_name: Option<String>, // Only for the renames and copies
+	pub insertions: usize, // For the binary files, the sizes in bytes after and before the change, if known
+	pub deletions: usize,
+	pub binary: bool, // The patches don't hold binary contents, but the stats may come from elsewhere
+}
+
+impl FileStat {
+	pub fn changes(&self) -> usize {
+		self.insertions + self.deletions
+	}
+
+	// Git shortens the names to the part that changed, e.g. "dir/{a.txt => b.txt}"
+	pub fn display_name(&self) -> String {
+		let old_name = match self.old_name {
+			Some(ref old_name) if *old_name != self.name => old_name,
+			_ => return self.name.clone()
+		};
+
+		let (old_name, new_name) = (old_name.as_bytes(), self.name.as_bytes());
+		let prefix_length = old_name.iter()
+				.zip(new_name.iter())
+				.take_while(|&(old_byte, new_byte)| old_byte == new_byte)
+				.enumerate()
+				.filter(|&(_, (byte, _))| *byte == b'/')
+				.map(|(position, _)| position + 1)
+				.last()
+				.unwrap_or(0);
+
+		// The suffix may overlap the prefix by the slash they share
+		let slash_adjustment = if prefix_length > 0 { 1 } else { 0 };
+		let mut suffix_length = 0;
+		let (mut old_position, mut new_position) = (old_name.len() as isize, new_name.len() as isize);
+		while old_position >= (prefix_length - slash_adjustment) as isize && new_position >= (prefix_length - slash_adjustment) as isize {
+			let old_byte = old_name.get(old_position as usize);
+			if old_byte != new_name.get(new_position as usize) { break; }
+			if old_byte == Some(&b'/') {
+				suffix_length = old_name.len() - old_position as usize;
+			}
+			old_position -= 1;
+			new_position -= 1;
+		}
+
+		let middle = |name: &[u8]| String::from_utf8_lossy(&name[prefix_length..name.len().saturating_sub(suffix_length).max(prefix_length)]).into_owned();
+		let (old_middle, new_middle) = (middle(old_name), middle(new_name));
+		if prefix_length + suffix_length == 0 {
+			return format!("{} => {}", old_middle, new_middle);
+		}
+
+		let prefix = String::from_utf8_lossy(&old_name[..prefix_length]);
+		let suffix = String::from_utf8_lossy(&old_name[old_name.len() - suffix_length..]);
+		format!("{}{{{} => {}}}{}", prefix, old_middle, new_middle, suffix)
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StatOptions {
+	pub width: usize, // The whole line, same as "--stat=<width>"
+	pub name_width: Option<usize>,
+	pub graph_width: Option<usize>,
+}
+
+impl Default for StatOptions {
+	fn default() -> StatOptions {
+		StatOptions {
+			width: DEFAULT_WIDTH,
+			name_width: None,
+			graph_width: None,
+		}
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DiffStat {
+	pub files: Vec<FileStat>,
+}
+
+impl DiffStat {
+	pub fn insertions(&self) -> usize {
+		self.files.iter().filter(|file| !file.binary).map(|file| file.insertions).sum()
+	}
+
+	pub fn deletions(&self) -> usize {
+		self.files.iter().filter(|file| !file.binary).map(|file| file.deletions).sum()
+	}
+
+	// The same output as "git diff --stat", without the colors
+	pub fn write<W: Write>(&self, options: &StatOptions, write: &mut W) -> Result<(), Error> {
+		let names = self.files.iter().map(|file| file.display_name()).collect::<Vec<_>>();
+		let max_name_length = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+		let max_change = self.files.iter().filter(|file| !file.binary).map(|file| file.changes()).max().unwrap_or(0);
+		let binary_width = self.files.iter()
+				.filter(|file| file.binary)
+				.map(|file| 14 + decimal_width(file.insertions) + decimal_width(file.deletions)) // "Bin XXX -> YYY bytes"
+				.max()
+				.unwrap_or(0);
+
+		let mut number_width = decimal_width(max_change);
+		if self.files.iter().any(|file| file.binary) {
+			number_width = number_width.max(3); // The counts are aligned with "Bin"
+		}
+		let width = options.width.max(MIN_NAME_AND_GRAPH_WIDTH + MIN_GRAPH_WIDTH + number_width);
+
+		let mut graph_width = if max_change + 4 > binary_width { max_change } else { binary_width - 4 };
+		if let Some(option_graph_width) = options.graph_width {
+			graph_width = graph_width.min(option_graph_width);
+		}
+		let mut name_width = options.name_width.map_or(max_name_length, |name_width| name_width.min(max_name_length));
+
+		if name_width + number_width + 6 + graph_width > width {
+			let proportional_graph_width = (width * 3 / 8).saturating_sub(number_width + 6);
+			if graph_width > proportional_graph_width {
+				graph_width = proportional_graph_width.max(MIN_GRAPH_WIDTH);
+			}
+			if let Some(option_graph_width) = options.graph_width {
+				graph_width = graph_width.min(option_graph_width);
+			}
+
+			let available_name_width = width - number_width - 6 - graph_width;
+			if name_width > available_name_width {
+				name_width = available_name_width;
+			} else {
+				graph_width = width - number_width - 6 - name_width;
+			}
+		}
+
+		for (file, name) in self.files.iter().zip(names.iter()) {
+			let (prefix, name) = shorten_name(name, name_width);
+			let padding = (name_width - prefix.len()).saturating_sub(name.chars().count());
+			write.write_fmt(format_args!(" {}{}{} | ", prefix, name, " ".repeat(padding)))?;
+
+			if file.binary {
+				if file.insertions == 0 && file.deletions == 0 {
+					write.write_fmt(format_args!("{:>width$}\n", "Bin", width = number_width))?;
+				} else {
+					write.write_fmt(format_args!("{:>width$} {} -> {} bytes\n", "Bin", file.deletions, file.insertions, width = number_width))?;
+				}
+				continue;
+			}
+
+			let (mut added, mut deleted) = (file.insertions, file.deletions);
+			if graph_width <= max_change {
+				let mut total = scale_linear(added + deleted, graph_width, max_change);
+				if total < 2 && added > 0 && deleted > 0 {
+					total = 2;
+				}
+				if added < deleted {
+					added = scale_linear(added, graph_width, max_change);
+					deleted = total - added;
+				} else {
+					deleted = scale_linear(deleted, graph_width, max_change);
+					added = total - deleted;
+				}
+			}
+
+			let separator = if file.changes() > 0 { " " } else { "" };
+			write.write_fmt(format_args!("{:>width$}{}{}{}\n", file.changes(), separator, "+".repeat(added), "-".repeat(deleted), width = number_width))?;
+		}
+
+		self.write_summary(write)
+	}
+
+	pub fn write_summary<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		if self.files.is_empty() {
+			return write.write_all(b" 0 files changed\n");
+		}
+
+		let pluralize = |count: usize, singular: &str, plural: &str| format!("{} {}", count, if count == 1 { singular } else { plural });
+		let (insertions, deletions) = (self.insertions(), self.deletions());
+		let mut summary = format!(" {}", pluralize(self.files.len(), "file changed", "files changed"));
+		if insertions > 0 || deletions == 0 {
+			summary += &format!(", {}", pluralize(insertions, "insertion(+)", "insertions(+)"));
+		}
+		if deletions > 0 || insertions == 0 {
+			summary += &format!(", {}", pluralize(deletions, "deletion(-)", "deletions(-)"));
+		}
+		write.write_fmt(format_args!("{}\n", summary))
+	}
+}
+
+fn decimal_width(number: usize) -> usize {
+	number.to_string().len()
+}
+
+// At least one character is shown for any change
+fn scale_linear(count: usize, width: usize, max_change: usize) -> usize {
+	if count == 0 { return 0; }
+	1 + count * (width - 1) / max_change
+}
+
+// The long names lose their beginning, up to a slash if there's one left
+fn shorten_name(name: &str, name_width: usize) -> (&'static str, &str) {
+	let name_length = name.chars().count();
+	if name_length <= name_width { return ("", name); }
+
+	let kept_length = name_width.saturating_sub(3);
+	let kept_start = name.char_indices().nth(name_length - kept_length).map_or(name.len(), |(position, _)| position);
+	let kept_name = &name[kept_start..];
+	match kept_name.find('/') {
+		Some(slash) => ("...", &kept_name[slash..]),
+		None => ("...", kept_name)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn file_stat(name: &str, insertions: usize, deletions: usize) -> FileStat {
+		FileStat {
+			name: name.into(),
+			old_name: None,
+			insertions,
+			deletions,
+			binary: false,
+		}
+	}
+
+	fn render(stat: &DiffStat, options: &StatOptions) -> String {
+		let mut buf = Vec::new();
+		stat.write(options, &mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn test_display_name() {
+		let rename = |old_name: &str, name: &str| FileStat { old_name: Some(old_name.into()), ..file_stat(name, 0, 0) }.display_name();
+		assert_eq!(rename("a.txt", "b.txt"), "a.txt => b.txt");
+		assert_eq!(rename("dir/a.txt", "dir/b.txt"), "dir/{a.txt => b.txt}");
+		assert_eq!(rename("old/sub/f.txt", "new/sub/f.txt"), "{old => new}/sub/f.txt");
+		assert_eq!(rename("d/f.txt", "d/e/f.txt"), "d/{ => e}/f.txt");
+		assert_eq!(rename("f.txt", "f.txt"), "f.txt");
+	}
+
+	// The expected output is from "git diff --stat" with the same options on the same changes
+	#[test]
+	fn test_write() {
+		let stat = DiffStat {
+			files: vec![
+				file_stat("a/rather/long/directory/name/with/a/file/inside/it/f.txt", 3, 1),
+				file_stat("big.txt", 200, 0),
+				FileStat { binary: true, ..file_stat("image.png", 1024, 0) },
+				FileStat { old_name: Some("m.txt".into()), ..file_stat("n.txt", 0, 0) }
+			],
+		};
+		let expected_outputs = vec![
+			(StatOptions::default(), vec![
+				" .../directory/name/with/a/file/inside/it/f.txt     |   4 +-",
+				" big.txt                                            | 200 +++++++++++++++++++++",
+				" image.png                                          | Bin 0 -> 1024 bytes",
+				" m.txt => n.txt                                     |   0"
+			]),
+			(StatOptions { width: 72, ..StatOptions::default() }, vec![
+				" .../name/with/a/file/inside/it/f.txt          |   4 +-",
+				" big.txt                                       | 200 ++++++++++++++++++",
+				" image.png                                     | Bin 0 -> 1024 bytes",
+				" m.txt => n.txt                                |   0"
+			]),
+			(StatOptions { width: 40, ..StatOptions::default() }, vec![
+				" .../file/inside/it/f.txt  |   4 +-",
+				" big.txt                   | 200 ++++++",
+				" image.png                 | Bin 0 -> 1024 bytes",
+				" m.txt => n.txt            |   0"
+			]),
+			(StatOptions { graph_width: Some(10), ..StatOptions::default() }, vec![
+				" a/rather/long/directory/name/with/a/file/inside/it/f.txt |   4 +-",
+				" big.txt                                                  | 200 ++++++++++",
+				" image.png                                                | Bin 0 -> 1024 bytes",
+				" m.txt => n.txt                                           |   0"
+			]),
+			(StatOptions { width: 60, name_width: Some(20), ..StatOptions::default() }, vec![
+				" .../inside/it/f.txt  |   4 +-",
+				" big.txt              | 200 +++++++++++++++++++++++++++++++",
+				" image.png            | Bin 0 -> 1024 bytes",
+				" m.txt => n.txt       |   0"
+			])
+		];
+		for (options, lines) in expected_outputs {
+			assert_eq!(render(&stat, &options), lines.join("\n") + "\n 4 files changed, 203 insertions(+), 1 deletion(-)\n");
+		}
+	}
+
+	#[test]
+	fn test_write_summary() {
+		let mut buf = Vec::new();
+		DiffStat { files: Vec::new() }.write_summary(&mut buf).unwrap();
+		DiffStat { files: vec![file_stat("f.txt", 0, 2)] }.write_summary(&mut buf).unwrap();
+		assert_eq!(String::from_utf8(buf).unwrap(), " 0 files changed\n 1 file changed, 2 deletions(-)\n");
+	}
+}
@@ -1,5 +1,10 @@
 pub mod parser;
 pub mod patch;
+pub mod applier;
+pub mod diff;
+pub mod combined_diff;
+pub mod patch_id;
+pub mod diffstat;
 
 #[cfg(test)]
 mod test_data;
\ No newline at end of file
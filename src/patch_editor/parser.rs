@@ -1,11 +1,12 @@
-use nom::{anychar, digit, is_space, is_hex_digit, is_oct_digit, line_ending, not_line_ending, space, Needed, IResult, ErrorKind, Err};
+use nom::{anychar, digit, is_space, is_hex_digit, is_oct_digit, line_ending, not_line_ending, space, Needed, IResult, ErrorKind, Err, Context};
 use std::ops::Range;
 use std::borrow::Cow;
 use std::string::FromUtf8Error;
 use std::num::ParseIntError;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display, Formatter};
 
-use super::patch::{Change, Patch, FileProperties, Hunk, ModificationType};
+use super::patch::{Change, Patch, FileProperties, Hunk, HunkLine, HunkLineKind, ModificationType, FILE_NAME_PLACEHOLDER};
+use super::combined_diff::{CombinedDiff, CombinedChange, CombinedHunk, CombinedHunkLine};
 use super::super::parsing_utils::{file_name, quoted_name};
 
 #[derive(Debug, Eq, PartialEq)]
@@ -28,7 +29,7 @@ enum PresenceChangeType {
 
 #[derive(Debug, Eq, PartialEq)]
 enum PatchPart<'a> {
-	Name(Vec<u8>, Order),
+	Name(Option<Vec<u8>>, Order), // No name for an absent file
 	NameChange(Vec<u8>, NameChangeType, Order),
 	PresenceChange {
 		change_type: PresenceChangeType,
@@ -45,9 +46,63 @@ enum PatchPart<'a> {
 	Hunk(Hunk),
 }
 
+const PATCH_HEADER_TAG: &[u8] = b"diff --git ";
+const DIFF_HEADER_TAG: &[u8] = b"diff "; // Any diff tool, e.g. "diff -ruN a/f.txt b/f.txt"
+const INDEX_PREAMBLE_TAG: &[u8] = b"Index: "; // Written by Subversion and quilt, followed by a line of "="
+const OLD_NAME_TAG: &[u8] = b"--- ";
+const NEW_NAME_TAG: &[u8] = b"+++ ";
+const HUNK_HEADER_TAG: &[u8] = b"@@ ";
+const DEFAULT_MODE: &str = "100644"; // The plain diffs don't have the modes
+const COMBINED_DIFF_HEADER_TAG: &[u8] = b"diff --c"; // Either "diff --cc" or "diff --combined"
+
+#[derive(Debug, Eq, PartialEq)]
+enum CombinedDiffPart<'a> {
+	Index(Vec<&'a [u8]>, &'a [u8]),
+	Addition(&'a [u8]),
+	Removal(Vec<&'a [u8]>),
+	ModeChange(Vec<&'a [u8]>, &'a [u8]),
+	Name(Option<Vec<u8>>, Order),
+	Hunk(CombinedHunk),
+}
+
 struct PatchParts<'a> {
+	start: &'a [u8], // The input from the header on, to locate the errors
 	names: Option<(Vec<u8>, Vec<u8>)>,
-	parts: Vec<PatchPart<'a>>,
+	parts: Vec<(&'a [u8], PatchPart<'a>)>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Location {
+	pub offset: usize, // In bytes from the beginning of the input
+	pub line: usize, // Both the line and the column start from 1, the column is in bytes
+	pub column: usize,
+	pub snippet: String, // The line the error is at
+	pub file_name: Option<String>,
+}
+
+impl Location {
+	fn new(input: &[u8], position: &[u8], file_name: Option<String>) -> Location {
+		let offset = input.len() - position.len();
+		let line_start = input[..offset].iter().rposition(|byte| *byte == b'\n').map_or(0, |position| position + 1);
+		let line_end = input[offset..].iter().position(|byte| *byte == b'\n').map_or(input.len(), |position| offset + position);
+
+		Location {
+			offset,
+			line: input[..offset].iter().filter(|byte| **byte == b'\n').count() + 1,
+			column: offset - line_start + 1,
+			snippet: String::from_utf8_lossy(&input[line_start..line_end]).into_owned(),
+			file_name,
+		}
+	}
+}
+
+impl Display for Location {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		if let Some(ref file_name) = self.file_name {
+			write!(f, "{}, ", file_name)?;
+		}
+		write!(f, "line {}, column {}: {:?}", self.line, self.column, self.snippet)
+	}
 }
 
 #[derive(Fail, Debug)]
@@ -62,11 +117,30 @@ pub enum ParseError {
 	PartConflict(String),
 	#[fail(display = "Absent part error: {}", _0)]
 	PartAbsent(&'static str),
+	#[fail(display = "Unable to strip {} leading components from the name {:?}", _0, _1)]
+	StripError(usize, String),
+	#[fail(display = "{} at {}", _1, _0)]
+	Located(Location, Box<ParseError>),
 }
 
-impl<I> From<Err<I, u32>> for ParseError {
-	fn from(error: Err<I, u32>) -> Self {
-		ParseError::LexerError(error.into_error_kind())
+impl ParseError {
+	pub fn location(&self) -> Option<&Location> {
+		match *self {
+			ParseError::Located(ref location, _) => Some(location),
+			_ => None
+		}
+	}
+
+	fn at(self, input: &[u8], position: &[u8], file_name: Option<String>) -> ParseError {
+		ParseError::Located(Location::new(input, position, file_name), Box::new(self))
+	}
+
+	fn from_lexer_error(input: &[u8], error: Err<&[u8], u32>, file_name: Option<String>) -> ParseError {
+		let (position, kind) = match error {
+			Err::Incomplete(_) => (&input[input.len()..], ErrorKind::Eof),
+			Err::Error(Context::Code(position, kind)) | Err::Failure(Context::Code(position, kind)) => (position, kind)
+		};
+		ParseError::LexerError(kind).at(input, position, file_name)
 	}
 }
 
@@ -113,6 +187,7 @@ struct Parser {
 	new_index: Option<String>,
 	similarity: Option<u8>,
 	hunks: Vec<Hunk>,
+	indexes_required: bool, // Only git writes the indexes
 }
 
 impl Parser {
@@ -120,7 +195,7 @@ impl Parser {
 		Ok(FileProperties {
 			name: self.old_name.clone().ok_or(ParseError::PartAbsent("Old name"))?,
 			mode: self.old_mode.clone().ok_or(ParseError::PartAbsent("Old mode"))?,
-			index: Some(self.old_index.clone().ok_or(ParseError::PartAbsent("Old index"))?),
+			index: if self.indexes_required { Some(self.old_index.clone().ok_or(ParseError::PartAbsent("Old index"))?) } else { self.old_index.clone() },
 		})
 	}
 
@@ -128,7 +203,7 @@ impl Parser {
 		Ok(FileProperties {
 			name: self.new_name.clone().ok_or(ParseError::PartAbsent("New name"))?,
 			mode: self.new_mode.clone().ok_or(ParseError::PartAbsent("New mode"))?,
-			index: Some(self.new_index.clone().ok_or(ParseError::PartAbsent("New index"))?),
+			index: if self.indexes_required { Some(self.new_index.clone().ok_or(ParseError::PartAbsent("New index"))?) } else { self.new_index.clone() },
 		})
 	}
 }
@@ -144,16 +219,47 @@ enum Operation {
 }
 
 pub fn parse_combined_patch<'a>(input: &'a [u8]) -> Result<Vec<Patch>, ParseError> {
-	let (_, patch_parts_vec) = combined_patch(input)?;
-	patch_parts_vec.into_iter()
-			.map(|patch_parts| patch_from_parts(patch_parts))
-			.collect()
+	git_patches(input, input)
+}
+
+fn git_patches<'a>(input: &'a [u8], position: &'a [u8]) -> Result<Vec<Patch>, ParseError> {
+	let mut patches = Vec::new();
+	let mut rest = position;
+	loop {
+		let (new_rest, patch_parts) = patch(input, rest)?;
+		patches.push(patch_from_parts(input, patch_parts)?);
+
+		rest = new_rest;
+		if rest.is_empty() { break; }
+	}
+	Ok(patches)
+}
+
+// Reads the diffs made by "diff -u", Subversion, "git diff --no-prefix" and the like, skipping the text between them.
+// The strip level is the number of the leading path components to remove from the names, same as for "patch -p".
+pub fn parse_unified_diff(input: &[u8], strip: usize) -> Result<Vec<Patch>, ParseError> {
+	let mut rest = skip_to_file_diff(input);
+	if strip == 1 && rest.starts_with(PATCH_HEADER_TAG) {
+		return git_patches(input, rest);
+	}
+
+	let mut patches = Vec::new();
+	while !rest.is_empty() {
+		let (new_rest, patch) = unified_diff(input, rest, strip)?;
+		patches.push(patch);
+		rest = skip_to_file_diff(new_rest);
+	}
+
+	if patches.is_empty() {
+		return Err(ParseError::PartAbsent("Patch contents").at(input, rest, None));
+	}
+	Ok(patches)
 }
 
 // To get the patch, run "git log --follow -p -1 --format= <file-path>"
 pub fn parse_patch(input: &[u8]) -> Result<Patch, ParseError> {
-	let (_, patch_parts) = patch(input)?;
-	patch_from_parts(patch_parts)
+	let (_, patch_parts) = patch(input, input)?;
+	patch_from_parts(input, patch_parts)
 }
 
 pub fn parse_rejects(input: &[u8]) -> Result<Vec<Hunk>, ParseError> {
@@ -166,11 +272,119 @@ pub fn parse_rejects(input: &[u8]) -> Result<Vec<Hunk>, ParseError> {
 
 	match result {
 		Ok((_, hunks)) => Ok(hunks),
-		Err(err) => Err(err.into())
+		Err(err) => Err(ParseError::from_lexer_error(input, err, None))
+	}
+}
+
+// To get the diffs, run "git diff-tree --cc --no-commit-id -r <merge-commit>"
+pub fn parse_combined_diffs(input: &[u8]) -> Result<Vec<CombinedDiff>, ParseError> {
+	let mut diffs = Vec::new();
+	let mut rest = input;
+	while !rest.is_empty() { // Clean merges have no combined diffs at all
+		let (new_rest, diff) = combined_diff(input, rest)?;
+		diffs.push(diff);
+		rest = new_rest;
+	}
+	Ok(diffs)
+}
+
+fn combined_diff<'a>(input: &'a [u8], position: &'a [u8]) -> Result<(&'a [u8], CombinedDiff), ParseError> {
+	let (mut rest, (dense, name)) = combined_diff_header(position).map_err(|err| ParseError::from_lexer_error(input, err, None))?;
+	let name = String::from_utf8(name).map_err(|err| ParseError::from(err).at(input, position, None))?;
+	let file_name = Some(name.clone());
+
+	let mut diff = CombinedDiff {
+		dense,
+		name,
+		change: CombinedChange::Modification { modes: None },
+		old_indexes: Vec::new(),
+		new_index: String::new(),
+		old_names: Vec::new(),
+		new_name: None,
+		hunks: Vec::new(),
+	};
+
+	while !rest.is_empty() && !rest.starts_with(COMBINED_DIFF_HEADER_TAG) {
+		let (new_rest, part) = combined_diff_part(rest).map_err(|err| ParseError::from_lexer_error(input, err, file_name.clone()))?;
+		parse_combined_diff_part(&mut diff, part).map_err(|err| err.at(input, rest, file_name.clone()))?;
+		rest = new_rest;
+	}
+
+	if diff.old_indexes.is_empty() {
+		return Err(ParseError::PartAbsent("Index").at(input, position, file_name));
+	}
+
+	Ok((rest, diff))
+}
+
+fn parse_combined_diff_part(diff: &mut CombinedDiff, part: CombinedDiffPart) -> Result<(), ParseError> {
+	let to_strings = |values: Vec<&[u8]>| values.into_iter().map(|value| String::from_utf8(value.to_vec())).collect::<Result<Vec<_>, _>>();
+
+	match part {
+		CombinedDiffPart::Index(old_indexes, new_index) => {
+			diff.old_indexes = to_strings(old_indexes)?;
+			diff.new_index = String::from_utf8(new_index.to_vec())?;
+		}
+		CombinedDiffPart::Addition(new_mode) => diff.change = CombinedChange::Addition { new_mode: String::from_utf8(new_mode.to_vec())? },
+		CombinedDiffPart::Removal(old_modes) => diff.change = CombinedChange::Removal { old_modes: to_strings(old_modes)? },
+		CombinedDiffPart::ModeChange(old_modes, new_mode) => diff.change = CombinedChange::Modification {
+			modes: Some((to_strings(old_modes)?, String::from_utf8(new_mode.to_vec())?))
+		},
+		CombinedDiffPart::Name(name, order) => {
+			let name = match name {
+				Some(name) => Some(String::from_utf8(name)?),
+				None => None
+			};
+			match order {
+				Order::Old => diff.old_names.push(name),
+				Order::New => diff.new_name = name
+			}
+		}
+		CombinedDiffPart::Hunk(hunk) => {
+			if hunk.old_file_ranges.len() != diff.old_indexes.len() {
+				return Err(ParseError::PartConflict(format!("The hunk has {} parents instead of {}", hunk.old_file_ranges.len(), diff.old_indexes.len())));
+			}
+			diff.hunks.push(hunk);
+		}
 	}
+	Ok(())
 }
 
-fn patch_from_parts<'a>(PatchParts { names, parts }: PatchParts<'a>) -> Result<Patch, ParseError> {
+// Reads the patch starting at the position, stopping at the header of the next one
+fn patch<'a>(input: &'a [u8], position: &'a [u8]) -> Result<(&'a [u8], PatchParts<'a>), ParseError> {
+	let (mut rest, names) = patch_header(position).map_err(|err| ParseError::from_lexer_error(input, err, None))?;
+
+	let mut parts = Vec::new();
+	while !rest.is_empty() && !rest.starts_with(PATCH_HEADER_TAG) {
+		match patch_part(rest) {
+			Ok((new_rest, part)) => {
+				parts.push((rest, part));
+				rest = new_rest;
+			}
+			Err(err) => return Err(ParseError::from_lexer_error(input, err, file_name_from_parts(&names, &parts)))
+		}
+	}
+
+	if parts.is_empty() {
+		return Err(ParseError::PartAbsent("Patch contents").at(input, rest, file_name_from_parts(&names, &parts)));
+	}
+
+	Ok((rest, PatchParts {
+		start: position,
+		names,
+		parts,
+	}))
+}
+
+fn file_name_from_parts(names: &Option<(Vec<u8>, Vec<u8>)>, parts: &[(&[u8], PatchPart)]) -> Option<String> {
+	let part_name = parts.iter().rev().filter_map(|&(_, ref part)| match *part {
+		PatchPart::Name(Some(ref name), _) | PatchPart::NameChange(ref name, _, _) => Some(name),
+		_ => None
+	}).next();
+	names.as_ref().map(|&(_, ref new_name)| new_name).or(part_name).map(|name| String::from_utf8_lossy(name).into_owned())
+}
+
+fn patch_from_parts<'a>(input: &'a [u8], PatchParts { start, names, parts }: PatchParts<'a>) -> Result<Patch, ParseError> {
 	let mut parser = Parser {
 		old_name: None,
 		new_name: None,
@@ -181,59 +395,73 @@ fn patch_from_parts<'a>(PatchParts { names, parts }: PatchParts<'a>) -> Result<P
 		new_index: None,
 		similarity: None,
 		hunks: Vec::new(),
+		indexes_required: true,
 	};
 
+	let file_name = file_name_from_parts(&names, &parts);
 	if let Some((header_old_name, header_new_name)) = names {
-		parser.old_name = Some(String::from_utf8(header_old_name)?);
-		parser.new_name = Some(String::from_utf8(header_new_name)?);
+		let header_names = String::from_utf8(header_old_name).and_then(|old_name| Ok((old_name, String::from_utf8(header_new_name)?)));
+		let (old_name, new_name) = header_names.map_err(|err| ParseError::from(err).at(input, start, file_name.clone()))?;
+		parser.old_name = Some(old_name);
+		parser.new_name = Some(new_name);
 	}
 
-	for part in parts {
-		match part {
-			PatchPart::Name(name, order) => check_and_update_string_value(name, order, &mut parser.old_name, &mut parser.new_name)?,
-			PatchPart::NameChange(name, change_type, order) => {
-				check_and_update_string_value(name, order, &mut parser.old_name, &mut parser.new_name)?;
+	for (position, part) in parts {
+		parse_part(&mut parser, part).map_err(|err| err.at(input, position, file_name.clone()))?;
+	}
 
-				let parsed_operation = match change_type {
-					NameChangeType::Rename => Operation::Renamed,
-					NameChangeType::Copy => Operation::Copied,
-				};
-				update_if_absent(&mut parser.operation, parsed_operation)?;
-			}
-			PatchPart::PresenceChange { change_type, mode } => {
-				let (parsed_operation, mode_order) = match change_type {
-					PresenceChangeType::Added => (Operation::Added, Order::New),
-					PresenceChangeType::Removed => (Operation::Removed, Order::Old),
-				};
-				update_if_absent(&mut parser.operation, parsed_operation)?;
-				check_and_update_string_value(mode.to_vec(), mode_order, &mut parser.old_mode, &mut parser.new_mode)?;
-			}
-			PatchPart::ModeChange(mode, order) => {
-				check_and_update_string_value(mode.to_vec(), order, &mut parser.old_mode, &mut parser.new_mode)?;
-			}
-			PatchPart::Index { old_index, new_index, mode } => {
-				let old_index_str = String::from_utf8(old_index.to_vec())?;
-				update_if_absent(&mut parser.old_index, old_index_str)?;
+	change_from_parser(parser).map_err(|err| err.at(input, start, file_name))
+}
 
-				let new_index_str = String::from_utf8(new_index.to_vec())?;
-				update_if_absent(&mut parser.new_index, new_index_str)?;
+fn parse_part(parser: &mut Parser, part: PatchPart) -> Result<(), ParseError> {
+	match part {
+		PatchPart::Name(Some(name), order) => check_and_update_string_value(name, order, &mut parser.old_name, &mut parser.new_name)?,
+		PatchPart::Name(None, _) => (),
+		PatchPart::NameChange(name, change_type, order) => {
+			check_and_update_string_value(name, order, &mut parser.old_name, &mut parser.new_name)?;
+
+			let parsed_operation = match change_type {
+				NameChangeType::Rename => Operation::Renamed,
+				NameChangeType::Copy => Operation::Copied,
+			};
+			update_if_absent(&mut parser.operation, parsed_operation)?;
+		}
+		PatchPart::PresenceChange { change_type, mode } => {
+			let (parsed_operation, mode_order) = match change_type {
+				PresenceChangeType::Added => (Operation::Added, Order::New),
+				PresenceChangeType::Removed => (Operation::Removed, Order::Old),
+			};
+			update_if_absent(&mut parser.operation, parsed_operation)?;
+			check_and_update_string_value(mode.to_vec(), mode_order, &mut parser.old_mode, &mut parser.new_mode)?;
+		}
+		PatchPart::ModeChange(mode, order) => {
+			check_and_update_string_value(mode.to_vec(), order, &mut parser.old_mode, &mut parser.new_mode)?;
+		}
+		PatchPart::Index { old_index, new_index, mode } => {
+			let old_index_str = String::from_utf8(old_index.to_vec())?;
+			update_if_absent(&mut parser.old_index, old_index_str)?;
 
-				if let Some(mode_data) = mode {
-					let mode_str = String::from_utf8(mode_data.to_vec())?;
-					update_if_absent(&mut parser.old_mode, mode_str.clone())?;
-					update_if_absent(&mut parser.new_mode, mode_str)?;
-				}
-			}
-			PatchPart::Similarity(similarity) => {
-				update_if_absent(&mut parser.similarity, String::from_utf8(similarity.to_vec())?.parse()?)?;
-			}
-			PatchPart::Dissimilarity(dissimilarity) => {
-				update_if_absent(&mut parser.similarity, 100 - String::from_utf8(dissimilarity.to_vec())?.parse::<u8>()?)?;
+			let new_index_str = String::from_utf8(new_index.to_vec())?;
+			update_if_absent(&mut parser.new_index, new_index_str)?;
+
+			if let Some(mode_data) = mode {
+				let mode_str = String::from_utf8(mode_data.to_vec())?;
+				update_if_absent(&mut parser.old_mode, mode_str.clone())?;
+				update_if_absent(&mut parser.new_mode, mode_str)?;
 			}
-			PatchPart::Hunk(hunk) => parser.hunks.push(hunk)
 		}
+		PatchPart::Similarity(similarity) => {
+			update_if_absent(&mut parser.similarity, String::from_utf8(similarity.to_vec())?.parse()?)?;
+		}
+		PatchPart::Dissimilarity(dissimilarity) => {
+			update_if_absent(&mut parser.similarity, 100 - String::from_utf8(dissimilarity.to_vec())?.parse::<u8>()?)?;
+		}
+		PatchPart::Hunk(hunk) => parser.hunks.push(hunk)
 	}
+	Ok(())
+}
 
+fn change_from_parser(parser: Parser) -> Result<Patch, ParseError> {
 	let operation = parser.operation.clone().unwrap_or(Operation::Edited);
 
 	let change = match operation {
@@ -260,24 +488,209 @@ fn patch_from_parts<'a>(PatchParts { names, parts }: PatchParts<'a>) -> Result<P
 	})
 }
 
-named!(combined_patch<Vec<PatchParts>>, many1!(complete!(patch)));
+fn split_line(input: &[u8]) -> (&[u8], &[u8]) {
+	match input.iter().position(|byte| *byte == b'\n') {
+		Some(position) => (&input[..position], &input[position + 1..]),
+		None => (input, &input[input.len()..])
+	}
+}
+
+fn is_file_diff_start(input: &[u8]) -> bool {
+	input.starts_with(DIFF_HEADER_TAG) || input.starts_with(INDEX_PREAMBLE_TAG) ||
+			input.starts_with(OLD_NAME_TAG) && split_line(input).1.starts_with(NEW_NAME_TAG)
+}
+
+fn skip_to_file_diff(input: &[u8]) -> &[u8] {
+	let mut rest = input;
+	while !rest.is_empty() && !is_file_diff_start(rest) {
+		rest = split_line(rest).1;
+	}
+	rest
+}
+
+fn strip_components(name: Vec<u8>, strip: usize) -> Result<Vec<u8>, ParseError> {
+	let mut stripped_name = &name[..];
+	for _ in 0..strip {
+		match stripped_name.iter().position(|byte| *byte == b'/') {
+			Some(position) => stripped_name = &stripped_name[position + 1..],
+			None => return Err(ParseError::StripError(strip, String::from_utf8_lossy(&name).into_owned()))
+		}
+	}
+	Ok(stripped_name.to_vec())
+}
+
+// The name may be followed by a tab and a timestamp, or a revision in case of Subversion. Some tools separate the timestamp with spaces instead.
+// No name for an absent file.
+fn unified_diff_name(input: &[u8], strip: usize) -> Result<Option<String>, ParseError> {
+	let name = match quoted_name(input) {
+		Ok((_, name)) => name,
+		Err(_) => {
+			let input = if input.ends_with(b"\r") { &input[..input.len() - 1] } else { input };
+			let end = input.iter().position(|byte| *byte == b'\t')
+					.or_else(|| (0..input.len()).find(|position| input[*position] == b' ' && is_timestamp(&input[position + 1..])))
+					.unwrap_or(input.len());
+			input[..end].to_vec()
+		}
+	};
+
+	if name == FILE_NAME_PLACEHOLDER.as_bytes() { return Ok(None); }
+	Ok(Some(String::from_utf8(strip_components(name, strip)?)?))
+}
+
+// Either "2018-10-09 12:34:56.000000000 +0300" of "diff -u" or "Tue Oct  9 12:34:56 2018" of the older tools
+fn is_timestamp(input: &[u8]) -> bool {
+	const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+	const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+	let input = String::from_utf8_lossy(input);
+	let words = input.split_whitespace().collect::<Vec<_>>();
+	let is_number = |word: &str| !word.is_empty() && word.bytes().all(|byte| byte.is_ascii_digit());
+	let is_time = |word: &str| word.contains(':') && word.split(|ch| ch == ':' || ch == '.').all(is_number);
+	let is_date = |word: &str| word.split('-').count() == 3 && word.split('-').all(is_number);
+
+	match words.len() {
+		0 | 1 => false,
+		2 | 3 => is_date(words[0]) && is_time(words[1]),
+		_ => (is_date(words[0]) && is_time(words[1])) ||
+				(WEEKDAYS.contains(&words[0]) && MONTHS.contains(&words[1]) && is_number(words[2]) && is_time(words[3]))
+	}
+}
+
+// Git quotes neither of the names if they don't need it, so the names are only known to be separated by one of the spaces
+fn git_header_name(input: &[u8], strip: usize) -> Option<String> {
+	(0..input.len())
+			.filter(|position| input[*position] == b' ')
+			.filter_map(|position| {
+				let name = strip_components(input[..position].to_vec(), strip).ok()?;
+				let other_name = strip_components(input[position + 1..].to_vec(), strip).ok()?;
+				if name == other_name { String::from_utf8(name).ok() } else { None }
+			})
+			.next()
+}
+
+// Picks the name for a file with different names on the sides, e.g. "f.txt.orig" and "f.txt", same as "patch" does
+fn best_name<'a>(name: &'a String, other_name: &'a String) -> &'a String {
+	let key = |name: &String| (name.matches('/').count(), name.rsplit('/').next().unwrap().len(), name.len());
+	if key(other_name) < key(name) { other_name } else { name }
+}
 
 named!(
-	patch<PatchParts>,
-	do_parse!(
-		names: patch_header >>
-		parts: many1!(complete!(patch_part)) >>
-		(PatchParts {
-			names,
-			parts
-		})
+	extended_header_part<PatchPart>,
+	alt_complete!(
+		similarity |
+		name_change |
+		mode_change |
+		presence_change |
+		index
 	)
 );
 
+fn unified_diff<'a>(input: &'a [u8], position: &'a [u8], strip: usize) -> Result<(&'a [u8], Patch), ParseError> {
+	let mut parser = Parser {
+		old_name: None,
+		new_name: None,
+		operation: None,
+		old_mode: None,
+		new_mode: None,
+		old_index: None,
+		new_index: None,
+		similarity: None,
+		hunks: Vec::new(),
+		indexes_required: false,
+	};
+	let mut rest = position;
+
+	let mut preamble_name = None;
+	if rest.starts_with(INDEX_PREAMBLE_TAG) {
+		let (line, new_rest) = split_line(rest);
+		preamble_name = unified_diff_name(&line[INDEX_PREAMBLE_TAG.len()..], strip).ok().and_then(|name| name);
+		rest = new_rest;
+		if rest.starts_with(b"===") {
+			rest = split_line(rest).1;
+		}
+	}
+
+	let mut header_name = None;
+	if rest.starts_with(PATCH_HEADER_TAG) {
+		let (line, new_rest) = split_line(rest);
+		header_name = git_header_name(&line[PATCH_HEADER_TAG.len()..], strip);
+		rest = new_rest;
+	} else if rest.starts_with(DIFF_HEADER_TAG) {
+		rest = split_line(rest).1;
+	}
+
+	let mut file_name = preamble_name.clone().or(header_name.clone());
+	while !rest.is_empty() && !is_file_diff_start(rest) && !rest.starts_with(HUNK_HEADER_TAG) {
+		let (new_rest, part) = match extended_header_part(rest) {
+			Ok(result) => result,
+			Err(_) => break
+		};
+		parse_part(&mut parser, part).map_err(|err| err.at(input, rest, file_name.clone()))?;
+		rest = new_rest;
+	}
+
+	let mut names = None;
+	if rest.starts_with(OLD_NAME_TAG) {
+		let (old_line, after_old_name) = split_line(rest);
+		let (new_line, after_new_name) = split_line(after_old_name);
+		if !new_line.starts_with(NEW_NAME_TAG) {
+			return Err(ParseError::PartAbsent("New name").at(input, after_old_name, file_name));
+		}
+
+		let old_name = unified_diff_name(&old_line[OLD_NAME_TAG.len()..], strip).map_err(|err| err.at(input, rest, file_name.clone()))?;
+		let new_name = unified_diff_name(&new_line[NEW_NAME_TAG.len()..], strip).map_err(|err| err.at(input, after_old_name, file_name.clone()))?;
+		file_name = new_name.clone().or(old_name.clone()).or(file_name);
+		names = Some((old_name, new_name));
+		rest = after_new_name;
+	}
+
+	while rest.starts_with(HUNK_HEADER_TAG) {
+		let (new_rest, hunk) = hunk(rest).map_err(|err| ParseError::from_lexer_error(input, err, file_name.clone()))?;
+		parser.hunks.push(hunk);
+		rest = new_rest;
+	}
+
+	if parser.operation.is_none() {
+		let from_empty = !parser.hunks.is_empty() && parser.hunks.iter().all(|hunk| hunk.old_file_range == (0..0));
+		let to_empty = !parser.hunks.is_empty() && parser.hunks.iter().all(|hunk| hunk.new_file_range == (0..0));
+		parser.operation = match names {
+			Some((None, _)) => Some(Operation::Added),
+			Some((_, None)) => Some(Operation::Removed),
+			_ if from_empty => Some(Operation::Added),
+			_ if to_empty => Some(Operation::Removed),
+			_ => None
+		};
+	}
+
+	let (old_name, new_name) = names.unwrap_or((None, None));
+	match parser.operation {
+		Some(Operation::Renamed) | Some(Operation::Copied) => (), // The extended header has the names without the prefixes
+		Some(Operation::Added) => parser.new_name = new_name.or(old_name).or(preamble_name).or(header_name),
+		Some(Operation::Removed) => parser.old_name = old_name.or(new_name).or(preamble_name).or(header_name),
+		_ => {
+			let name = match (old_name, new_name) {
+				(Some(old_name), Some(new_name)) => {
+					if old_name == new_name { Some(new_name) } else { preamble_name.or_else(|| Some(best_name(&old_name, &new_name).clone())) }
+				}
+				(old_name, new_name) => new_name.or(old_name).or(preamble_name).or(header_name)
+			};
+			parser.old_name = name.clone();
+			parser.new_name = name;
+		}
+	}
+
+	let (old_mode, new_mode) = (parser.old_mode.clone(), parser.new_mode.clone());
+	parser.old_mode = old_mode.clone().or(new_mode.clone()).or(Some(DEFAULT_MODE.into()));
+	parser.new_mode = new_mode.or(old_mode).or(Some(DEFAULT_MODE.into()));
+
+	let patch = change_from_parser(parser).map_err(|err| err.at(input, position, file_name))?;
+	Ok((rest, patch))
+}
+
 named!(
 	patch_header<Option<(Vec<u8>, Vec<u8>)>>,
 	do_parse!(
-		tag!(b"diff --git ") >>
+		tag!(PATCH_HEADER_TAG) >>
 		names: alt!(
 			do_parse!(
 				name: map_opt!(quoted_name, trim_to_slash_inclusive) >>
@@ -287,7 +700,7 @@ named!(
 				(Some((name, other_name)))
 			) |
 			do_parse!(
-				name: map_opt!(map!(take_until_either!("\"\n"), |name| trim_right(name)), trim_to_slash_inclusive) >>
+				name: map_opt!(map!(take_until_either!("\"\n"), strip_separator), trim_to_slash_inclusive) >>
 				other_name: map_opt!(quoted_name, trim_to_slash_inclusive) >>
 				line_ending >>
 				(Some((name, other_name)))
@@ -306,11 +719,9 @@ fn trim_to_slash_inclusive<'a, I: Into<Cow<'a, [u8]>>>(input: I) -> Option<Vec<u
 	}
 }
 
-fn trim_right(input: &[u8]) -> &[u8] {
-	match input.iter().rposition(|byte| !is_space(*byte)) {
-		Some(position) => &input[..position + 1],
-		None => input
-	}
+// Only the space separating the names is removed, the rest of the whitespace belongs to the name
+fn strip_separator(input: &[u8]) -> &[u8] {
+	if input.ends_with(b" ") { &input[..input.len() - 1] } else { input }
 }
 
 fn matching_name_pair(input: &[u8]) -> IResult<&[u8], Option<(Vec<u8>, Vec<u8>)>> {
@@ -318,21 +729,22 @@ fn matching_name_pair(input: &[u8]) -> IResult<&[u8], Option<(Vec<u8>, Vec<u8>)>
 		Some(position) => position,
 		None => return Err(Err::Incomplete(Needed::Unknown))
 	};
+	let name_end = if line_end > 0 && input[line_end - 1] == b'\r' { line_end - 1 } else { line_end };
 
 	let mut separator_start = 0;
-	while separator_start < line_end {
+	while separator_start < name_end {
 		if !is_space(input[separator_start]) {
 			separator_start += 1;
 			continue;
 		}
 
 		let mut separator_end = separator_start;
-		while separator_end < line_end && is_space(input[separator_end]) {
+		while separator_end < name_end && is_space(input[separator_end]) {
 			separator_end += 1;
 		}
 
 		if let Some(name) = trim_to_slash_inclusive(&input[0..separator_start]) {
-			if let Some(other_name) = trim_to_slash_inclusive(&input[separator_end..line_end]) {
+			if let Some(other_name) = trim_to_slash_inclusive(&input[separator_end..name_end]) {
 				if name == other_name {
 					return Ok((&input[line_end + 1..], Some((name, other_name))));
 				}
@@ -342,7 +754,7 @@ fn matching_name_pair(input: &[u8]) -> IResult<&[u8], Option<(Vec<u8>, Vec<u8>)>
 		separator_start = separator_end + 1;
 	}
 
-	return Ok((&input[line_end..], None));
+	return Ok((&input[line_end + 1..], None));
 }
 
 named!(
@@ -368,7 +780,7 @@ named!(
 			take_while_s!(is_hex_digit)
 		) >>
 		mode: opt!(preceded!(tag!(b" "), take_while!(is_oct_digit))) >>
-		tag!("\n") >>
+		line_ending >>
 		(PatchPart::Index {
 			old_index: hashes.0,
 			new_index: hashes.1,
@@ -382,7 +794,7 @@ named!(
 	do_parse!(
 		order: alt!(value!(Order::Old, tag!("old mode ")) | value!(Order::New, tag!("new mode "))) >>
 		mode: take_while!(is_oct_digit) >>
-		tag!("\n") >>
+		line_ending >>
 		(PatchPart::ModeChange(mode, order))
 	)
 );
@@ -397,7 +809,7 @@ named!(
 			value!((NameChangeType::Copy, Order::New), tag!("copy to "))
 		) >>
 		name: file_name >>
-		tag!("\n") >>
+		line_ending >>
 		(PatchPart::NameChange(name, change_type_and_order.0, change_type_and_order.1))
 	)
 );
@@ -407,7 +819,7 @@ named!(
 	do_parse!(
 		change_type: alt!(value!(PresenceChangeType::Removed, tag!("deleted file mode ")) | value!(PresenceChangeType::Added, tag!("new file mode "))) >>
 		mode: take_while!(is_oct_digit) >>
-		tag!("\n") >>
+		line_ending >>
 		(PatchPart::PresenceChange {
 			change_type: change_type,
 			mode: mode
@@ -417,11 +829,19 @@ named!(
 
 named!(
 	name<PatchPart>,
+	map!(prefixed_name, |(name, order)| PatchPart::Name(name, order))
+);
+
+named!(
+	prefixed_name<(Option<Vec<u8>>, Order)>,
 	do_parse!(
 		order: alt!(value!(Order::Old, tag!("--- ")) | value!(Order::New, tag!("+++ "))) >>
-		name: map_opt!(file_name, trim_to_slash_inclusive) >>
-		tag!("\n") >>
-		(PatchPart::Name(name, order))
+		name: alt!(
+			value!(None, tag!(FILE_NAME_PLACEHOLDER)) |
+			map!(map_opt!(file_name, trim_to_slash_inclusive), Some)
+		) >>
+		line_ending >>
+		((name, order))
 	)
 );
 
@@ -430,34 +850,49 @@ named!(
 	do_parse!(
 		dissimilarity_flag: alt!(value!(false, tag!("similarity index ")) | value!(true, tag!("dissimilarity index "))) >>
 		score: digit >>
-		tag!("\n") >>
-		(if dissimilarity_flag { PatchPart::Similarity(score) } else { PatchPart::Dissimilarity(score) })
+		tag!("%") >>
+		line_ending >>
+		(if dissimilarity_flag { PatchPart::Dissimilarity(score) } else { PatchPart::Similarity(score) })
 	)
 );
 
 named!(
 	hunk<Hunk>,
 	do_parse!(
-		file_ranges: hunk_header >>
-		hunk: apply!(hunk_data, file_ranges.0, file_ranges.1) >>
-		(hunk)
+		header: hunk_header >>
+		hunk: apply!(hunk_data, header.0, header.1) >>
+		({
+			let mut hunk = hunk;
+			hunk.section_heading = header.2;
+			hunk
+		})
 	)
 );
 
 named!(
-	hunk_header<(Range<usize>, Range<usize>)>,
+	hunk_header<(Range<usize>, Range<usize>, Option<String>)>,
 	do_parse!(
 		tag!("@@ -") >>
 		old_file_range: range >>
 		tag!(" +") >>
 		new_file_range: range >>
 		tag!(" @@") >>
-		take_until!("\n") >>
+		section_heading: take_until!("\n") >>
 		line_ending >>
-		((old_file_range, new_file_range))
+		((old_file_range, new_file_range, parse_section_heading(section_heading)))
 	)
 );
 
+fn parse_section_heading(input: &[u8]) -> Option<String> {
+	let input = if input.ends_with(b"\r") { &input[..input.len() - 1] } else { input };
+	let heading = if input.starts_with(b" ") { &input[1..] } else { input };
+	if heading.is_empty() {
+		None
+	} else {
+		Some(String::from_utf8_lossy(heading).into_owned()) // Git truncates long headings, possibly in the middle of a character
+	}
+}
+
 named!(
 	range<Range<usize>>,
 	do_parse!(
@@ -481,60 +916,226 @@ fn hunk_data(input: &[u8], old_file_range: Range<usize>, new_file_range: Range<u
 	let mut new_file_lines_left = new_file_range.end - new_file_range.start;
 
 	let mut rest = input;
-	let mut bytes_consumed_total = 0;
+	let mut lines = Vec::new();
 	while old_file_lines_left > 0 || new_file_lines_left > 0 {
-		let (new_rest, ((old_file_lines_consumed, new_file_lines_consumed), bytes_consumed)) = try_parse!(rest, hunk_line);
-
+		// Once the header is read, the hunk must be complete, so the errors point at the offending line
+		let (new_rest, mut line) = match hunk_line(rest) {
+			Ok(result) => result,
+			Err(Err::Incomplete(_)) => return Err(Err::Failure(Context::Code(rest, ErrorKind::Eof))),
+			Err(Err::Error(context)) | Err(Err::Failure(context)) => return Err(Err::Failure(context))
+		};
 		rest = new_rest;
-		old_file_lines_left -= old_file_lines_consumed;
-		new_file_lines_left -= new_file_lines_consumed;
-		bytes_consumed_total += bytes_consumed;
-
-		if old_file_lines_left == 0 && old_file_lines_consumed > 0 || new_file_lines_left == 0 && new_file_lines_consumed > 0 {
-			let (new_rest, newline_absence_indicator_length) = try_parse!(rest, no_new_line_marker);
-			if let Some(bytes_consumed) = newline_absence_indicator_length {
-				bytes_consumed_total += bytes_consumed;
+
+		let old_file_line_consumed = line.kind != HunkLineKind::Added;
+		let new_file_line_consumed = line.kind != HunkLineKind::Removed;
+		if old_file_line_consumed { old_file_lines_left -= 1; }
+		if new_file_line_consumed { new_file_lines_left -= 1; }
+
+		if old_file_lines_left == 0 && old_file_line_consumed || new_file_lines_left == 0 && new_file_line_consumed {
+			let (new_rest, newline_absent) = try_parse!(rest, no_new_line_marker);
+			if newline_absent {
+				line.content.pop();
+				line.no_newline_at_eof = true;
 			}
 			rest = new_rest;
 		}
+
+		lines.push(line);
 	}
 
-	Ok((&input[bytes_consumed_total..], Hunk {
-		old_file_range,
-		new_file_range,
-		data: input[..bytes_consumed_total].into(),
-	}))
+	Ok((rest, Hunk::new(old_file_range, new_file_range, lines)))
 }
 
 named!(
-	hunk_line<((usize, usize), usize)>,
+	hunk_line<HunkLine>,
 	do_parse!(
-		lines_consumed_in_both_files: switch!(peek!(anychar),
-			'\n' => value!((1, 1)) |
-			' ' => value!((1, 1)) |
-			'-' => value!((1, 0)) |
-			'+' => value!((0, 1))
+		kind_and_prefix_omission: switch!(peek!(anychar),
+			'\n' => value!((HunkLineKind::Context, true)) |
+			'\r' => value!((HunkLineKind::Context, true), peek!(tag!("\r\n"))) |
+			' ' => value!((HunkLineKind::Context, false), anychar) |
+			'-' => value!((HunkLineKind::Removed, false), anychar) |
+			'+' => value!((HunkLineKind::Added, false), anychar)
 		) >>
 		line: take_until!("\n") >>
-		line_ending >>
-		((lines_consumed_in_both_files, line.len() + 1))
+		tag!("\n") >>
+		({
+			let mut content = line.to_vec();
+			content.push(b'\n');
+
+			let mut hunk_line = HunkLine::new(kind_and_prefix_omission.0, content);
+			hunk_line.prefix_omitted = kind_and_prefix_omission.1;
+			hunk_line
+		})
 	)
 );
 
 named!(
-	no_new_line_marker<Option<usize>>,
-	opt!(
-		complete!(
-			do_parse!(
-				tag!("\\") >>
-				line: take_until!("\n") >>
-				line_ending >>
-				(line.len() + 2)
+	no_new_line_marker<bool>,
+	map!(
+		opt!(
+			complete!(
+				do_parse!(
+					tag!("\\") >>
+					take_until!("\n") >>
+					line_ending >>
+					()
+				)
 			)
+		),
+		|marker| marker.is_some()
+	)
+);
+
+named!(
+	combined_diff_header<(bool, Vec<u8>)>,
+	do_parse!(
+		dense: alt!(value!(true, tag!("diff --cc ")) | value!(false, tag!("diff --combined "))) >>
+		name: file_name >>
+		tag!("\n") >>
+		((dense, name))
+	)
+);
+
+named!(
+	combined_diff_part<CombinedDiffPart>,
+	alt_complete!(
+		map!(combined_hunk, CombinedDiffPart::Hunk) |
+		map!(prefixed_name, |(name, order)| CombinedDiffPart::Name(name, order)) |
+		combined_index |
+		combined_mode_change
+	)
+);
+
+named!(
+	combined_index<CombinedDiffPart>,
+	do_parse!(
+		tag!("index ") >>
+		old_indexes: separated_nonempty_list!(tag!(","), take_while1!(is_hex_digit)) >>
+		tag!("..") >>
+		new_index: take_while1!(is_hex_digit) >>
+		tag!("\n") >>
+		(CombinedDiffPart::Index(old_indexes, new_index))
+	)
+);
+
+named!(mode_list<Vec<&[u8]>>, separated_nonempty_list!(tag!(","), take_while1!(is_oct_digit)));
+
+named!(
+	combined_mode_change<CombinedDiffPart>,
+	alt!(
+		do_parse!(
+			tag!("new file mode ") >>
+			mode: take_while1!(is_oct_digit) >>
+			tag!("\n") >>
+			(CombinedDiffPart::Addition(mode))
+		) |
+		do_parse!(
+			tag!("deleted file mode ") >>
+			modes: mode_list >>
+			tag!("\n") >>
+			(CombinedDiffPart::Removal(modes))
+		) |
+		do_parse!(
+			tag!("mode ") >>
+			old_modes: mode_list >>
+			tag!("..") >>
+			new_mode: take_while1!(is_oct_digit) >>
+			tag!("\n") >>
+			(CombinedDiffPart::ModeChange(old_modes, new_mode))
 		)
 	)
 );
 
+named!(
+	combined_hunk<CombinedHunk>,
+	do_parse!(
+		header: combined_hunk_header >>
+		lines: apply!(combined_hunk_data, &header.0, &header.1) >>
+		(CombinedHunk {
+			old_file_ranges: header.0,
+			new_file_range: header.1,
+			section_heading: header.2,
+			lines
+		})
+	)
+);
+
+// There is one more marker than there are parents, e.g. "@@@ -1,5 -1,5 +1,6 @@@" for two parents
+named!(
+	combined_hunk_header<(Vec<Range<usize>>, Range<usize>, Option<String>)>,
+	do_parse!(
+		markers: verify!(take_while!(|byte| byte == b'@'), |markers: &[u8]| markers.len() > 2) >>
+		old_file_ranges: many_m_n!(markers.len() - 1, markers.len() - 1, preceded!(tag!(" -"), combined_range)) >>
+		tag!(" +") >>
+		new_file_range: combined_range >>
+		tag!(" ") >>
+		tag!(markers) >>
+		section_heading: take_until!("\n") >>
+		tag!("\n") >>
+		((old_file_ranges, new_file_range, parse_section_heading(section_heading)))
+	)
+);
+
+// Git writes the empty ranges with the start of the line after the position
+named!(
+	combined_range<Range<usize>>,
+	map!(range, |range: Range<usize>| if range.start == range.end {
+		let start = range.start.saturating_sub(1);
+		start..start
+	} else {
+		range
+	})
+);
+
+fn combined_hunk_data<'a>(input: &'a [u8], old_file_ranges: &[Range<usize>], new_file_range: &Range<usize>) -> IResult<&'a [u8], Vec<CombinedHunkLine>> {
+	let parent_count = old_file_ranges.len();
+	let mut old_file_lines_left = old_file_ranges.iter().map(|range| range.end - range.start).collect::<Vec<_>>();
+	let mut new_file_lines_left = new_file_range.end - new_file_range.start;
+
+	let mut rest = input;
+	let mut lines = Vec::new();
+	while new_file_lines_left > 0 || old_file_lines_left.iter().any(|lines_left| *lines_left > 0) {
+		let line_end = match rest.iter().position(|byte| *byte == b'\n') {
+			Some(position) => position,
+			None => return Err(Err::Failure(Context::Code(rest, ErrorKind::Eof)))
+		};
+		if line_end < parent_count {
+			return Err(Err::Failure(Context::Code(rest, ErrorKind::Char)));
+		}
+
+		let kinds = rest[..parent_count].iter()
+				.map(|prefix| match *prefix {
+					b' ' => Some(HunkLineKind::Context),
+					b'+' => Some(HunkLineKind::Added),
+					b'-' => Some(HunkLineKind::Removed),
+					_ => None
+				})
+				.collect::<Option<Vec<_>>>();
+		let line = match kinds {
+			Some(ref kinds) if !(kinds.contains(&HunkLineKind::Added) && kinds.contains(&HunkLineKind::Removed)) => CombinedHunkLine {
+				kinds: kinds.clone(),
+				content: rest[parent_count..line_end + 1].to_vec(),
+			},
+			_ => return Err(Err::Failure(Context::Code(rest, ErrorKind::Char)))
+		};
+
+		// A removed line is only present in the parents it's removed from, other lines are in the result and in the parents having them
+		let removed = line.is_removed();
+		if !removed {
+			new_file_lines_left = new_file_lines_left.checked_sub(1).ok_or(Err::Failure(Context::Code(rest, ErrorKind::Verify)))?;
+		}
+		let parent_kind = if removed { HunkLineKind::Removed } else { HunkLineKind::Context };
+		for (lines_left, _) in old_file_lines_left.iter_mut().zip(&line.kinds).filter(|&(_, kind)| *kind == parent_kind) {
+			*lines_left = lines_left.checked_sub(1).ok_or(Err::Failure(Context::Code(rest, ErrorKind::Verify)))?;
+		}
+
+		lines.push(line);
+		rest = &rest[line_end + 1..];
+	}
+
+	Ok((rest, lines))
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -554,6 +1155,16 @@ mod test {
 		match_name(b"diff --git a/gradle.properties b/gradle.properties\n", b"gradle.properties");
 	}
 
+	#[test]
+	fn test_patch_header_with_different_names() {
+		assert_eq!(patch_header(b"diff --git a/f.txt b/h.txt\nrename from f.txt\n"), Ok((&b"rename from f.txt\n"[..], None)));
+	}
+
+	#[test]
+	fn test_patch_header_trailing_space() {
+		match_name(b"diff --git a/trailing  \"b/trailing \"\n", b"trailing ");
+	}
+
 	#[test]
 	fn test_unquote() {
 		assert_eq!(quoted_name(br#""Test""#), Ok((&b""[..], (&b"Test"[..]).into())));
@@ -564,7 +1175,8 @@ mod test {
 	#[test]
 	fn test_hunk_header() {
 		let header = b"@@ -14,4 +8,4 @@ org.gradle.jvmargs=-Xmx1536m\n";
-		assert_eq!(hunk_header(header), Ok((&b""[..], (14..18, 8..12))));
+		assert_eq!(hunk_header(header), Ok((&b""[..], (14..18, 8..12, Some("org.gradle.jvmargs=-Xmx1536m".into())))));
+		assert_eq!(hunk_header(b"@@ -1 +1 @@\n"), Ok((&b""[..], (1..2, 1..2, None))));
 	}
 
 	#[test]
@@ -573,15 +1185,34 @@ mod test {
 		assert_eq!(range(b"14 "), Ok((&b" "[..], 14..15)));
 	}
 
-	fn match_line(line: &[u8], old_file_lines_consumed: usize, new_file_lines_consumed: usize) {
-		assert_eq!(hunk_line(line), Ok((&b""[..], ((old_file_lines_consumed, new_file_lines_consumed), line.len()))));
+	fn match_line(line: &[u8], kind: HunkLineKind, content: &[u8]) {
+		assert_eq!(hunk_line(line), Ok((&b""[..], HunkLine::new(kind, content.to_vec()))));
 	}
 
 	#[test]
 	fn test_hunk_line() {
-		match_line(b" # When configured, Gradle will run in incubating parallel mode.\n", 1, 1);
-		match_line(b"-# org.gradle.parallel=true\n", 1, 0);
-		match_line(b"+org.gradle.parallel=true\n", 0, 1);
+		match_line(b" # When configured, Gradle will run in incubating parallel mode.\n", HunkLineKind::Context,
+			b"# When configured, Gradle will run in incubating parallel mode.\n");
+		match_line(b"-# org.gradle.parallel=true\n", HunkLineKind::Removed, b"# org.gradle.parallel=true\n");
+		match_line(b"+org.gradle.parallel=true\n", HunkLineKind::Added, b"org.gradle.parallel=true\n");
+	}
+
+	#[test]
+	fn test_hunk_line_numbers() {
+		let (_, hunk) = hunk(&**PATCH_DATA_HUNK_2).unwrap();
+		let line_numbers = hunk.lines.iter().map(|line| (line.old_line_number, line.new_line_number)).collect::<Vec<_>>();
+		assert_eq!(line_numbers, vec![(Some(14), Some(8)), (Some(15), Some(9)), (Some(16), Some(10)), (Some(17), None), (None, Some(11))]);
+	}
+
+	#[test]
+	fn test_hunk_no_new_line() {
+		let hunk_data = [PATCH_DATA_NO_NEW_LINES_HUNK_HEADER, PATCH_DATA_NO_NEW_LINES_HUNK_CONTENTS].concat();
+		let (_, hunk) = hunk(&hunk_data).unwrap();
+		assert!(hunk.lines.iter().all(|line| line.no_newline_at_eof && !line.content.ends_with(b"\n")));
+
+		let mut buf = Vec::new();
+		hunk.write(&mut buf).unwrap();
+		assert_eq!(buf, hunk_data);
 	}
 
 	#[test]
@@ -618,15 +1249,358 @@ mod test {
 		assert_eq!(result, *PATCH_NO_NEW_LINES);
 	}
 
+	#[test]
+	fn test_parse_patch_addition_with_contents() {
+		let result = parse_patch(b"diff --git a/f.txt b/f.txt
+new file mode 100644
+index 0000000..e97f8c1
+--- /dev/null
++++ b/f.txt
+@@ -0,0 +1 @@
++f 1
+").unwrap();
+		match result.change {
+			Change::Addition { ref new_properties } => assert_eq!(new_properties.name, "f.txt"),
+			_ => panic!("The patch is supposed to be an addition")
+		}
+		assert_eq!(result.hunks.len(), 1);
+	}
+
+	#[test]
+	fn test_parse_patch_rename() {
+		let result = parse_patch(b"diff --git a/f.txt b/h.txt
+similarity index 84%
+rename from f.txt
+rename to h.txt
+index de69fc1..687e2b4 100644
+--- a/f.txt
++++ b/h.txt
+@@ -9 +9 @@ f 8
+-f 9
++f nine
+").unwrap();
+		match result.change {
+			Change::Modification { modification_type: ModificationType::Renamed { similarity }, ref old_properties, ref new_properties } => {
+				assert_eq!(similarity, Some(84));
+				assert_eq!(old_properties.name, "f.txt");
+				assert_eq!(new_properties.name, "h.txt");
+			}
+			_ => panic!("The patch is supposed to be a rename")
+		}
+	}
+
+	#[test]
+	fn test_parse_patch_dissimilarity() {
+		let result = parse_patch(b"diff --git a/f.txt b/h.txt
+dissimilarity index 16%
+rename from f.txt
+rename to h.txt
+index de69fc1..687e2b4 100644
+--- a/f.txt
++++ b/h.txt
+@@ -9 +9 @@ f 8
+-f 9
++f nine
+").unwrap();
+		match result.change {
+			Change::Modification { modification_type: ModificationType::Renamed { similarity }, .. } => assert_eq!(similarity, Some(84)),
+			_ => panic!("The patch is supposed to be a rename")
+		}
+	}
+
 	#[test]
 	fn test_parse_combined_patch() {
 		let result = parse_combined_patch(&*COMBINED_PATCH_DATA).unwrap();
 		assert_eq!(result.iter().collect::<Vec<&Patch>>(), *COMBINED_PATCH);
 	}
 
+	fn assert_round_trip(data: &[u8]) {
+		let mut buf = Vec::new();
+		for patch in parse_combined_patch(data).unwrap() {
+			patch.write(&mut buf).unwrap();
+		}
+		assert_eq!(String::from_utf8_lossy(&buf), String::from_utf8_lossy(data));
+	}
+
+	#[test]
+	fn test_round_trip_crlf() {
+		assert_round_trip(b"diff --git a/crlf.txt b/crlf.txt
+index e1587ff..d48bcb7 100644
+--- a/crlf.txt
++++ b/crlf.txt
+@@ -1,3 +1,3 @@
+ one\r
+-two\r
++2\r
+ three\r
+");
+	}
+
+	#[test]
+	fn test_parse_patch_crlf_headers() {
+		let rename_data = b"diff --git a/f.txt b/h.txt\nsimilarity index 84%\nrename from f.txt\nrename to h.txt\nindex e97f8c1..37f355d 100644\n\
+			--- a/f.txt\n+++ b/h.txt\n@@ -1,2 +1,2 @@\n f 1\n-f 2\n+f two\n";
+		let edit_data = b"diff --git a/f.txt b/f.txt\nold mode 100644\nnew mode 100755\nindex e97f8c1..37f355d\n\
+			--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,2 @@\n f 1\n-f 2\n+f two\n";
+
+		for data in &[&rename_data[..], &edit_data[..]] {
+			let crlf_data = String::from_utf8_lossy(data).replace("\n", "\r\n");
+			let patch = parse_patch(crlf_data.as_bytes()).unwrap();
+			assert_eq!(patch.change, parse_patch(data).unwrap().change);
+			assert_eq!(patch.hunks[0].section_heading, None);
+			assert_eq!(patch.hunks[0].lines[0].content, b"f 1\r\n");
+		}
+	}
+
+	#[test]
+	fn test_round_trip_mixed_line_endings() {
+		let data = b"diff --git a/mixed file.txt b/mixed file.txt
+index 66ad5d0..2dce48b 100644
+--- a/mixed file.txt\t
++++ b/mixed file.txt\t
+@@ -1,4 +1,4 @@
+ one
+ two\r
+-three\rstill three
+-four
+\\ No newline at end of file
++three\rstill 3
++four\r
+\\ No newline at end of file
+";
+		assert_round_trip(data);
+
+		let patch = parse_patch(data).unwrap();
+		let contents = patch.hunks[0].lines.iter().map(|line| &line.content[..]).collect::<Vec<_>>();
+		assert_eq!(contents, vec![&b"one\n"[..], b"two\r\n", b"three\rstill three\n", b"four", b"three\rstill 3\n", b"four\r"]);
+	}
+
+	#[test]
+	fn test_round_trip_rename_and_addition() {
+		assert_round_trip(b"diff --git a/f.txt b/h.txt
+similarity index 84%
+rename from f.txt
+rename to h.txt
+index de69fc1..687e2b4 100644
+--- a/f.txt
++++ b/h.txt
+@@ -9 +9 @@ f 8
+-f 9
++f nine
+diff --git a/g.txt b/g.txt
+new file mode 100644
+index 0000000..fde5c9b
+--- /dev/null
++++ b/g.txt
+@@ -0,0 +1 @@
++g 1
+\\ No newline at end of file
+");
+	}
+
+	fn error_location(result: Result<Vec<Patch>, ParseError>) -> Location {
+		result.unwrap_err().location().unwrap().clone()
+	}
+
+	#[test]
+	fn test_parse_error_location() {
+		let location = error_location(parse_combined_patch(b"diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1 +1 @@
+-f 1
++f one
+diff --git a/g.txt b/g.txt
+index 3724560..fd218d3 100644
+--- a/g.txt
++++ b/g.txt
+@@ -1,2 +1,2 @@
+ g 1
+*g 2
++g two
+"));
+		assert_eq!(location, Location {
+			offset: 207,
+			line: 14,
+			column: 1,
+			snippet: "*g 2".into(),
+			file_name: Some("g.txt".into()),
+		});
+	}
+
+	#[test]
+	fn test_parse_conflict_location() {
+		let location = error_location(parse_combined_patch(b"diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/g.txt
++++ b/f.txt
+@@ -1 +1 @@
+-f 1
++f one
+"));
+		assert_eq!((location.line, location.column), (3, 1));
+		assert_eq!(location.snippet, "--- a/g.txt");
+		assert_eq!(location.file_name, Some("f.txt".into()));
+	}
+
+	#[test]
+	fn test_parse_truncated_hunk_location() {
+		let data = b"diff --git a/f.txt b/f.txt
+index e97f8c1..de69fc1 100644
+--- a/f.txt
++++ b/f.txt
+@@ -1,3 +1,3 @@
+ f 1
+-f 2
++f two
+";
+		let location = error_location(parse_combined_patch(data));
+		assert_eq!(location.offset, data.len());
+		assert_eq!((location.line, location.column), (9, 1));
+		assert_eq!(location.snippet, "");
+	}
+
 	#[test]
 	fn test_parse_rejects() {
 		let result = parse_rejects(&*REJECTS_DATA).unwrap();
 		assert_eq!(result, *REJECTED_HUNKS);
 	}
+
+	fn patch_summary(patch: &Patch) -> (String, String, Option<String>, usize) {
+		let (operation, properties) = match patch.change {
+			Change::Addition { ref new_properties } => ("added", new_properties),
+			Change::Removal { ref old_properties } => ("removed", old_properties),
+			Change::Modification { ref new_properties, .. } => ("modified", new_properties)
+		};
+		(operation.into(), properties.name.clone(), properties.index.clone(), patch.hunks.len())
+	}
+
+	#[test]
+	fn test_parse_unified_diff_recursive() {
+		let data = b"Only in old: extra.txt
+diff -ruN old/src/f.txt new/src/f.txt
+--- old/src/f.txt	2018-10-18 18:43:37.725053261 +0000
++++ new/src/f.txt	2018-10-18 18:43:37.725053261 +0000
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+diff -ruN old/src/g.txt new/src/g.txt
+--- old/src/g.txt	2018-10-18 18:43:37.725053261 +0000
++++ new/src/g.txt	1970-01-01 00:00:00.000000000 +0000
+@@ -1 +0,0 @@
+-gone
+diff -ruN old/src/n.txt new/src/n.txt
+--- old/src/n.txt	1970-01-01 00:00:00.000000000 +0000
++++ new/src/n.txt	2018-10-18 18:43:37.725053261 +0000
+@@ -0,0 +1,2 @@
++n 1
++n 2
+";
+		let summaries = parse_unified_diff(data, 1).unwrap().iter().map(patch_summary).collect::<Vec<_>>();
+		assert_eq!(summaries, vec![
+			("modified".into(), "src/f.txt".into(), None, 1),
+			("removed".into(), "src/g.txt".into(), None, 1),
+			("added".into(), "src/n.txt".into(), None, 1)
+		]);
+
+		let patches = parse_unified_diff(data, 2).unwrap();
+		assert_eq!(patch_summary(&patches[0]).1, "f.txt");
+
+		match parse_unified_diff(data, 3) {
+			Err(ParseError::Located(location, err)) => match *err {
+				ParseError::StripError(3, _) => assert_eq!(location.line, 3),
+				err => panic!("Unexpected error: {:?}", err)
+			},
+			result => panic!("Unexpected result: {:?}", result)
+		}
+	}
+
+	#[test]
+	fn test_parse_unified_diff_no_prefix() {
+		let data = b"diff --git f.txt f.txt
+index de98044..7be73ce 100644
+--- f.txt
++++ f.txt
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+diff --git m.sh m.sh
+old mode 100644
+new mode 100755
+diff --git n.txt n.txt
+new file mode 100644
+index 0000000..8ba3a16
+--- /dev/null
++++ n.txt
+@@ -0,0 +1 @@
++n
+";
+		let patches = parse_unified_diff(data, 0).unwrap();
+		assert_eq!(patches.iter().map(patch_summary).collect::<Vec<_>>(), vec![
+			("modified".into(), "f.txt".into(), Some("7be73ce".into()), 1),
+			("modified".into(), "m.sh".into(), None, 0),
+			("added".into(), "n.txt".into(), Some("8ba3a16".into()), 1)
+		]);
+		match patches[1].change {
+			Change::Modification { ref old_properties, ref new_properties, .. } => {
+				assert_eq!((old_properties.mode.as_str(), new_properties.mode.as_str()), ("100644", "100755"));
+			}
+			ref change => panic!("Unexpected change: {:?}", change)
+		}
+
+		// The regular git diffs go through the same parser as the combined patches
+		let git_data = b"diff --git a/f.txt b/f.txt\nindex de98044..7be73ce 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -2 +2 @@\n-b\n+B\n";
+		assert_eq!(parse_unified_diff(git_data, 1).unwrap(), parse_combined_patch(git_data).unwrap());
+	}
+
+	#[test]
+	fn test_parse_unified_diff_index_preamble() {
+		let data = b"Index: trunk/f.txt
+===================================================================
+--- trunk/f.txt.orig	(revision 1)
++++ trunk/f.txt	(working copy)
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+";
+		let summaries = parse_unified_diff(data, 1).unwrap().iter().map(patch_summary).collect::<Vec<_>>();
+		assert_eq!(summaries, vec![("modified".into(), "f.txt".into(), None, 1)]);
+
+		let data = b"--- f.txt.orig\n+++ f.txt\n@@ -1 +1 @@\n-x\n+y\n";
+		let summaries = parse_unified_diff(data, 0).unwrap().iter().map(patch_summary).collect::<Vec<_>>();
+		assert_eq!(summaries, vec![("modified".into(), "f.txt".into(), None, 1)]);
+	}
+
+	#[test]
+	fn test_parse_unified_diff_space_separated_timestamps() {
+		let data = b"--- old/my file.txt 2018-10-18 18:43:37.725053261 +0000
++++ new/my file.txt  2018-10-18 18:43:37 +0000
+@@ -1 +1 @@
+-x
++y
+--- old/g.txt Thu Oct 18 18:43:37 2018
++++ new/g.txt Thu Oct 18 18:43:37 2018
+@@ -1 +1 @@
+-x
++y
+--- old/h 2.txt\r
++++ new/h 2.txt\r
+@@ -1 +1 @@
+-x
++y
+";
+		let summaries = parse_unified_diff(data, 1).unwrap().iter().map(patch_summary).collect::<Vec<_>>();
+		assert_eq!(summaries, vec![
+			("modified".into(), "my file.txt".into(), None, 1),
+			("modified".into(), "g.txt".into(), None, 1),
+			("modified".into(), "h 2.txt".into(), None, 1)
+		]);
+	}
 }
@@ -1,32 +1,388 @@
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
 use std::ops::Range;
 use std::io::{Write, Error};
 use std::borrow::{Borrow, Cow};
 
-const FILE_NAME_PLACEHOLDER: &str = "/dev/null";
+use super::patch_id::{patch_id, PatchId};
+use super::diffstat::FileStat;
+use super::diff::{diff_patch, DiffOptions};
 
-fn do_ranges_overlap(range: &Range<usize>, other_range: &Range<usize>) -> bool {
-	range.start < other_range.end && range.end > other_range.start
+pub const FILE_NAME_PLACEHOLDER: &str = "/dev/null";
+const NO_NEWLINE_MARKER: &[u8] = b"\n\\ No newline at end of file\n";
+
+// For empty ranges the start is the line after which the lines are inserted, so it's one less than the first line position
+pub fn range_begin(range: &Range<usize>) -> usize {
+	if range.start == range.end { range.start + 1 } else { range.start }
 }
 
-fn check_overlaps(hunks: &[Hunk], other_hunks: &[Hunk]) -> bool {
-	for hunk in hunks {
-		let overlaps_found = other_hunks.iter().any(|&Hunk { ref old_file_range, ref new_file_range, .. }| {
-			do_ranges_overlap(old_file_range, &hunk.old_file_range) || do_ranges_overlap(new_file_range, &hunk.new_file_range)
-		});
+pub fn range_from_begin(begin: usize, length: usize) -> Range<usize> {
+	let start = if length == 0 { begin - 1 } else { begin };
+	start..start + length
+}
+
+// One side of the hunk: the lines present in that side of the file, and the lines of the other side located before each of them
+struct HunkSide<'a> {
+	hunk: &'a Hunk,
+	begin: usize,
+	lines: Vec<&'a HunkLine>,
+	other_side_lines: Vec<Vec<&'a HunkLine>>,
+}
+
+impl<'a> HunkSide<'a> {
+	fn old_file_side(hunk: &'a Hunk) -> HunkSide<'a> {
+		HunkSide::new(hunk, &hunk.old_file_range, HunkLineKind::Added)
+	}
+
+	fn new_file_side(hunk: &'a Hunk) -> HunkSide<'a> {
+		HunkSide::new(hunk, &hunk.new_file_range, HunkLineKind::Removed)
+	}
+
+	fn new(hunk: &'a Hunk, range: &Range<usize>, other_side_kind: HunkLineKind) -> HunkSide<'a> {
+		let mut side = HunkSide {
+			hunk,
+			begin: range_begin(range),
+			lines: Vec::new(),
+			other_side_lines: vec![Vec::new()],
+		};
+
+		for line in &hunk.lines {
+			if line.kind == other_side_kind {
+				side.other_side_lines.last_mut().unwrap().push(line);
+			} else {
+				side.lines.push(line);
+				side.other_side_lines.push(Vec::new());
+			}
+		}
+
+		side
+	}
+
+	fn end(&self) -> usize {
+		self.begin + self.lines.len()
+	}
+
+	fn line_at(&self, position: usize) -> Option<&'a HunkLine> {
+		if position < self.begin || position >= self.end() { return None; }
+		Some(self.lines[position - self.begin])
+	}
+
+	fn other_side_lines_at(&self, position: usize) -> &[&'a HunkLine] {
+		if position < self.begin || position > self.end() { return &[]; }
+		&self.other_side_lines[position - self.begin]
+	}
+
+}
+
+fn have_same_content(line: &HunkLine, other_line: &HunkLine) -> bool {
+	line.content == other_line.content && line.no_newline_at_eof == other_line.no_newline_at_eof
+}
+
+fn line_with_kind(line: &HunkLine, kind: HunkLineKind) -> HunkLine {
+	let mut line = line.clone();
+	line.kind = kind;
+	line.prefix_omitted = line.prefix_omitted && kind == HunkLineKind::Context;
+	line
+}
+
+fn merge_two_hunks(hunk: &Hunk, other_hunk: &Hunk) -> Result<Hunk, OverlappingHunkError> {
+	let lines = HunkSide::old_file_side(hunk);
+	let other_lines = HunkSide::old_file_side(other_hunk);
+
+	let begin = min(lines.begin, other_lines.begin);
+	let end = max(lines.end(), other_lines.end());
+
+	let mut merged_lines = Vec::new();
+	for position in begin..end + 1 {
+		for &inserted_line in lines.other_side_lines_at(position).iter().chain(other_lines.other_side_lines_at(position)) {
+			merged_lines.push(inserted_line.clone());
+		}
+
+		if position == end { break; }
+
+		let line = match (lines.line_at(position), other_lines.line_at(position)) {
+			(Some(line), Some(other_line)) => {
+				if !have_same_content(line, other_line) { return Err(OverlappingHunkError); }
+				if other_line.kind == HunkLineKind::Removed { other_line } else { line }
+			}
+			(Some(line), None) | (None, Some(line)) => line,
+			(None, None) => return Err(OverlappingHunkError) // There's a gap between the hunks
+		};
+		merged_lines.push(line.clone());
+	}
+
+	let new_file_length = merged_lines.iter().filter(|line| line.kind != HunkLineKind::Removed).count();
+	let mut merged_hunk = Hunk::new(range_from_begin(begin, end - begin), range_from_begin(range_begin(&hunk.new_file_range), new_file_length),
+		merged_lines);
+	merged_hunk.section_heading = hunk.section_heading.clone();
+	Ok(merged_hunk)
+}
+
+// The second hunks should apply to the file produced by the first ones. Positions are in the lines of that intermediate file.
+fn compose_hunks(first_hunks: &[Hunk], second_hunks: &[Hunk]) -> Result<Vec<Hunk>, CompositionError> {
+	let first_sides = first_hunks.iter().map(HunkSide::new_file_side).collect::<Vec<_>>();
+	let second_sides = second_hunks.iter().map(HunkSide::old_file_side).collect::<Vec<_>>();
+
+	let context_length = first_hunks.iter().chain(second_hunks.iter()).map(context_length).max().unwrap_or(0);
+
+	let mut hunks = Vec::new();
+	let mut first_offset = 0isize;
+	let (mut first_pos, mut second_pos) = (0, 0);
+	while first_pos < first_sides.len() || second_pos < second_sides.len() {
+		let begin = match (first_sides.get(first_pos), second_sides.get(second_pos)) {
+			(Some(first_side), Some(second_side)) => min(first_side.begin, second_side.begin),
+			(Some(side), None) | (None, Some(side)) => side.begin,
+			(None, None) => unreachable!()
+		};
+
+		let mut cluster_first_sides = Vec::new();
+		let mut cluster_second_sides = Vec::new();
+		let mut end = begin;
+		loop {
+			if first_pos < first_sides.len() && first_sides[first_pos].begin <= end {
+				end = max(end, first_sides[first_pos].end());
+				cluster_first_sides.push(&first_sides[first_pos]);
+				first_pos += 1;
+			} else if second_pos < second_sides.len() && second_sides[second_pos].begin <= end {
+				end = max(end, second_sides[second_pos].end());
+				cluster_second_sides.push(&second_sides[second_pos]);
+				second_pos += 1;
+			} else {
+				break;
+			}
+		}
+
+		let mut lines = Vec::new();
+		for position in begin..end + 1 {
+			for side in &cluster_first_sides {
+				lines.extend(side.other_side_lines_at(position).iter().map(|line| line_with_kind(line, HunkLineKind::Removed)));
+			}
+			for side in &cluster_second_sides {
+				lines.extend(side.other_side_lines_at(position).iter().map(|line| line_with_kind(line, HunkLineKind::Added)));
+			}
+
+			if position == end { break; }
+
+			let first_line = cluster_first_sides.iter().filter_map(|side| side.line_at(position)).next();
+			let second_line = cluster_second_sides.iter().filter_map(|side| side.line_at(position)).next();
+			let line = match (first_line, second_line) {
+				(Some(first_line), Some(second_line)) => {
+					if !have_same_content(first_line, second_line) { return Err(CompositionError::MismatchingHunks); }
+					first_line
+				}
+				(Some(line), None) | (None, Some(line)) => line,
+				(None, None) => unreachable!()
+			};
+
+			let present_before = first_line.map_or(true, |line| line.kind == HunkLineKind::Context);
+			let present_after = second_line.map_or(true, |line| line.kind == HunkLineKind::Context);
+			match (present_before, present_after) {
+				(true, true) => lines.push(line_with_kind(line, HunkLineKind::Context)),
+				(true, false) => lines.push(line_with_kind(line, HunkLineKind::Removed)),
+				(false, true) => lines.push(line_with_kind(line, HunkLineKind::Added)),
+				(false, false) => ()
+			}
+		}
+
+		let mut old_file_begin = (begin as isize - first_offset) as usize;
+		first_offset += cluster_first_sides.iter().map(|side| length_difference(side.hunk)).sum::<isize>();
+
+		// The second patch may revert what the first one did
+		let old_lines = lines.iter().filter(|line| line.kind != HunkLineKind::Added);
+		let new_lines = lines.iter().filter(|line| line.kind != HunkLineKind::Removed).collect::<Vec<_>>();
+		if old_lines.clone().count() == new_lines.len() && old_lines.zip(new_lines).all(|(old_line, new_line)| have_same_content(old_line, new_line)) { continue; }
+
+		// Both patches bring their own context, so the cluster may have more of it than a regular diff would
+		let leading_context = lines.iter().take_while(|line| line.kind == HunkLineKind::Context).count();
+		let trailing_context = lines.iter().rev().take_while(|line| line.kind == HunkLineKind::Context).count();
+		let lines_count = lines.len();
+		lines.truncate(lines_count - trailing_context.saturating_sub(context_length));
+		let trimmed_context = leading_context.saturating_sub(context_length);
+		lines.drain(..trimmed_context);
+		old_file_begin += trimmed_context;
+
+		let old_file_length = lines.iter().filter(|line| line.kind != HunkLineKind::Added).count();
+		let new_file_length = lines.iter().filter(|line| line.kind != HunkLineKind::Removed).count();
+		let mut hunk = Hunk::new(range_from_begin(old_file_begin, old_file_length), range_from_begin(old_file_begin, new_file_length), lines);
+		// A heading only describes the hunk that starts at the same line
+		hunk.section_heading = cluster_first_sides.iter().chain(cluster_second_sides.iter())
+				.filter(|side| side.begin == begin + trimmed_context)
+				.filter_map(|side| side.hunk.section_heading.clone())
+				.next();
+		hunks.push(hunk);
+	}
+
+	merge_hunks(hunks).map_err(|_| CompositionError::MismatchingHunks)
+}
+
+// Returns the second hunks rewritten for the original file and the first hunks rewritten to apply on top of them
+fn commute_hunks(first_hunks: &[Hunk], second_hunks: &[Hunk]) -> Result<(Vec<Hunk>, Vec<Hunk>), CommutationError> {
+	let first_changes = first_hunks.iter().map(|hunk| changed_lines(hunk, &hunk.new_file_range, HunkLineKind::Removed)).collect::<Vec<_>>();
+	let second_changes = second_hunks.iter().map(|hunk| changed_lines(hunk, &hunk.old_file_range, HunkLineKind::Added)).collect::<Vec<_>>();
+
+	// A hunk can't be moved if its context refers to the lines changed by the other patch
+	for (first_hunk, first_changed_lines) in first_hunks.iter().zip(&first_changes) {
+		for (second_hunk, second_changed_lines) in second_hunks.iter().zip(&second_changes) {
+			if ranges_interfere(&begin_based_range(&first_hunk.new_file_range), second_changed_lines)
+					|| ranges_interfere(&begin_based_range(&second_hunk.old_file_range), first_changed_lines) {
+				return Err(CommutationError::OverlappingHunks);
+			}
+		}
+	}
+
+	let new_second_hunks = second_hunks.iter()
+			.zip(&second_changes)
+			.map(|(hunk, changed_lines)| {
+				let offset = first_hunks.iter()
+						.zip(&first_changes)
+						.filter(|&(_, first_changed_lines)| first_changed_lines.start < changed_lines.start)
+						.map(|(first_hunk, _)| -length_difference(first_hunk))
+						.sum();
+				shift_hunk(hunk, offset)
+			})
+			.collect();
+
+	let new_first_hunks = first_hunks.iter()
+			.zip(&first_changes)
+			.map(|(hunk, changed_lines)| {
+				let offset = second_hunks.iter()
+						.zip(&second_changes)
+						.filter(|&(_, second_changed_lines)| second_changed_lines.start < changed_lines.start)
+						.map(|(second_hunk, _)| length_difference(second_hunk))
+						.sum();
+				shift_hunk(hunk, offset)
+			})
+			.collect();
+
+	Ok((new_second_hunks, new_first_hunks))
+}
+
+fn begin_based_range(range: &Range<usize>) -> Range<usize> {
+	let begin = range_begin(range);
+	begin..begin + (range.end - range.start)
+}
+
+// The positions of the changed lines on one side of the hunk. Pure insertions or removals produce an empty range.
+fn changed_lines(hunk: &Hunk, range: &Range<usize>, other_side_kind: HunkLineKind) -> Range<usize> {
+	let mut position = range_begin(range);
+	let mut changed_lines: Option<Range<usize>> = None;
+	for line in &hunk.lines {
+		let next_position = if line.kind == other_side_kind { position } else { position + 1 };
+		if line.kind != HunkLineKind::Context {
+			changed_lines = Some(match changed_lines {
+				Some(changed_lines) => changed_lines.start..next_position,
+				None => position..next_position
+			});
+		}
+		position = next_position;
+	}
+	changed_lines.unwrap_or(position..position)
+}
+
+// Touching empty ranges interfere too, as the order of the insertions at the same place matters
+fn ranges_interfere(range: &Range<usize>, other_range: &Range<usize>) -> bool {
+	if range.start == range.end || other_range.start == other_range.end {
+		range.start <= other_range.end && other_range.start <= range.end
+	} else {
+		range.start < other_range.end && other_range.start < range.end
+	}
+}
+
+fn length_difference(hunk: &Hunk) -> isize {
+	(hunk.new_file_range.end - hunk.new_file_range.start) as isize - (hunk.old_file_range.end - hunk.old_file_range.start) as isize
+}
+
+fn shift_range(range: &Range<usize>, offset: isize) -> Range<usize> {
+	range_from_begin((range_begin(range) as isize + offset) as usize, range.end - range.start)
+}
+
+fn shift_hunk(hunk: &Hunk, offset: isize) -> Hunk {
+	let mut hunk = hunk.clone();
+	hunk.old_file_range = shift_range(&hunk.old_file_range, offset);
+	hunk.new_file_range = shift_range(&hunk.new_file_range, offset);
+	hunk.number_lines();
+	hunk
+}
+
+fn context_length(hunk: &Hunk) -> usize {
+	let leading_context = hunk.lines.iter().take_while(|line| line.kind == HunkLineKind::Context).count();
+	let trailing_context = hunk.lines.iter().rev().take_while(|line| line.kind == HunkLineKind::Context).count();
+	max(leading_context, trailing_context)
+}
 
-		if overlaps_found {
-			return true;
+// The position after the last line of the old file side, even if the side has no lines
+fn old_file_end(hunk: &Hunk) -> usize {
+	range_begin(&hunk.old_file_range) + (hunk.old_file_range.end - hunk.old_file_range.start)
+}
+
+fn merge_hunks(hunks: Vec<Hunk>) -> Result<Vec<Hunk>, OverlappingHunkError> {
+	merge_touching_hunks(hunks, true)
+}
+
+// Only the hunks that agree on the lines they share can be merged. The adjacent ones are either merged too or left apart.
+fn merge_touching_hunks(hunks: Vec<Hunk>, merge_adjacent: bool) -> Result<Vec<Hunk>, OverlappingHunkError> {
+	let mut sorted_hunks = hunks;
+	sorted_hunks.sort_unstable();
+
+	let mut merged_hunks: Vec<Hunk> = Vec::new();
+	for hunk in sorted_hunks {
+		let merged_hunk = match merged_hunks.last() {
+			Some(last_hunk) if range_begin(&hunk.old_file_range) < old_file_end(last_hunk) ||
+					(merge_adjacent && range_begin(&hunk.old_file_range) == old_file_end(last_hunk)) => Some(merge_two_hunks(last_hunk, &hunk)?),
+			_ => None
+		};
+
+		match merged_hunk {
+			Some(merged_hunk) => *merged_hunks.last_mut().unwrap() = merged_hunk,
+			None => merged_hunks.push(hunk)
 		}
 	}
 
-	return false;
+	update_new_file_ranges(&mut merged_hunks);
+	Ok(merged_hunks)
+}
+
+fn update_new_file_ranges(hunks: &mut [Hunk]) {
+	let mut offset = 0isize;
+	for hunk in hunks {
+		let old_file_length = hunk.old_file_range.end - hunk.old_file_range.start;
+		let new_file_length = hunk.new_file_range.end - hunk.new_file_range.start;
+
+		let new_file_begin = (range_begin(&hunk.old_file_range) as isize + offset) as usize;
+		hunk.new_file_range = range_from_begin(new_file_begin, new_file_length);
+		hunk.number_lines();
+
+		offset += new_file_length as isize - old_file_length as isize;
+	}
 }
 
 #[derive(Fail, Debug)]
-#[fail(display = "Some hunks are overlapping")]
+#[fail(display = "Some hunks are overlapping and contradict each other")]
 pub struct OverlappingHunkError;
 
+#[derive(Fail, Debug)]
+pub enum CompositionError {
+	#[fail(display = "The second patch doesn't apply on top of the first one")]
+	MismatchingHunks,
+	#[fail(display = "Only the consecutive changes of the same file can be composed")]
+	UnsupportedChanges,
+}
+
+#[derive(Fail, Debug)]
+pub enum CommutationError {
+	#[fail(display = "The patches change the same lines")]
+	OverlappingHunks,
+	#[fail(display = "One of the patches depends on the file changes made by the other one")]
+	DependentChanges,
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "The line without a newline at the end of the file has to stay the last one")]
+pub struct InseparableLinesError;
+
+#[derive(Fail, Debug)]
+#[fail(display = "Can't invert the copy of {} as the patch doesn't contain the full contents of the copy", _0)]
+pub struct InversionError(String);
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Patch {
 	pub change: Change,
@@ -34,62 +390,82 @@ pub struct Patch {
 }
 
 impl Patch {
-	// TODO: check if we actually need to forbid overlapping hunks
+	// The adjacent hunks stay as they are, only the absorbed hunks get merged
+	// The overlapping hunks are merged unless they contradict each other, the adjacent ones stay as they are
 	pub fn new(change: Change, hunks: Vec<Hunk>) -> Result<Patch, OverlappingHunkError> {
-		let mut sorted_hunks = hunks;
-		sorted_hunks.sort_unstable();
-
-		if check_overlaps(&sorted_hunks, &sorted_hunks) { return Err(OverlappingHunkError); }
-
 		Ok(Patch {
 			change,
-			hunks: sorted_hunks,
+			hunks: merge_touching_hunks(hunks, false)?,
 		})
 	}
 
 	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		self.write_with_prefixes(write, "a/", "b/")
+	}
+
+	// E.g. no prefixes for the patches applied with "patch -p0"
+	pub fn write_with_prefixes<W: Write>(&self, write: &mut W, old_prefix: &str, new_prefix: &str) -> Result<(), Error> {
 		let prefixed_old_name = match self.change {
 			Change::Addition { .. } => FILE_NAME_PLACEHOLDER.into(),
-			Change::Removal { ref old_properties } | Change::Modification { ref old_properties, .. } => String::from("a/") + &old_properties.name
+			Change::Removal { ref old_properties } | Change::Modification { ref old_properties, .. } => String::from(old_prefix) + &old_properties.name
 		};
 		let prefixed_escaped_old_name = format_name(&prefixed_old_name);
 
 		let prefixed_new_name = match self.change {
-			Change::Addition { ref new_properties } | Change::Modification { ref new_properties, .. } => String::from("b/") + &new_properties.name,
+			Change::Addition { ref new_properties } | Change::Modification { ref new_properties, .. } => String::from(new_prefix) + &new_properties.name,
 			Change::Removal { .. } => FILE_NAME_PLACEHOLDER.into(),
 		};
 		let prefixed_escaped_new_name = format_name(&prefixed_new_name);
 
-		write.write_fmt(format_args!("diff --git {} {}\n", prefixed_escaped_old_name, prefixed_escaped_new_name))?;
+		// Unlike the name lines, the header names the file on both sides even if it's absent on one
+		let (header_old_name, header_new_name) = match self.change {
+			Change::Addition { new_properties: ref properties } | Change::Removal { old_properties: ref properties } => {
+				(String::from(old_prefix) + &properties.name, String::from(new_prefix) + &properties.name)
+			}
+			Change::Modification { .. } => (prefixed_old_name.clone(), prefixed_new_name.clone())
+		};
+		write.write_fmt(format_args!("diff --git {} {}\n", format_name(&header_old_name), format_name(&header_new_name)))?;
 
+		// The hash of the absent file is written as zeros of the same length as the other hash, same as in git
+		let zero_index = |index: &String| "0".repeat(index.len());
 		let operation_lines = match self.change {
 			Change::Addition { ref new_properties } => {
 				let mut operation_lines = format!("new file mode {}\n", new_properties.mode);
 				if let Some(ref index) = new_properties.index {
-					operation_lines.push_str(&format!("index 00000000..{}\n", index));
+					operation_lines.push_str(&format!("index {}..{}\n", zero_index(index), index));
 				}
 				operation_lines
 			}
 			Change::Removal { ref old_properties } => {
 				let mut operation_lines = format!("deleted file mode {}\n", old_properties.mode);
 				if let Some(ref index) = old_properties.index {
-					operation_lines.push_str(&format!("index {}..00000000 {}\n", index, old_properties.mode));
+					operation_lines.push_str(&format!("index {}..{}\n", index, zero_index(index)));
 				}
 				operation_lines
 			}
 			Change::Modification { ref modification_type, ref old_properties, ref new_properties } => {
-				let mut operation_lines = match modification_type {
-					&ModificationType::Edited => "".into(),
-					&ModificationType::Copied { .. } => format!("copy from {}\ncopy to {}\n", format_name(&old_properties.name),
-						format_name(&new_properties.name)),
-					&ModificationType::Renamed { .. } => format!("rename from {}\nrename to {}\n", format_name(&old_properties.name),
-						format_name(&new_properties.name)),
-					&ModificationType::ModeChanged => format!("old mode {}\nnew mode {}\n", old_properties.mode, new_properties.mode)
+				let mut operation_lines = if old_properties.mode != new_properties.mode {
+					format!("old mode {}\nnew mode {}\n", old_properties.mode, new_properties.mode)
+				} else {
+					String::new()
 				};
 
+				match modification_type {
+					&ModificationType::Edited | &ModificationType::ModeChanged => (),
+					&ModificationType::Copied { similarity } => operation_lines.push_str(&format!("{}copy from {}\ncopy to {}\n",
+						format_similarity(similarity), format_name(&old_properties.name), format_name(&new_properties.name))),
+					&ModificationType::Renamed { similarity } => operation_lines.push_str(&format!("{}rename from {}\nrename to {}\n",
+						format_similarity(similarity), format_name(&old_properties.name), format_name(&new_properties.name)))
+				}
+
 				if let Some(ref old_index) = old_properties.index {
 					if let Some(ref new_index) = new_properties.index {
-						operation_lines.push_str(&format!("index {}..{} {}\n", old_index, new_index, old_properties.mode));
+						// The mode is only a part of the index line when it doesn't change
+						if old_properties.mode == new_properties.mode {
+							operation_lines.push_str(&format!("index {}..{} {}\n", old_index, new_index, old_properties.mode));
+						} else {
+							operation_lines.push_str(&format!("index {}..{}\n", old_index, new_index));
+						}
 					}
 				}
 				operation_lines
@@ -97,8 +473,12 @@ impl Patch {
 		};
 
 		write.write_all(operation_lines.as_bytes())?;
-		write.write_fmt(format_args!("--- {}\n", prefixed_escaped_old_name))?;
-		write.write_fmt(format_args!("+++ {}\n", prefixed_escaped_new_name))?;
+		if self.hunks.is_empty() { return Ok(()); }
+
+		// Git terminates the names containing spaces with a tab
+		let name_terminator = |name: &str| if name.contains(' ') { "\t" } else { "" };
+		write.write_fmt(format_args!("--- {}{}\n", prefixed_escaped_old_name, name_terminator(&prefixed_escaped_old_name)))?;
+		write.write_fmt(format_args!("+++ {}{}\n", prefixed_escaped_new_name, name_terminator(&prefixed_escaped_new_name)))?;
 
 		for hunk in &self.hunks {
 			hunk.write(write)?;
@@ -107,6 +487,143 @@ impl Patch {
 		Ok(())
 	}
 
+	pub fn patch_id(&self) -> Option<PatchId> {
+		let mut buf = Vec::new();
+		self.write(&mut buf).expect("Writing to memory can't fail");
+		patch_id(&buf)
+	}
+
+	pub fn stat(&self) -> FileStat {
+		let (name, old_name) = match self.change {
+			Change::Addition { ref new_properties } => (new_properties.name.clone(), None),
+			Change::Removal { ref old_properties } => (old_properties.name.clone(), None),
+			Change::Modification { ref old_properties, ref new_properties, .. } => {
+				let old_name = Some(old_properties.name.clone()).filter(|old_name| *old_name != new_properties.name);
+				(new_properties.name.clone(), old_name)
+			}
+		};
+		let count_lines = |kind: HunkLineKind| self.hunks.iter().map(|hunk| hunk.lines.iter().filter(|line| line.kind == kind).count()).sum();
+		FileStat {
+			name,
+			old_name,
+			insertions: count_lines(HunkLineKind::Added),
+			deletions: count_lines(HunkLineKind::Removed),
+			binary: false,
+		}
+	}
+
+	pub fn invert(&self) -> Result<Patch, InversionError> {
+		let change = match self.change {
+			Change::Addition { ref new_properties } => Change::Removal { old_properties: new_properties.clone() },
+			Change::Removal { ref old_properties } => Change::Addition { new_properties: old_properties.clone() },
+			Change::Modification { modification_type: ModificationType::Copied { .. }, ref new_properties, .. } => {
+				return Err(InversionError(new_properties.name.clone()));
+			}
+			Change::Modification { ref modification_type, ref old_properties, ref new_properties } => Change::Modification {
+				modification_type: modification_type.clone(),
+				old_properties: new_properties.clone(),
+				new_properties: old_properties.clone(),
+			}
+		};
+
+		Ok(Patch {
+			change,
+			hunks: self.hunks.iter().map(|hunk| hunk.invert()).collect(),
+		})
+	}
+
+	// The next patch should change the file produced by this one. An addition followed by a removal can't be composed, as nothing remains.
+	pub fn compose(&self, next_patch: &Patch) -> Result<Patch, CompositionError> {
+		let change = match (&self.change, &next_patch.change) {
+			(&Change::Addition { .. }, &Change::Modification { ref new_properties, .. }) => {
+				Change::Addition { new_properties: new_properties.clone() }
+			}
+			(&Change::Modification { ref modification_type, ref old_properties, .. }, &Change::Removal { .. }) => {
+				if let ModificationType::Copied { .. } = *modification_type { return Err(CompositionError::UnsupportedChanges); }
+				Change::Removal { old_properties: old_properties.clone() }
+			}
+			(&Change::Removal { ref old_properties }, &Change::Addition { ref new_properties }) => {
+				// Both patches list the whole file, so the contents can be compared instead
+				let contents = |patch: &Patch| patch.hunks.iter()
+						.flat_map(|hunk| hunk.lines.iter())
+						.flat_map(|line| line.content.iter().cloned())
+						.collect::<Vec<_>>();
+				let change = Change::Modification {
+					modification_type: composed_modification_type(&ModificationType::Edited, &ModificationType::Edited, old_properties, new_properties),
+					old_properties: old_properties.clone(),
+					new_properties: new_properties.clone(),
+				};
+				return Ok(diff_patch(change, &contents(self), &contents(next_patch), &DiffOptions::default()));
+			}
+			(&Change::Modification { ref modification_type, ref old_properties, .. },
+					&Change::Modification { modification_type: ref next_modification_type, ref new_properties, .. }) => {
+				Change::Modification {
+					modification_type: composed_modification_type(modification_type, next_modification_type, old_properties, new_properties),
+					old_properties: old_properties.clone(),
+					new_properties: new_properties.clone(),
+				}
+			}
+			_ => return Err(CompositionError::UnsupportedChanges)
+		};
+
+		Ok(Patch {
+			change,
+			hunks: compose_hunks(&self.hunks, &next_patch.hunks)?,
+		})
+	}
+
+	// Returns the next patch rewritten to apply before this one, and this patch rewritten to apply after it
+	pub fn commute(&self, next_patch: &Patch) -> Result<(Patch, Patch), CommutationError> {
+		let (old_properties, new_properties, next_new_properties) = match (&self.change, &next_patch.change) {
+			(&Change::Modification { ref modification_type, ref old_properties, ref new_properties },
+					&Change::Modification { modification_type: ref next_modification_type, new_properties: ref next_new_properties, .. }) => {
+				match (modification_type, next_modification_type) {
+					(ModificationType::Edited, ModificationType::Edited) | (ModificationType::Edited, ModificationType::ModeChanged)
+							| (ModificationType::ModeChanged, ModificationType::Edited) => (),
+					_ => return Err(CommutationError::DependentChanges)
+				}
+				(old_properties, new_properties, next_new_properties)
+			}
+			_ => return Err(CommutationError::DependentChanges)
+		};
+
+		let (new_next_hunks, new_hunks) = commute_hunks(&self.hunks, &next_patch.hunks)?;
+
+		// Only one of the patches can change the mode, so the other one keeps the mode it sees
+		let intermediate_mode = if new_properties.mode == old_properties.mode { next_new_properties.mode.clone() } else { old_properties.mode.clone() };
+
+		let mut new_next_patch = Patch {
+			change: next_patch.change.clone(),
+			hunks: new_next_hunks,
+		};
+		if let Change::Modification { old_properties: ref mut next_old_properties, new_properties: ref mut next_new_properties, .. } = new_next_patch.change {
+			*next_old_properties = old_properties.clone();
+			next_new_properties.mode = intermediate_mode.clone();
+		}
+		new_next_patch.remove_indexes();
+
+		let mut new_patch = Patch {
+			change: self.change.clone(),
+			hunks: new_hunks,
+		};
+		if let Change::Modification { old_properties: ref mut new_old_properties, new_properties: ref mut new_new_properties, .. } = new_patch.change {
+			new_old_properties.mode = intermediate_mode;
+			new_old_properties.index = None;
+			new_new_properties.mode = next_new_properties.mode.clone();
+		}
+		new_patch.remove_indexes();
+
+		Ok((new_next_patch, new_patch))
+	}
+
+	// Only the changes of the existing files count, the added and removed files have no context anyway
+	pub fn has_hunks_without_context(&self) -> bool {
+		match self.change {
+			Change::Modification { .. } => self.hunks.iter().any(|hunk| hunk.lines.iter().all(|line| line.kind != HunkLineKind::Context)),
+			_ => false
+		}
+	}
+
 	pub fn is_edit(&self) -> bool {
 		match self.change {
 			Change::Modification { modification_type: ModificationType::Edited, .. } => true,
@@ -114,6 +631,45 @@ impl Patch {
 		}
 	}
 
+	// E.g. all the hunks moved out, or an edit reverted by the next patch. A rename or a mode change still does something.
+	pub fn is_empty(&self) -> bool {
+		match self.change {
+			Change::Modification { ref old_properties, ref new_properties, .. } => {
+				self.hunks.is_empty() && old_properties.name == new_properties.name && old_properties.mode == new_properties.mode
+			}
+			_ => false
+		}
+	}
+
+	pub fn is_copy(&self) -> bool {
+		match self.change {
+			Change::Modification { modification_type: ModificationType::Copied { .. }, .. } => true,
+			_ => false
+		}
+	}
+
+	pub fn old_properties(&self) -> Option<&FileProperties> {
+		match self.change {
+			Change::Addition { .. } => None,
+			Change::Removal { ref old_properties } | Change::Modification { ref old_properties, .. } => Some(old_properties)
+		}
+	}
+
+	pub fn new_properties(&self) -> Option<&FileProperties> {
+		match self.change {
+			Change::Addition { ref new_properties } | Change::Modification { ref new_properties, .. } => Some(new_properties),
+			Change::Removal { .. } => None
+		}
+	}
+
+	pub fn old_file_name(&self) -> Option<&String> {
+		self.old_properties().map(|properties| &properties.name)
+	}
+
+	pub fn new_file_name(&self) -> Option<&String> {
+		self.new_properties().map(|properties| &properties.name)
+	}
+
 	pub fn get_edit_patch_file_name(&self) -> Option<&String> {
 		match self.change {
 			Change::Modification { modification_type: ModificationType::Edited, ref new_properties, .. } => Some(&new_properties.name),
@@ -121,9 +677,17 @@ impl Patch {
 		}
 	}
 
+	// The hunks of a rename or a mode change are moved without the rename or the mode change. The hunks of a copy depend on the file it's made from.
+	pub fn has_movable_hunks(&self) -> bool {
+		match self.change {
+			Change::Modification { .. } => !self.is_copy(),
+			_ => false
+		}
+	}
+
 	fn move_out_hunks(&mut self, positions: &[usize]) -> Vec<Hunk> {
-		if !self.is_edit() {
-			panic!("Only the edit patch can be changed. No addition, removal, mode change, or name change patches can be changed.");
+		if !self.has_movable_hunks() {
+			panic!("Only the hunks of the modification patch can be moved. No addition, removal, or copy patches can be changed.");
 		}
 
 		self.remove_indexes();
@@ -135,6 +699,8 @@ impl Patch {
 		for position in sorted_positions.into_iter().rev() {
 			hunks.insert(0, self.hunks.remove(position));
 		}
+		update_new_file_ranges(&mut self.hunks);
+		update_new_file_ranges(&mut hunks);
 		hunks
 	}
 
@@ -151,15 +717,103 @@ impl Patch {
 	}
 
 	pub fn move_hunks_to(&mut self, positions: &[usize], patch: &mut Patch) -> Result<(), OverlappingHunkError> {
-		let mut hunks = self.move_out_hunks(positions);
+		let hunks = positions.iter().map(|&position| self.hunks[position].clone()).collect::<Vec<_>>();
+		let merged_patch = patch.with_merged_hunks(hunks)?;
 
-		if check_overlaps(&hunks, &patch.hunks) { return Err(OverlappingHunkError); }
+		self.move_out_hunks(positions);
+		*patch = merged_patch;
 
+		Ok(())
+	}
+
+	pub fn with_merged_hunks(&self, hunks: Vec<Hunk>) -> Result<Patch, OverlappingHunkError> {
+		let mut all_hunks = self.hunks.clone();
+		all_hunks.extend(hunks);
+
+		let mut patch = Patch {
+			change: self.change.clone(),
+			hunks: merge_hunks(all_hunks)?,
+		};
 		patch.remove_indexes();
-		patch.hunks.append(&mut hunks);
-		patch.hunks.sort_unstable();
 
-		Ok(())
+		Ok(patch)
+	}
+
+	// Every changed line goes to the part given at its position in the hunk, and the parts are applied one after another.
+	// The parts without any of the lines get no patch. The first part with the lines renames the file or changes its mode, if the patch does.
+	pub fn split_lines(&self, line_parts: &[Vec<usize>], part_count: usize) -> Result<Vec<Option<Patch>>, InseparableLinesError> {
+		if !self.has_movable_hunks() {
+			panic!("Only the hunks of the modification patch can be split. No addition, removal, or copy patches can be changed.");
+		}
+
+		let mut length_differences = vec![0isize; part_count]; // Made by each part in the hunks so far
+		let mut part_hunks = vec![Vec::new(); part_count];
+		for (hunk, parts) in self.hunks.iter().zip(line_parts) {
+			for part in 0..part_count {
+				// The lines of the earlier parts are already there, the lines of the later ones aren't yet
+				let lines = hunk.lines.iter().zip(parts).filter_map(|(line, &line_part)| {
+					let kind = match line.kind {
+						HunkLineKind::Context => HunkLineKind::Context,
+						_ if line_part == part => line.kind,
+						HunkLineKind::Removed if line_part < part => return None,
+						HunkLineKind::Added if line_part > part => return None,
+						_ => HunkLineKind::Context
+					};
+					Some(line_with_kind(line, kind))
+				}).collect::<Vec<_>>();
+				if lines.iter().all(|line| line.kind == HunkLineKind::Context) { continue; }
+
+				for other_side_kind in &[HunkLineKind::Added, HunkLineKind::Removed] {
+					let side_lines = lines.iter().filter(|line| line.kind != *other_side_kind).collect::<Vec<_>>();
+					if side_lines.iter().rev().skip(1).any(|line| line.no_newline_at_eof) { return Err(InseparableLinesError); }
+				}
+
+				let begin = (range_begin(&hunk.old_file_range) as isize + length_differences[..part].iter().sum::<isize>()) as usize;
+				let old_file_length = lines.iter().filter(|line| line.kind != HunkLineKind::Added).count();
+				let new_file_length = lines.iter().filter(|line| line.kind != HunkLineKind::Removed).count();
+				let mut part_hunk = Hunk::new(range_from_begin(begin, old_file_length), range_from_begin(begin, new_file_length), lines);
+				part_hunk.section_heading = hunk.section_heading.clone();
+				part_hunks[part].push(part_hunk);
+			}
+
+			for (line, &line_part) in hunk.lines.iter().zip(parts) {
+				match line.kind {
+					HunkLineKind::Added => length_differences[line_part] += 1,
+					HunkLineKind::Removed => length_differences[line_part] -= 1,
+					HunkLineKind::Context => ()
+				}
+			}
+		}
+
+		let mut change = Some(self.change.clone());
+		Ok(part_hunks.into_iter().map(|mut hunks| {
+			if hunks.is_empty() { return None; }
+			update_new_file_ranges(&mut hunks);
+
+			let new_properties = self.new_properties().unwrap().clone();
+			let mut patch = Patch {
+				change: change.take().unwrap_or_else(|| Change::Modification {
+					modification_type: ModificationType::Edited,
+					old_properties: new_properties.clone(),
+					new_properties,
+				}),
+				hunks,
+			};
+			patch.remove_all_indexes(); // The file in between the parts is new
+			Some(patch)
+		}).collect())
+	}
+
+	pub fn hunks_by_section_heading(&self) -> Vec<(Option<&str>, Vec<usize>)> {
+		let mut groups: Vec<(Option<&str>, Vec<usize>)> = Vec::new();
+		for (position, hunk) in self.hunks.iter().enumerate() {
+			let section_heading = hunk.section_heading.as_ref().map(|heading| heading.as_str());
+			match groups.iter_mut().find(|&&mut (heading, _)| heading == section_heading) {
+				Some(&mut (_, ref mut positions)) => positions.push(position),
+				None => groups.push((section_heading, vec![position]))
+			}
+		}
+		groups
 	}
 
 	pub fn remove_hunks(&mut self, positions: &[usize]) {
@@ -173,6 +827,19 @@ impl Patch {
 				.for_each(|position| {
 					self.hunks.remove(position);
 				});
+		update_new_file_ranges(&mut self.hunks);
+	}
+
+	// The hashes are no longer valid when the contents of the file before the patch change
+	pub fn remove_all_indexes(&mut self) {
+		match self.change {
+			Change::Addition { ref mut new_properties } => new_properties.index = None,
+			Change::Removal { ref mut old_properties } => old_properties.index = None,
+			Change::Modification { ref mut old_properties, ref mut new_properties, .. } => {
+				old_properties.index = None;
+				new_properties.index = None;
+			}
+		}
 	}
 
 	fn remove_indexes(&mut self) {
@@ -185,7 +852,35 @@ impl Patch {
 	}
 }
 
-fn format_name(name: &str) -> Cow<str> {
+// The similarity of the original patches doesn't describe the composed contents, so it's dropped
+fn composed_modification_type(modification_type: &ModificationType, next_modification_type: &ModificationType,
+		old_properties: &FileProperties, new_properties: &FileProperties) -> ModificationType {
+	let is_copy = |modification_type: &ModificationType| match *modification_type {
+		ModificationType::Copied { .. } => true,
+		_ => false
+	};
+
+	if old_properties.name != new_properties.name {
+		if is_copy(modification_type) || is_copy(next_modification_type) {
+			ModificationType::Copied { similarity: None }
+		} else {
+			ModificationType::Renamed { similarity: None }
+		}
+	} else if old_properties.mode != new_properties.mode {
+		ModificationType::ModeChanged
+	} else {
+		ModificationType::Edited
+	}
+}
+
+fn format_similarity(similarity: Option<u8>) -> String {
+	match similarity {
+		Some(similarity) => format!("similarity index {}%\n", similarity),
+		None => "".into()
+	}
+}
+
+pub fn format_name(name: &str) -> Cow<str> {
 	let escape = name.chars().any(|ch| ch.is_control() || ch == '"' || ch == '\\' || ch >= 0x80 as char);
 	if !escape { return name.into(); }
 
@@ -207,7 +902,7 @@ fn format_name(name: &str) -> Cow<str> {
 				let mut acc = String::new();
 				for byte in ch.encode_utf8(&mut conversion_buf).as_bytes() {
 					acc.push('\\');
-					acc.push_str(&format!("{:03o})", byte));
+					acc.push_str(&format!("{:03o}", byte));
 				}
 				acc.into()
 			}
@@ -255,7 +950,8 @@ pub enum ModificationType {
 pub struct Hunk {
 	pub old_file_range: Range<usize>,
 	pub new_file_range: Range<usize>,
-	pub data: Vec<u8>,
+	pub section_heading: Option<String>,
+	pub lines: Vec<HunkLine>,
 }
 
 fn range_to_str(range: &Range<usize>) -> String {
@@ -268,17 +964,131 @@ fn range_to_str(range: &Range<usize>) -> String {
 }
 
 impl Hunk {
+	pub fn new(old_file_range: Range<usize>, new_file_range: Range<usize>, lines: Vec<HunkLine>) -> Hunk {
+		let mut hunk = Hunk {
+			old_file_range,
+			new_file_range,
+			section_heading: None,
+			lines,
+		};
+		hunk.number_lines();
+		hunk
+	}
+
+	fn number_lines(&mut self) {
+		let mut old_line_number = range_begin(&self.old_file_range);
+		let mut new_line_number = range_begin(&self.new_file_range);
+		for line in &mut self.lines {
+			line.old_line_number = None;
+			line.new_line_number = None;
+
+			if line.kind != HunkLineKind::Added {
+				line.old_line_number = Some(old_line_number);
+				old_line_number += 1;
+			}
+			if line.kind != HunkLineKind::Removed {
+				line.new_line_number = Some(new_line_number);
+				new_line_number += 1;
+			}
+		}
+	}
+
+	pub fn invert(&self) -> Hunk {
+		let mut lines = Vec::with_capacity(self.lines.len());
+		let mut added_lines = Vec::new();
+		for line in &self.lines {
+			let mut inverted_line = line.clone();
+			inverted_line.kind = match line.kind {
+				HunkLineKind::Context => HunkLineKind::Context,
+				HunkLineKind::Added => HunkLineKind::Removed,
+				HunkLineKind::Removed => HunkLineKind::Added,
+			};
+
+			// Keep removals in front of additions, just like diff does
+			match inverted_line.kind {
+				HunkLineKind::Added => added_lines.push(inverted_line),
+				HunkLineKind::Removed => lines.push(inverted_line),
+				HunkLineKind::Context => {
+					lines.append(&mut added_lines);
+					lines.push(inverted_line);
+				}
+			}
+		}
+		lines.append(&mut added_lines);
+
+		let mut hunk = Hunk::new(self.new_file_range.clone(), self.old_file_range.clone(), lines);
+		hunk.section_heading = self.section_heading.clone();
+		hunk
+	}
+
 	pub fn header(&self) -> String {
 		let old_file_range_str = range_to_str(&self.old_file_range);
 		let new_file_range_str = range_to_str(&self.new_file_range);
-		format!("@@ -{} +{} @@\n", old_file_range_str, new_file_range_str)
+		match self.section_heading {
+			Some(ref section_heading) => format!("@@ -{} +{} @@ {}\n", old_file_range_str, new_file_range_str, section_heading),
+			None => format!("@@ -{} +{} @@\n", old_file_range_str, new_file_range_str)
+		}
 	}
 
-	fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
 		let header = self.header();
 
 		write.write_all(header.as_bytes())?;
-		write.write_all(&self.data)?;
+		for line in &self.lines {
+			line.write(write)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HunkLineKind {
+	Context,
+	Added,
+	Removed,
+}
+
+impl HunkLineKind {
+	pub fn prefix(&self) -> u8 {
+		match *self {
+			HunkLineKind::Context => b' ',
+			HunkLineKind::Added => b'+',
+			HunkLineKind::Removed => b'-',
+		}
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct HunkLine {
+	pub kind: HunkLineKind,
+	pub content: Vec<u8>, // Includes the line terminator unless the line is the last one and has none
+	pub no_newline_at_eof: bool,
+	pub prefix_omitted: bool, // Some tools strip the space from empty context lines
+	pub old_line_number: Option<usize>,
+	pub new_line_number: Option<usize>,
+}
+
+impl HunkLine {
+	pub fn new(kind: HunkLineKind, content: Vec<u8>) -> HunkLine {
+		HunkLine {
+			kind,
+			content,
+			no_newline_at_eof: false,
+			prefix_omitted: false,
+			old_line_number: None,
+			new_line_number: None,
+		}
+	}
+
+	pub fn write<W: Write>(&self, write: &mut W) -> Result<(), Error> {
+		if !self.prefix_omitted {
+			write.write_all(&[self.kind.prefix()])?;
+		}
+		write.write_all(&self.content)?;
+		if self.no_newline_at_eof {
+			write.write_all(NO_NEWLINE_MARKER)?;
+		}
 
 		Ok(())
 	}
@@ -286,19 +1096,194 @@ impl Hunk {
 
 impl PartialOrd for Hunk {
 	fn partial_cmp(&self, other: &Hunk) -> Option<Ordering> {
-		Some(self.old_file_range.start.cmp(&other.old_file_range.start))
+		Some(self.cmp(other))
 	}
 }
 
 impl<'a> Ord for Hunk {
 	fn cmp(&self, other: &Self) -> Ordering {
-		self.old_file_range.start.cmp(&other.old_file_range.start)
+		range_begin(&self.old_file_range).cmp(&range_begin(&other.old_file_range))
 	}
 }
 
 #[cfg(test)]
 mod test {
+	use super::*;
 	use super::super::test_data::*;
+	use super::super::parser::parse_patch;
+
+	const FIRST_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index ac9837c..0466647 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -1,8 +1,10 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
++added a
++added b
+ line 6
+ line 7
+ line 8
+@@ -13,6 +15,7 @@ line 12
+ line 13
+ line 14
+ line 15
++added c
+ line 16
+ line 17
+ line 18
+";
+
+	const SECOND_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index 0466647..445f489 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -4,7 +4,7 @@ line 3
+ line 4
+ line 5
+ added a
+-added b
++added B
+ line 6
+ line 7
+ line 8
+@@ -15,7 +15,6 @@ line 12
+ line 13
+ line 14
+ line 15
+-added c
+ line 16
+ line 17
+ line 18
+@@ -24,7 +23,7 @@ line 20
+ line 21
+ line 22
+ line 23
+-line 24
++line twenty-four
+ line 25
+ line 26
+ line 27
+";
+
+	const COMPOSED_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index ac9837c..445f489 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -1,8 +1,10 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
++added a
++added B
+ line 6
+ line 7
+ line 8
+@@ -21,7 +23,7 @@ line 20
+ line 21
+ line 22
+ line 23
+-line 24
++line twenty-four
+ line 25
+ line 26
+ line 27
+";
+
+	const COMMUTABLE_FIRST_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index ac9837c..3150e0d 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -1,5 +1,5 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
+@@ -13,6 +13,7 @@ line 12
+ line 13
+ line 14
+ line 15
++added c
+ line 16
+ line 17
+ line 18
+";
+
+	const COMMUTABLE_SECOND_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index 3150e0d..37e2c37 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -6,7 +6,6 @@ line 5
+ line 6
+ line 7
+ line 8
+-line 9
+ line 10
+ line 11
+ line 12
+@@ -22,7 +21,7 @@ line 20
+ line 21
+ line 22
+ line 23
+-line 24
++line twenty-four
+ line 25
+ line 26
+ line 27
+";
+
+	const COMMUTED_SECOND_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index ac9837c..376c723 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -6,7 +6,6 @@ line 5
+ line 6
+ line 7
+ line 8
+-line 9
+ line 10
+ line 11
+ line 12
+@@ -21,7 +20,7 @@ line 20
+ line 21
+ line 22
+ line 23
+-line 24
++line twenty-four
+ line 25
+ line 26
+ line 27
+";
+
+	const COMMUTED_FIRST_PATCH_DATA: &[u8] = b"diff --git a/lines.txt b/lines.txt
+index 376c723..37e2c37 100644
+--- a/lines.txt
++++ b/lines.txt
+@@ -1,5 +1,5 @@
+ line 1
+-line 2
++line two
+ line 3
+ line 4
+ line 5
+@@ -12,6 +12,7 @@ line 12
+ line 13
+ line 14
+ line 15
++added c
+ line 16
+ line 17
+ line 18
+";
 
 	#[test]
 	fn test_write_patch() {
@@ -317,6 +1302,18 @@ mod test {
 		assert_eq!(&*buf, &**PATCH_DATA_NO_EXTENDED_HEADER);
 	}
 
+	#[test]
+	fn test_write_non_ascii_name() {
+		let data: &[u8] = b"diff --git \"a/\\303\\274ber.txt\" \"b/\\303\\274ber.txt\"\nindex ac9837c..0466647 100644\n\
+			--- \"a/\\303\\274ber.txt\"\n+++ \"b/\\303\\274ber.txt\"\n@@ -1 +1 @@\n-line 1\n+line one\n";
+		let patch = parse_patch(data).unwrap();
+		assert_eq!(patch.new_file_name(), Some(&String::from("\u{fc}ber.txt")));
+
+		let mut buf = Vec::new();
+		patch.write(&mut buf).unwrap();
+		assert_eq!(&*buf, data);
+	}
+
 	#[test]
 	fn test_write_hunk() {
 		let mut buf = Vec::new();
@@ -324,4 +1321,176 @@ mod test {
 
 		assert_eq!(&*buf, &**PATCH_DATA_HUNK_1);
 	}
-}
\ No newline at end of file
+
+	fn hunk(old_file_range: Range<usize>, new_file_range: Range<usize>, data: &[u8]) -> Hunk {
+		Hunk::new(old_file_range, new_file_range, lines_from_data(data))
+	}
+
+	#[test]
+	fn test_merge_overlapping_hunks() {
+		let hunks = vec![hunk(3..5, 3..5, b" c\n-d\n+D\n"), hunk(1..4, 1..4, b" a\n-b\n+B\n c\n")];
+		let merged_hunks = merge_hunks(hunks).unwrap();
+		assert_eq!(merged_hunks, vec![hunk(1..5, 1..5, b" a\n-b\n+B\n c\n-d\n+D\n")]);
+	}
+
+	#[test]
+	fn test_merge_adjacent_hunks() {
+		let hunks = vec![hunk(1..3, 1..2, b" a\n-b\n"), hunk(3..4, 2..4, b"-c\n+C\n+C2\n"), hunk(10..11, 11..12, b"-x\n+X\n")];
+		let merged_hunks = merge_hunks(hunks).unwrap();
+		assert_eq!(merged_hunks, vec![hunk(1..4, 1..4, b" a\n-b\n-c\n+C\n+C2\n"), hunk(10..11, 10..11, b"-x\n+X\n")]);
+	}
+
+	#[test]
+	fn test_merge_insertion_with_adjacent_hunks() {
+		let hunks = vec![hunk(3..3, 4..5, b"+x\n"), hunk(4..5, 4..5, b"-d\n+D\n")];
+		assert_eq!(merge_hunks(hunks).unwrap(), vec![hunk(4..5, 4..6, b"+x\n-d\n+D\n")]);
+
+		let hunks = vec![hunk(3..3, 4..5, b"+x\n"), hunk(3..4, 3..4, b"-c\n+C\n")];
+		assert_eq!(merge_hunks(hunks).unwrap(), vec![hunk(3..4, 3..5, b"-c\n+C\n+x\n")]);
+	}
+
+	#[test]
+	fn test_merge_contradicting_hunks() {
+		let hunks = vec![hunk(1..4, 1..4, b" a\n-b\n+B\n c\n"), hunk(3..4, 3..4, b"-x\n+X\n")];
+		assert!(merge_hunks(hunks).is_err());
+	}
+
+	#[test]
+	fn test_new_patch_with_overlapping_hunks() {
+		let patch = Patch::new(PATCH.change.clone(), vec![hunk(3..5, 3..5, b" c\n-d\n+D\n"), hunk(1..4, 1..4, b" a\n-b\n+B\n c\n")]).unwrap();
+		assert_eq!(patch.hunks, vec![hunk(1..5, 1..5, b" a\n-b\n+B\n c\n-d\n+D\n")]);
+	}
+
+	#[test]
+	fn test_move_hunk_next_to_sibling() {
+		let mut patch = Patch::new(PATCH.change.clone(), vec![hunk(3..4, 2..3, b"-c\n+C\n"), hunk(1..3, 1..2, b" a\n-b\n")]).unwrap();
+		assert_eq!(patch.hunks.len(), 2);
+		assert!(Patch::new(PATCH.change.clone(), vec![hunk(1..3, 1..2, b" a\n-b\n"), hunk(2..4, 1..3, b"-x\n+X\n c\n")]).is_err());
+
+		let mut other_patch = patch.clone();
+		other_patch.hunks = vec![hunk(4..5, 4..5, b"-d\n+D\n")];
+		other_patch.move_hunks_to(&[0], &mut patch).unwrap();
+
+		assert!(other_patch.hunks.is_empty());
+		assert_eq!(patch.hunks, vec![hunk(1..5, 1..4, b" a\n-b\n-c\n+C\n-d\n+D\n")]);
+	}
+
+	#[test]
+	fn test_move_hunks_out_of_rename() {
+		let mut renaming_patch = PATCH.clone();
+		if let Change::Modification { ref mut modification_type, ref mut new_properties, .. } = renaming_patch.change {
+			*modification_type = ModificationType::Renamed { similarity: Some(90) };
+			new_properties.name = "renamed.txt".into();
+		}
+		assert!(renaming_patch.has_movable_hunks());
+
+		let mut patch = PATCH.clone();
+		patch.hunks.clear();
+		renaming_patch.move_hunks_to(&[0, 1], &mut patch).unwrap();
+		assert!(renaming_patch.hunks.is_empty());
+		assert_eq!(renaming_patch.new_file_name(), Some(&String::from("renamed.txt")));
+		assert_eq!(patch.hunks, PATCH.hunks);
+
+		let mut copying_patch = PATCH.clone();
+		if let Change::Modification { ref mut modification_type, .. } = copying_patch.change {
+			*modification_type = ModificationType::Copied { similarity: Some(90) };
+		}
+		assert!(!copying_patch.has_movable_hunks());
+	}
+
+	#[test]
+	fn test_hunks_by_section_heading() {
+		let mut patch = PATCH.clone();
+		patch.hunks.push(hunk(30..31, 24..25, b"-x\n+X\n"));
+		patch.hunks[1].section_heading = Some("fn first()".into());
+		patch.hunks[2].section_heading = Some("fn first()".into());
+
+		assert_eq!(patch.hunks_by_section_heading(), vec![(None, vec![0]), (Some("fn first()"), vec![1, 2])]);
+
+		let mut buf = Vec::new();
+		patch.hunks[1].write(&mut buf).unwrap();
+		assert!(buf.starts_with(b"@@ -14,4 +8,4 @@ fn first()\n"));
+	}
+
+	#[test]
+	fn test_invert_patch() {
+		let inverted_patch = PATCH.invert().unwrap();
+		match inverted_patch.change {
+			Change::Modification { ref old_properties, ref new_properties, .. } => {
+				assert_eq!(old_properties.index, Some("f33a6d7".into()));
+				assert_eq!(new_properties.index, Some("aac7c9b".into()));
+			}
+			_ => panic!("The inverted patch is supposed to stay a modification")
+		}
+
+		let mut buf = Vec::new();
+		inverted_patch.hunks[1].write(&mut buf).unwrap();
+		assert_eq!(&*buf, &b"@@ -8,4 +14,4 @@
+ # When configured, Gradle will run in incubating parallel mode.
+ # This option should only be used with decoupled projects. More details, visit
+ # http://www.gradle.org/docs/current/userguide/multi_project_builds.html#sec:decoupled_projects
+-org.gradle.parallel=true
++# org.gradle.parallel=true
+"[..]);
+
+		assert_eq!(inverted_patch.invert().unwrap(), *PATCH);
+	}
+
+	#[test]
+	fn test_invert_addition() {
+		let inverted_patch = PATCH_ADDITION.invert().unwrap();
+		match inverted_patch.change {
+			Change::Removal { ref old_properties } => assert_eq!(old_properties.name, " b/Новый текстовый документ.txt"),
+			_ => panic!("The inverted addition is supposed to be a removal")
+		}
+	}
+
+	#[test]
+	fn test_hunks_without_context() {
+		let mut patch = parse_patch(FIRST_PATCH_DATA).unwrap();
+		assert!(!patch.has_hunks_without_context());
+		patch.hunks.push(hunk(20..21, 21..22, b"-line 20\n+line twenty\n"));
+		assert!(patch.has_hunks_without_context());
+		assert!(!PATCH_ADDITION.has_hunks_without_context());
+	}
+
+	#[test]
+	fn test_compose_patches() {
+		let first_patch = parse_patch(FIRST_PATCH_DATA).unwrap();
+		let second_patch = parse_patch(SECOND_PATCH_DATA).unwrap();
+		let composed_patch = first_patch.compose(&second_patch).unwrap();
+
+		let mut buf = Vec::new();
+		composed_patch.write(&mut buf).unwrap();
+		assert_eq!(String::from_utf8(buf).unwrap(), String::from_utf8(COMPOSED_PATCH_DATA.to_vec()).unwrap());
+	}
+
+	#[test]
+	fn test_compose_mismatching_patches() {
+		let first_patch = parse_patch(FIRST_PATCH_DATA).unwrap();
+		let mut second_patch = first_patch.clone();
+		second_patch.hunks = vec![hunk(2..3, 2..3, b"-line 2\n+line 2 again\n")];
+		assert!(first_patch.compose(&second_patch).is_err());
+	}
+
+	#[test]
+	fn test_commute_patches() {
+		let first_patch = parse_patch(COMMUTABLE_FIRST_PATCH_DATA).unwrap();
+		let second_patch = parse_patch(COMMUTABLE_SECOND_PATCH_DATA).unwrap();
+		let (new_second_patch, new_first_patch) = first_patch.commute(&second_patch).unwrap();
+
+		assert_eq!(new_second_patch.hunks, parse_patch(COMMUTED_SECOND_PATCH_DATA).unwrap().hunks);
+		assert_eq!(new_first_patch.hunks, parse_patch(COMMUTED_FIRST_PATCH_DATA).unwrap().hunks);
+
+		let (second_patch_again, first_patch_again) = new_second_patch.commute(&new_first_patch).unwrap();
+		assert_eq!(second_patch_again.hunks, first_patch.hunks);
+		assert_eq!(first_patch_again.hunks, second_patch.hunks);
+	}
+
+	#[test]
+	fn test_commute_overlapping_patches() {
+		let first_patch = parse_patch(FIRST_PATCH_DATA).unwrap();
+		let second_patch = parse_patch(SECOND_PATCH_DATA).unwrap();
+		assert!(first_patch.commute(&second_patch).is_err());
+	}
+}
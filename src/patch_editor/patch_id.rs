@@ -0,0 +1,212 @@
+use std::fmt::{self, Display, Formatter};
+
+// The same as "git patch-id --stable" computes: whitespace and line numbers don't matter, neither does the order of the files
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct PatchId(pub [u8; 20]);
+
+impl Display for PatchId {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		for byte in &self.0 {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
+
+// Follows the git implementation line by line, including its handling of the unusual input. No ID if there are no changes.
+pub fn patch_id(diff: &[u8]) -> Option<PatchId> {
+	let mut result = [0u8; 20];
+	let mut sha1 = Sha1::new();
+	let mut patch_length = 0;
+	let (mut before, mut after) = (-1, -1); // The lines left in the hunk, -1 while in the file header
+
+	for line in diff.split(|byte| *byte == b'\n') {
+		if patch_length == 0 && !line.starts_with(b"diff ") { continue; }
+		if line.starts_with(b"\\") { continue; } // The absent newline markers
+
+		if before == -1 {
+			if line.starts_with(b"index ") {
+				continue;
+			} else if line.starts_with(b"--- ") {
+				before = 1;
+				after = 1;
+			} else if !line.first().map_or(false, |byte| byte.is_ascii_alphabetic()) {
+				break;
+			}
+		}
+
+		if before == 0 && after == 0 {
+			if line.starts_with(b"@@ -") {
+				let (old_count, new_count) = hunk_line_counts(line);
+				before = old_count;
+				after = new_count;
+				continue;
+			}
+
+			if !line.starts_with(b"diff ") { break; }
+
+			add_hash(&mut result, &sha1.digest());
+			sha1 = Sha1::new();
+			before = -1;
+			after = -1;
+		}
+
+		match line.first() {
+			Some(&b'-') => before -= 1,
+			Some(&b'+') => after -= 1,
+			Some(&b' ') => {
+				before -= 1;
+				after -= 1;
+			}
+			_ => ()
+		}
+
+		// Only the whitespace of git's own isspace, without the form feed and the vertical tab
+		let stripped_line = line.iter().cloned().filter(|byte| ![b' ', b'\t', b'\n', b'\r'].contains(byte)).collect::<Vec<_>>();
+		patch_length += stripped_line.len();
+		sha1.update(&stripped_line);
+	}
+
+	if patch_length == 0 { return None; }
+	add_hash(&mut result, &sha1.digest());
+	Some(PatchId(result))
+}
+
+fn hunk_line_counts(header: &[u8]) -> (isize, isize) {
+	let header = String::from_utf8_lossy(header);
+	let mut ranges = header.split(' ').skip(1).take(2).map(|range| {
+		range.splitn(2, ',').nth(1).map_or(Some(1), |count| count.parse().ok()).unwrap_or(0)
+	});
+	(ranges.next().unwrap_or(0), ranges.next().unwrap_or(0))
+}
+
+// The hashes of the files are summed up as 20-byte numbers, so that their order doesn't matter
+fn add_hash(result: &mut [u8; 20], hash: &[u8; 20]) {
+	let mut carry = 0u16;
+	for (result_byte, hash_byte) in result.iter_mut().zip(hash.iter()) {
+		carry += *result_byte as u16 + *hash_byte as u16;
+		*result_byte = carry as u8;
+		carry >>= 8;
+	}
+}
+
+struct Sha1 {
+	state: [u32; 5],
+	buffer: Vec<u8>,
+	length: u64,
+}
+
+impl Sha1 {
+	fn new() -> Sha1 {
+		Sha1 {
+			state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+			buffer: Vec::with_capacity(64),
+			length: 0,
+		}
+	}
+
+	fn update(&mut self, data: &[u8]) {
+		self.length += data.len() as u64;
+		self.buffer.extend_from_slice(data);
+		let block_count = self.buffer.len() / 64;
+		for block_index in 0..block_count {
+			let mut block = [0u8; 64];
+			block.copy_from_slice(&self.buffer[block_index * 64..(block_index + 1) * 64]);
+			self.process_block(&block);
+		}
+		self.buffer.drain(..block_count * 64);
+	}
+
+	fn digest(mut self) -> [u8; 20] {
+		let bit_length = self.length * 8;
+		let mut padding = vec![0x80u8];
+		padding.resize((119 - self.buffer.len()) % 64 + 1, 0);
+		padding.extend((0..8).rev().map(|byte_index| (bit_length >> (byte_index * 8)) as u8));
+		self.update(&padding);
+
+		let mut digest = [0u8; 20];
+		for (index, word) in self.state.iter().enumerate() {
+			for byte_index in 0..4 {
+				digest[index * 4 + byte_index] = (word >> (24 - byte_index * 8)) as u8;
+			}
+		}
+		digest
+	}
+
+	fn process_block(&mut self, block: &[u8; 64]) {
+		let mut words = [0u32; 80];
+		for index in 0..16 {
+			words[index] = (0..4).fold(0, |word, byte_index| word << 8 | block[index * 4 + byte_index] as u32);
+		}
+		for index in 16..80 {
+			words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+		}
+
+		let (mut a, mut b, mut c, mut d, mut e) = (self.state[0], self.state[1], self.state[2], self.state[3], self.state[4]);
+		for (index, word) in words.iter().enumerate() {
+			let (f, k) = match index {
+				0..=19 => ((b & c) | (!b & d), 0x5A827999),
+				20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+				40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+				_ => (b ^ c ^ d, 0xCA62C1D6)
+			};
+			let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = temp;
+		}
+
+		for (state_word, word) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+			*state_word = state_word.wrapping_add(*word);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use super::super::parser::parse_patch;
+
+	fn hex_patch_id(diff: &[u8]) -> String {
+		patch_id(diff).unwrap().to_string()
+	}
+
+	#[test]
+	fn test_sha1() {
+		let digest = |data: &[u8]| {
+			let mut sha1 = Sha1::new();
+			sha1.update(data);
+			PatchId(sha1.digest()).to_string()
+		};
+		assert_eq!(digest(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+		assert_eq!(digest(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+		assert_eq!(digest(&[b'a'; 1000]), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+	}
+
+	#[test]
+	fn test_patch_id() {
+		let diff = b"diff --git a/f.txt b/f.txt\nindex de98044..7be73ce 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+		assert_eq!(hex_patch_id(diff), "5a41e5301314e0a273b1c98efe37ed79c8c03a94");
+
+		let moved_diff = b"diff --git a/f.txt b/f.txt\nindex 1111111..2222222 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -10,3 +10,3 @@\n a\n-b\n+  B\n c\n";
+		assert_eq!(hex_patch_id(moved_diff), "5a41e5301314e0a273b1c98efe37ed79c8c03a94");
+		assert_eq!(parse_patch(moved_diff).unwrap().patch_id(), patch_id(diff));
+
+		let form_feed_diff = b"diff --git a/f.txt b/f.txt\nindex de98044..7be73ce 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+\x0CB\n c\n";
+		assert_ne!(hex_patch_id(form_feed_diff), "5a41e5301314e0a273b1c98efe37ed79c8c03a94");
+
+		let diff = b"diff --git a/f.txt b/f.txt\nindex 7898192..7be73ce 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+			diff --git a/m.sh b/m.sh\nold mode 100644\nnew mode 100755\n\
+			diff --git a/n.txt b/n.txt\nnew file mode 100644\nindex 0000000..8ba3a16\n--- /dev/null\n+++ b/n.txt\n@@ -0,0 +1 @@\n+n\n";
+		assert_eq!(hex_patch_id(diff), "ac3468802a025647316b20fb7c850bbc5e9a3d30");
+
+		let no_newline_diff = b"diff --git a/f.txt b/f.txt\nindex de98044..7be73ce 100644\n--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,2 @@\n a\n-b\n\
+			\\ No newline at end of file\n+B\n\\ No newline at end of file\n\
+			diff --git a/g.txt b/g.txt\nindex de98044..7be73ce 100644\n--- a/g.txt\n+++ b/g.txt\n@@ -1 +1 @@\n-x\n+y\n";
+		assert_eq!(hex_patch_id(no_newline_diff), "6d0ec58308249f026b54026961ce6de82be966ae");
+
+		assert_eq!(patch_id(b""), None);
+	}
+}
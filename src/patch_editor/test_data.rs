@@ -1,4 +1,4 @@
-use super::patch::{Change, FileProperties, Hunk, ModificationType, Patch};
+use super::patch::{Change, FileProperties, Hunk, HunkLine, HunkLineKind, ModificationType, Patch};
 
 pub const PATCH_DATA_HEADER: &[u8] = b"diff --git a/gradle.properties b/gradle.properties\n";
 pub const PATCH_DATA_EXTENDED_HEADER: &[u8] = b"index aac7c9b..f33a6d7 100644\n";
@@ -137,20 +137,35 @@ lazy_static! {
 	pub static ref REJECTED_HUNKS: Vec<Hunk> = vec![generate_hunk_1(), generate_hunk_2()];
 }
 
-pub fn generate_hunk_1<'a>() -> Hunk {
-	Hunk {
-		old_file_range: 1..10,
-		new_file_range: 1..4,
-		data: PATCH_DATA_HUNK_1_CONTENTS.into(),
+pub fn lines_from_data(data: &[u8]) -> Vec<HunkLine> {
+	let mut lines: Vec<HunkLine> = Vec::new();
+	for line in data[..data.len() - 1].split(|&byte| byte == b'\n') {
+		let (kind, content, prefix_omitted) = match line.first() {
+			Some(&b'\\') => {
+				let last_line = lines.last_mut().unwrap();
+				last_line.content.pop();
+				last_line.no_newline_at_eof = true;
+				continue;
+			}
+			Some(&b' ') => (HunkLineKind::Context, &line[1..], false),
+			Some(&b'+') => (HunkLineKind::Added, &line[1..], false),
+			Some(&b'-') => (HunkLineKind::Removed, &line[1..], false),
+			_ => (HunkLineKind::Context, line, true)
+		};
+
+		let mut hunk_line = HunkLine::new(kind, vec_from_slices![content, b"\n"]);
+		hunk_line.prefix_omitted = prefix_omitted;
+		lines.push(hunk_line);
 	}
+	lines
+}
+
+pub fn generate_hunk_1<'a>() -> Hunk {
+	Hunk::new(1..10, 1..4, lines_from_data(PATCH_DATA_HUNK_1_CONTENTS))
 }
 
 pub fn generate_hunk_2<'a>() -> Hunk {
-	Hunk {
-		old_file_range: 14..18,
-		new_file_range: 8..12,
-		data: PATCH_DATA_HUNK_2_CONTENTS.into(),
-	}
+	Hunk::new(14..18, 8..12, lines_from_data(PATCH_DATA_HUNK_2_CONTENTS))
 }
 
 fn generate_patch_data(no_extended_header: bool) -> Vec<u8> {
@@ -162,9 +177,5 @@ fn generate_patch_data(no_extended_header: bool) -> Vec<u8> {
 }
 
 fn generate_hunk_no_new_lines<'a>() -> Hunk {
-	Hunk {
-		old_file_range: 1..2,
-		new_file_range: 1..2,
-		data: PATCH_DATA_NO_NEW_LINES_HUNK_CONTENTS.into()
-	}
+	Hunk::new(1..2, 1..2, lines_from_data(PATCH_DATA_NO_NEW_LINES_HUNK_CONTENTS))
 }
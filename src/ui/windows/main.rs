@@ -30,10 +30,13 @@ use winapi::um::commctrl::{self, WC_TREEVIEW, WC_STATIC, TVS_HASLINES, TVM_INSER
 use winapi::shared::windowsx::{GET_X_LPARAM, GET_Y_LPARAM};
 
 use super::helpers::*;
-use begitter::model::main::{BranchItem, MainModel, MainViewReceiver};
+use begitter::model::main::{BranchItem, Duplicate, MainModel, MainViewReceiver};
 use begitter::model::View;
 use begitter::change_set::Commit;
 use begitter::change_set::CombinedPatch;
+use begitter::change_set::dependencies::DependencyGraph;
+use begitter::patch_editor::combined_diff::CombinedDiff;
+use begitter::git::GitDiffOptions;
 use ui::windows::text::{load_string, STRING_MAIN_PATCHES_COLUMNS, STRING_MAIN_WINDOW_NAME, STRING_MAIN_BRANCHES, STRING_MAIN_PATCHES,
 	STRING_MAIN_COMMITS, STRING_MAIN_COMMITS_COLUMNS, format_time, STRING_MAIN_ABORT, STRING_MAIN_RESOLVE_REJECTS,
 	STRING_MAIN_RESOLVE_CONFLICTS, STRING_MAIN_EDIT};
@@ -277,6 +280,22 @@ impl MainViewReceiver for MainViewRelay {
 		self.post_on_main_thread(MainViewMessage::CombinedPatches(combined_patches)).map_err(|err| err.into())
 	}
 
+	fn show_conflicting_moves(&self, source_position: usize, conflicting_moves: Vec<bool>) -> Result<(), failure::Error> {
+		self.post_on_main_thread(MainViewMessage::ConflictingMoves(source_position, conflicting_moves)).map_err(|err| err.into())
+	}
+
+	fn show_dependency_graph(&self, dependency_graph: DependencyGraph) -> Result<(), failure::Error> {
+		self.post_on_main_thread(MainViewMessage::DependencyGraph(dependency_graph)).map_err(|err| err.into())
+	}
+
+	fn show_merge_diff(&self, _commit_hash: String, _combined_diffs: Vec<CombinedDiff>) -> Result<(), failure::Error> {
+		Ok(()) // The main window doesn't show the merges yet
+	}
+
+	fn show_duplicates(&self, _duplicates: Vec<Option<Duplicate>>) -> Result<(), failure::Error> {
+		Ok(()) // The main window doesn't request the duplicates yet
+	}
+
 	fn resolve_rejects(&self) -> Result<(), failure::Error> {
 		self.post_on_main_thread(MainViewMessage::ResolveRejects).map_err(|err| err.into())
 	}
@@ -290,6 +309,8 @@ enum MainViewMessage {
 	Branches(Vec<BranchItem>),
 	Commits(Vec<Commit>),
 	CombinedPatches(Vec<CombinedPatch>),
+	ConflictingMoves(usize, Vec<bool>),
+	DependencyGraph(DependencyGraph),
 	ResolveRejects,
 	NotifyConflicts
 }
@@ -318,6 +339,7 @@ struct MainView {
 	commit_strings: Vec<Vec<WideString>>,
 	combined_patches: Vec<CombinedPatch>,
 	combined_patch_strings: Vec<Vec<WideString>>,
+	dependency_graph: Option<DependencyGraph>, // Requested after every change of the patches
 	continue_button_state: ContinueButtonState,
 
 	combined_patches_list_view_drag_tracker: ListViewDragTracker
@@ -402,6 +424,7 @@ impl MainView {
 			commits: Vec::new(),
 			commit_strings: Vec::new(),
 			combined_patch_strings: Vec::new(),
+			dependency_graph: None,
 			continue_button_state: ContinueButtonState::Unavailable,
 			combined_patches_list_view_drag_tracker: ListViewDragTracker::new(combined_patches_list_view)
 		};
@@ -445,13 +468,20 @@ impl MainView {
 			}
 			MainViewMessage::CombinedPatches(combined_patches) => {
 				self.combined_patches = combined_patches;
-				MainView::update_list_view(self.combined_patches_list_view, &self.combined_patches, &mut self.combined_patch_strings,
-						|patch| {
-							let info = &patch.info;
-							vec![info.message.as_str().into(),
-								info.author_action.name.as_str().into(),
-								format_time(info.author_action.time).into()]
-						})?;
+				self.dependency_graph = None;
+				self.update_combined_patches_list_view()?;
+				self.model.as_ref().unwrap().request_dependency_graph();
+			}
+			MainViewMessage::DependencyGraph(dependency_graph) => {
+				// The patches could change again while the graph was being computed
+				if dependency_graph.dependencies.len() == self.combined_patches.len() {
+					self.dependency_graph = Some(dependency_graph);
+					self.update_combined_patches_list_view()?;
+				}
+			}
+			MainViewMessage::ConflictingMoves(source_position, conflicting_moves) => {
+				self.combined_patches_list_view_drag_tracker.conflicting_moves = Some((source_position, conflicting_moves));
+				self.combined_patches_list_view_drag_tracker.invalidate()?;
 			}
 			MainViewMessage::ResolveRejects => self.resolve_rejects()?,
 			MainViewMessage::NotifyConflicts => self.set_continue_button_state(ContinueButtonState::ResolveConflicts)?
@@ -460,6 +490,29 @@ impl MainView {
 		Ok(())
 	}
 
+	fn update_combined_patches_list_view(&mut self) -> Result<(), WinApiError> {
+		// The dependencies are shown as the numbers of the rows, counting from 1
+		let dependency_graph = &self.dependency_graph;
+		let items = self.combined_patches.iter()
+				.enumerate()
+				.map(|(index, patch)| {
+					let dependencies = dependency_graph.as_ref().map_or(String::new(), |graph| {
+						graph.dependencies[index].iter().map(|dependency| (dependency + 1).to_string()).collect::<Vec<_>>().join(", ")
+					});
+					(patch, dependencies)
+				})
+				.collect::<Vec<_>>();
+
+		MainView::update_list_view(self.combined_patches_list_view, &items, &mut self.combined_patch_strings,
+				|&(patch, ref dependencies)| {
+					let info = &patch.info;
+					vec![info.message.as_str().into(),
+						info.author_action.name.as_str().into(),
+						format_time(info.author_action.time).into(),
+						dependencies.as_str().into()]
+				})
+	}
+
 	fn update_list_view<I>(list_view: HWND, item_slice: &[I], item_strings: &mut Vec<Vec<WideString>>,
 			string_generator: fn(&I) -> Vec<Cow<str>>) -> Result<(), WinApiError> {
 		try_send_message!(list_view, LVM_DELETEALLITEMS, 0, 0);
@@ -681,7 +734,7 @@ impl MainView {
 						.iter()
 						.map(|commit| commit.clone())
 						.collect();
-				self.model.as_ref().unwrap().import_commits(commits);
+				self.model.as_ref().unwrap().import_commits(commits, GitDiffOptions::default());
 				true
 			}
 			self::ID_MENU_APPLY => {
@@ -765,7 +818,8 @@ impl MainView {
 struct ListViewDragTracker {
 	model: Option<MainModel>,
 	combined_patches_list_view: HWND,
-	item_positions: Option<(usize, usize)>
+	item_positions: Option<(usize, usize)>,
+	conflicting_moves: Option<(usize, Vec<bool>)> // Requested when dragging starts
 }
 
 impl ListViewDragTracker {
@@ -773,7 +827,8 @@ impl ListViewDragTracker {
 		ListViewDragTracker {
 			model: None,
 			combined_patches_list_view,
-			item_positions: None
+			item_positions: None,
+			conflicting_moves: None
 		}
 	}
 
@@ -800,6 +855,8 @@ impl ListViewDragTracker {
 
 							let item =  unsafe { *(message_data.l_param as *const NMLISTVIEW) };
 							self.item_positions = Some((item.iItem as usize, item.iItem as usize));
+							self.conflicting_moves = None;
+							self.model.as_ref().unwrap().find_conflicting_moves(item.iItem as usize);
 							self.invalidate()?;
 							true
 						}
@@ -851,7 +908,7 @@ impl ListViewDragTracker {
 					let item_count = try_send_message!(self.combined_patches_list_view, LVM_GETITEMCOUNT, 0, 0);
 					if item_count > 0 {
 						let insertion_position = self.calculate_insertion_position(message_data, item_count as usize)?;
-						self.model.as_ref().unwrap().move_patch(source_position, insertion_position);
+						self.model.as_ref().unwrap().move_patch(source_position, insertion_position, false);
 
 						self.item_positions = None;
 						self.invalidate()?;
@@ -880,8 +937,8 @@ impl ListViewDragTracker {
 		}
 		let item_count = item_count as usize;
 
-		let insertion_position = match self.item_positions {
-			Some((_, insertion_position)) => insertion_position,
+		let (source_position, insertion_position) = match self.item_positions {
+			Some(item_positions) => item_positions,
 			None => return Ok(())
 		};
 
@@ -900,7 +957,14 @@ impl ListViewDragTracker {
 		};
 
 		let data_holder = PaintingDataHolder::new(self.combined_patches_list_view)?;
-		let brush = Brush::new_solid(0xFF, 0, 0)?;
+		let conflicting = self.conflicting_moves
+				.as_ref()
+				.and_then(|&(conflicts_source_position, ref conflicting_moves)| {
+					if conflicts_source_position == source_position { conflicting_moves.get(insertion_position) } else { None }
+				})
+				.cloned()
+				.unwrap_or(false);
+		let brush = if conflicting { Brush::new_solid(0xFF, 0, 0)? } else { Brush::new_solid(0, 0, 0)? };
 		try_call!(FillRect(data_holder.context(), &insertion_mark_rect, brush.brush()), 0);
 
 		Ok(())
@@ -21,8 +21,8 @@ use winapi::um::wingdi::GetTextExtentPoint32W;
 
 use ui::windows::utils::{close_dialog, get_window_client_area, set_fonts};
 use ui::windows::helpers::{to_wstring, WinApiError, MessageData};
-use ui::windows::text::binary_to_text;
-use begitter::model::patches::{PatchesModel, PatchesViewReceiver, TargetSide};
+use ui::windows::text::hunk_to_text;
+use begitter::model::patches::{PatchesModel, PatchesViewReceiver, TargetSide, Placement};
 use begitter::model::View;
 use begitter::change_set::CombinedPatch;
 use begitter::patch_editor::patch::Change;
@@ -228,7 +228,9 @@ impl PatchesView {
 							true
 						}
 						ID_NEW_PATCH_BUTTON if notification == winuser::BN_CLICKED  => {
-							self.patches_model.new_patch()?;
+							if let Some(id) = self.patches_model.selected_combined_patch(TargetSide::Left) {
+								self.patches_model.new_patch(Placement::After(id))?;
+							}
 							true
 						}
 						ID_LEFT_ALL_HUNKS_BUTTON  if notification == winuser::BN_CLICKED => {
@@ -522,14 +524,14 @@ impl PatchesView {
 						4, top, 16, 16, hunks_window, 0 as HMENU, 0 as HINSTANCE, null_mut()));
 					hunks_checkboxes.push(checkbox);
 
-					let hunk_text = match binary_to_text(&hunk.data) {
+					let hunk_text = match hunk_to_text(hunk) {
 						Ok(text) => text,
 						Err(err) => {
 							println!("Couldn't read the file text: {:?}", err); // TODO: this isn't proper handling
 							return Ok(());
 						}
 					};
-					let text = format!("{}\r\n{}", hunk.header(), hunk_text);
+					let text = format!("{}\r\n{}", hunk.header().trim_right(), hunk_text);
 
 					let context = try_call!(GetDC(hunks_window), null_mut());
 					let line_sizes = text.split("\r\n").map(|line| {
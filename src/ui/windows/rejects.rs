@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 use std::ptr::null_mut;
 use std::sync::Arc;
+use std::string::FromUtf8Error;
 
 use failure;
 
@@ -17,7 +18,7 @@ use begitter::model::View;
 use begitter::patch_editor::patch::Hunk;
 use ui::windows::helpers::from_wstring;
 use ui::windows::text::{load_string, STRING_REJECTS_UNACCEPT_HUNK, STRING_REJECTS_ACCEPT_HUNK};
-use ui::windows::text::binary_to_text;
+use ui::windows::text::{binary_to_text, hunk_to_text};
 
 const ID_REJECTS_FILES_LISTBOX: c_int = 3;
 const ID_REJECTS_HUNKS_LISTBOX: c_int = 4;
@@ -149,8 +150,8 @@ impl RejectsView {
 			*Box::from_raw(message_data.l_param as *mut _)
 		};
 
-		fn set_edit_text_contents(handle: HWND, data: &Vec<u8>) -> Result<(), WinApiError> {
-			let raw_text = match binary_to_text(data) {
+		fn set_edit_text_contents(handle: HWND, text_result: Result<String, FromUtf8Error>) -> Result<(), WinApiError> {
+			let raw_text = match text_result {
 				Ok(text) => text,
 				Err(err) => {
 					println!("Couldn't read the file text: {:?}", err); // TODO: this isn't proper handling
@@ -192,11 +193,11 @@ impl RejectsView {
 			}
 			RejectsViewMessage::ShowFileData(data, file_pos) => {
 				try_send_message!(self.files_list_box, LB_SETCURSEL, file_pos, 0);
-				set_edit_text_contents(self.file_edit_text, &*data)?;
+				set_edit_text_contents(self.file_edit_text, binary_to_text(&*data))?;
 			}
 			RejectsViewMessage::ShowActiveHunk(hunk, hunk_pos) => {
 				try_send_message!(self.hunks_list_box, LB_SETCURSEL, hunk_pos, 0);
-				set_edit_text_contents(self.hunk_edit_text, &hunk.data)?;
+				set_edit_text_contents(self.hunk_edit_text, hunk_to_text(&hunk))?;
 
 				let string_id = if self.hunks[hunk_pos].1 { STRING_REJECTS_UNACCEPT_HUNK } else { STRING_REJECTS_ACCEPT_HUNK };
 				let accept_button_text = load_string(string_id)?;
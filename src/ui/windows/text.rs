@@ -7,6 +7,7 @@ use time;
 use winapi::shared::minwindef::UINT;
 use winapi::um::libloaderapi::LoadStringW;
 
+use begitter::patch_editor::patch::Hunk;
 use ui::windows::helpers::WinApiError;
 
 pub const STRING_MAIN_WINDOW_NAME: UINT = 1;
@@ -23,11 +24,11 @@ pub const STRING_MAIN_COMMITS_COLUMN_HASH: UINT = 12;
 pub const STRING_MAIN_COMMITS_COLUMNS: Range<UINT> = STRING_MAIN_COMMITS_COLUMN_MESSAGE..STRING_MAIN_COMMITS_COLUMN_HASH + 1;
 
 pub const STRING_MAIN_PATCHES_COLUMN_MESSAGE: UINT = 13;
-pub const STRING_MAIN_PATCHES_COLUMN_DATE: UINT = 15;
-pub const STRING_MAIN_PATCHES_COLUMNS: Range<UINT> = STRING_MAIN_PATCHES_COLUMN_MESSAGE..STRING_MAIN_PATCHES_COLUMN_DATE + 1;
+pub const STRING_MAIN_PATCHES_COLUMN_DEPENDENCIES: UINT = 16;
+pub const STRING_MAIN_PATCHES_COLUMNS: Range<UINT> = STRING_MAIN_PATCHES_COLUMN_MESSAGE..STRING_MAIN_PATCHES_COLUMN_DEPENDENCIES + 1;
 
-pub const STRING_REJECTS_ACCEPT_HUNK: UINT = 16;
-pub const STRING_REJECTS_UNACCEPT_HUNK: UINT = 17;
+pub const STRING_REJECTS_ACCEPT_HUNK: UINT = 17;
+pub const STRING_REJECTS_UNACCEPT_HUNK: UINT = 18;
 
 pub fn load_string(id: UINT) -> Result<Vec<u16>, WinApiError> {
 	let mut string_pointer = null_mut::<u16>();
@@ -53,4 +54,14 @@ pub fn binary_to_text(data: &Vec<u8>) -> Result<String, FromUtf8Error> {
 	}
 
 	Ok(raw_text)
-}
\ No newline at end of file
+}
+
+pub fn hunk_to_text(hunk: &Hunk) -> Result<String, FromUtf8Error> {
+	let mut lines = Vec::new();
+	for line in &hunk.lines {
+		let content = String::from_utf8(line.content.clone())?;
+		lines.push(format!("{}{}", line.kind.prefix() as char, content.trim_right_matches(&['\r', '\n'][..])));
+	}
+
+	Ok(lines.join("\r\n"))
+}